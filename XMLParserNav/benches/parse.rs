@@ -0,0 +1,56 @@
+//! Compares the xml-rs backend with the borrowed backend on a generated catalog.
+//!
+//! Run with `cargo bench`.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use XMLParserNav::BorrowedDocument;
+use XMLParserNav::ElementTree;
+
+fn catalog(books: usize) -> String {
+    let mut doc = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<catalog>\n");
+    for i in 0..books {
+        doc.push_str(&format!(
+            "  <book id=\"bk{}\" lang=\"en\">\n    <author>Author {}</author>\n    <title>Title &amp; subtitle {}</title>\n    <price>{}.95</price>\n    <description>{}</description>\n  </book>\n",
+            i,
+            i,
+            i,
+            i % 50,
+            "A long description of the book. ".repeat(8)
+        ));
+    }
+    doc.push_str("</catalog>\n");
+    doc
+}
+
+fn time<F: FnMut()>(name: &str, bytes: usize, mut f: F) -> Duration {
+    // warm up, then take the best of a few runs
+    f();
+    let best = (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap();
+    let mb_per_s = bytes as f64 / best.as_secs_f64() / 1_000_000.0;
+    println!("{:<28} {:>10.2?} {:>8.1} MB/s", name, best, mb_per_s);
+    best
+}
+
+fn main() {
+    for books in [100, 10_000] {
+        let doc = catalog(books);
+        println!("{} books, {} bytes", books, doc.len());
+        let owned = time("xml-rs ElementTree::parse", doc.len(), || {
+            black_box(ElementTree::parse_str(black_box(&doc)).unwrap());
+        });
+        let borrowed = time("BorrowedDocument::parse", doc.len(), || {
+            black_box(BorrowedDocument::parse(black_box(&doc)).unwrap());
+        });
+        time("BorrowedDocument + to_tree", doc.len(), || {
+            black_box(BorrowedDocument::parse(black_box(&doc)).unwrap().to_tree());
+        });
+        println!("borrowed is {:.1}x faster\n", owned.as_secs_f64() / borrowed.as_secs_f64());
+    }
+}
@@ -0,0 +1,81 @@
+//! `#[derive(FromXml)]` for XMLParserNav, re-exported by it with the `derive` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, ToTokens};
+use syn::{parse_macro_input, Data, DeriveInput, Field, Fields, LitStr};
+
+/// Derives `XMLParserNav::FromXml` for a struct with named fields.
+///
+/// Each field is read from what the XPath in its `#[xpath("...")]` attribute selects under
+/// the element, or from the child elements named after the field if it has none. A path may
+/// end with an attribute step (`@id`, `price/@currency`).
+#[proc_macro_derive(FromXml, attributes(xpath))]
+pub fn derive_from_xml(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => return Err(syn::Error::new_spanned(&input.ident, "FromXml needs a struct with named fields")),
+        },
+        _ => return Err(syn::Error::new_spanned(&input.ident, "FromXml can only be derived for structs")),
+    };
+
+    let mut inits = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named fields have names");
+        let ty = &field.ty;
+        let path = match xpath(field)? {
+            Some(path) => path,
+            None => LitStr::new(&ident.to_string(), ident.span()),
+        };
+        // the type as written, for error messages
+        let ty_name = ty.to_token_stream().to_string().replace(' ', "");
+        inits.push(quote! {
+            #ident: ::XMLParserNav::from_xml::field::<#ty>(element, #path, #ty_name)?
+        });
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::XMLParserNav::from_xml::FromXml for #name #ty_generics #where_clause {
+            fn from_xml(
+                element: &::XMLParserNav::Element,
+            ) -> ::std::result::Result<Self, ::XMLParserNav::XmlErrors> {
+                ::std::result::Result::Ok(#name {
+                    #(#inits,)*
+                })
+            }
+        }
+
+        impl #impl_generics ::XMLParserNav::from_xml::FromMatches for #name #ty_generics #where_clause {
+            fn from_matches(
+                path: &str,
+                matches: ::std::vec::Vec<::XMLParserNav::from_xml::Match<'_>>,
+            ) -> ::std::result::Result<Self, ::XMLParserNav::XmlErrors> {
+                ::XMLParserNav::from_xml::nested(path, matches)
+            }
+        }
+    })
+}
+
+///The path of the field's `#[xpath("...")]` attribute, if it has one
+fn xpath(field: &Field) -> syn::Result<Option<LitStr>> {
+    let mut path: Option<LitStr> = None;
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("xpath")) {
+        if path.is_some() {
+            return Err(syn::Error::new_spanned(attr, "a field can only have one #[xpath]"));
+        }
+        let lit: LitStr = attr.parse_args()?;
+        if lit.value().trim().is_empty() {
+            return Err(syn::Error::new_spanned(lit, "the XPath is empty"));
+        }
+        path = Some(lit);
+    }
+    Ok(path)
+}
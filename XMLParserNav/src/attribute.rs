@@ -0,0 +1,215 @@
+use std::slice::{Iter, IterMut};
+use xml::attribute::OwnedAttribute;
+use xml::namespace::{NS_XMLNS_PREFIX, NS_XMLNS_URI, NS_XML_PREFIX, NS_XML_URI};
+use crate::position::Span;
+
+/// An attribute of an XML element
+#[derive(Debug, Clone, Eq)]
+pub struct Attribute {
+    /// The prefix of the attribute name
+    pub prefix: Option<String>,
+    /// The local name of the attribute
+    pub name: String,
+    /// The namespace URI the prefix is bound to
+    pub namespace: Option<String>,
+    /// The value of the attribute
+    pub value: String,
+    /// Where the attribute was found in the source, if it was parsed
+    pub span: Option<Span>,
+}
+
+impl PartialEq for Attribute {
+    // source positions do not take part in comparisons
+    fn eq(&self, other: &Self) -> bool {
+        self.prefix == other.prefix
+            && self.name == other.name
+            && self.namespace == other.namespace
+            && self.value == other.value
+    }
+}
+
+impl Attribute {
+    ///Creates an attribute from its qualified name (`prefix:local`) and value.
+    ///Only the reserved `xml` and `xmlns` prefixes, and `xmlns` itself, get a namespace URI here.
+    pub fn new<K, V>(qname: K, value: V) -> Attribute
+    where
+        K: ToString,
+        V: ToString,
+    {
+        let qname = qname.to_string();
+        let (prefix, name) = match qname.split_once(':') {
+            Some((prefix, local)) => (Some(prefix.to_string()), local.to_string()),
+            None => (None, qname),
+        };
+        let namespace = match prefix.as_deref() {
+            Some(NS_XML_PREFIX) => Some(NS_XML_URI.to_string()),
+            Some(NS_XMLNS_PREFIX) => Some(NS_XMLNS_URI.to_string()),
+            None if name == NS_XMLNS_PREFIX => Some(NS_XMLNS_URI.to_string()),
+            _ => None,
+        };
+        Attribute {
+            prefix,
+            name,
+            namespace,
+            value: value.to_string(),
+            span: None,
+        }
+    }
+
+    ///Creates an attribute in the namespace `uri`
+    pub fn new_ns<U, K, V>(uri: U, qname: K, value: V) -> Attribute
+    where
+        U: ToString,
+        K: ToString,
+        V: ToString,
+    {
+        Attribute {
+            namespace: Some(uri.to_string()),
+            ..Attribute::new(qname, value)
+        }
+    }
+
+    ///Returns the qualified name, `prefix:local` or just `local`
+    pub fn qname(&self) -> String {
+        match self.prefix {
+            Some(ref prefix) => format!("{}:{}", prefix, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    ///Returns true if the qualified name of the attribute is `qname`
+    pub fn has_qname(&self, qname: &str) -> bool {
+        match (qname.split_once(':'), &self.prefix) {
+            (Some((prefix, local)), Some(own)) => prefix == own && local == self.name,
+            (None, None) => qname == self.name,
+            _ => false,
+        }
+    }
+}
+
+impl From<OwnedAttribute> for Attribute {
+    fn from(attr: OwnedAttribute) -> Self {
+        Attribute {
+            prefix: attr.name.prefix,
+            name: attr.name.local_name,
+            namespace: attr.name.namespace,
+            value: attr.value,
+            span: None,
+        }
+    }
+}
+
+/// The attributes of an element, kept in document order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attributes {
+    list: Vec<Attribute>,
+}
+
+impl Attributes {
+    ///Creates an empty attribute list
+    pub fn new() -> Attributes {
+        Attributes { list: Vec::new() }
+    }
+
+    ///Looks up the value of an attribute by its qualified name (`prefix:local`)
+    pub fn get(&self, qname: &str) -> Option<&str> {
+        self.get_attribute(qname).map(|attr| attr.value.as_str())
+    }
+
+    ///Looks up the value of an attribute by namespace URI and local name
+    pub fn get_ns(&self, uri: &str, local: &str) -> Option<&str> {
+        self.list
+            .iter()
+            .find(|attr| attr.name == local && attr.namespace.as_deref() == Some(uri))
+            .map(|attr| attr.value.as_str())
+    }
+
+    ///Looks up a whole attribute by its qualified name
+    pub fn get_attribute(&self, qname: &str) -> Option<&Attribute> {
+        self.list.iter().find(|attr| attr.has_qname(qname))
+    }
+
+    ///Looks up a whole attribute by its qualified name, for modification
+    pub fn get_attribute_mut(&mut self, qname: &str) -> Option<&mut Attribute> {
+        self.list.iter_mut().find(|attr| attr.has_qname(qname))
+    }
+
+    ///Returns true if an attribute with the qualified name `qname` exists
+    pub fn contains(&self, qname: &str) -> bool {
+        self.get_attribute(qname).is_some()
+    }
+
+    ///Sets the attribute `qname`, keeping its position if it already exists.
+    ///Returns the previous value.
+    pub fn insert<K, V>(&mut self, qname: K, value: V) -> Option<String>
+    where
+        K: ToString,
+        V: ToString,
+    {
+        self.push(Attribute::new(qname, value))
+    }
+
+    ///Adds `attr`, replacing an attribute with the same qualified name in place.
+    ///Returns the previous value.
+    pub fn push(&mut self, attr: Attribute) -> Option<String> {
+        let qname = attr.qname();
+        match self.list.iter_mut().find(|old| old.has_qname(&qname)) {
+            Some(old) => Some(std::mem::replace(old, attr).value),
+            None => {
+                self.list.push(attr);
+                None
+            }
+        }
+    }
+
+    ///Removes the attribute `qname`, returning it
+    pub fn remove(&mut self, qname: &str) -> Option<Attribute> {
+        let index = self.list.iter().position(|attr| attr.has_qname(qname))?;
+        Some(self.list.remove(index))
+    }
+
+    ///Iterates over the attributes in document order
+    pub fn iter(&self) -> Iter<Attribute> {
+        self.list.iter()
+    }
+
+    ///Iterates over the attributes in document order, for modification
+    pub fn iter_mut(&mut self) -> IterMut<Attribute> {
+        self.list.iter_mut()
+    }
+
+    ///Number of attributes
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    ///Returns true if there are no attributes
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+}
+
+impl<'a> IntoIterator for &'a Attributes {
+    type Item = &'a Attribute;
+    type IntoIter = Iter<'a, Attribute>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.list.iter()
+    }
+}
+
+impl FromIterator<Attribute> for Attributes {
+    fn from_iter<T: IntoIterator<Item = Attribute>>(iter: T) -> Self {
+        let mut attributes = Attributes::new();
+        for attr in iter {
+            attributes.push(attr);
+        }
+        attributes
+    }
+}
+
+impl From<Vec<OwnedAttribute>> for Attributes {
+    fn from(attributes: Vec<OwnedAttribute>) -> Self {
+        attributes.into_iter().map(Attribute::from).collect()
+    }
+}
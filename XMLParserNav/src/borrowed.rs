@@ -0,0 +1,444 @@
+use std::borrow::Cow;
+use xml::common::XmlVersion;
+use xml::namespace::{NS_XMLNS_PREFIX, NS_XMLNS_URI, NS_XML_PREFIX, NS_XML_URI};
+use crate::attribute::{Attribute, Attributes};
+use crate::element::{Comment, Element, Node, ProcessingInstruction, Text};
+use crate::errors::{qualified, ParseError, ParseErrorKind, XmlErrors};
+use crate::lexer::{is_space, BorrowedAttribute, Lexeme, Lexer};
+use crate::options::{Budget, ParseOptions, DEFAULT_OPTIONS};
+use crate::position::Span;
+use crate::tree::ElementTree;
+
+/// An element parsed by `BorrowedDocument`, borrowing its names and text from the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BorrowedElement<'a> {
+    /// The prefix of the element name
+    pub prefix: Option<&'a str>,
+    /// The local name of the element
+    pub tag: &'a str,
+    /// The attributes of the element, in document order
+    pub attributes: Vec<BorrowedAttribute<'a>>,
+    /// The child elements of the element
+    pub children: Vec<BorrowedElement<'a>>,
+    /// The text content of the element, only allocated when it had to be unescaped or joined
+    pub text: Option<Cow<'a, str>>,
+    /// The content of the element in document order
+    pub nodes: Vec<BorrowedNode<'a>>,
+    /// Where the element was found in the input
+    pub span: Span,
+}
+
+/// A piece of a `BorrowedElement`'s content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BorrowedNode<'a> {
+    /// A child element, by its index in `children`
+    Element(usize),
+    /// A run of character data
+    Text(Cow<'a, str>, Span),
+    /// A comment
+    Comment(&'a str, Span),
+    /// A processing instruction, with its target and data
+    ProcessingInstruction(&'a str, Option<&'a str>, Span),
+}
+
+impl<'a> BorrowedNode<'a> {
+    ///Copies the node into an owned `Node`
+    pub fn to_node(&self) -> Node {
+        match *self {
+            BorrowedNode::Element(index) => Node::Element(index),
+            BorrowedNode::Text(ref value, span) => Node::Text(Text {
+                value: value.to_string(),
+                span: Some(span),
+            }),
+            BorrowedNode::Comment(value, span) => Node::Comment(Comment {
+                value: value.to_string(),
+                span: Some(span),
+            }),
+            BorrowedNode::ProcessingInstruction(target, data, span) => {
+                Node::ProcessingInstruction(ProcessingInstruction {
+                    target: target.to_string(),
+                    data: data.map(str::to_string),
+                    span: Some(span),
+                })
+            }
+        }
+    }
+}
+
+impl<'a> BorrowedElement<'a> {
+    ///Looks up the value of an attribute by its qualified name (`prefix:local`)
+    pub fn attribute(&self, qname: &str) -> Option<&str> {
+        let (prefix, local) = match qname.split_once(':') {
+            Some((prefix, local)) => (Some(prefix), local),
+            None => (None, qname),
+        };
+        self.attributes
+            .iter()
+            .find(|attr| attr.prefix == prefix && attr.name == local)
+            .map(|attr| attr.value.as_ref())
+    }
+
+    ///Copies the element and its subtree into an owned `Element`
+    pub fn to_element(&self) -> Element {
+        self.to_element_in(&mut Vec::new())
+    }
+
+    ///Copies the element, `scope` holding the namespace declarations of its ancestors.
+    ///Elements are kept on a stack rather than the call stack, like `Element::parse_started`.
+    pub(crate) fn to_element_in<'s>(&'s self, scope: &mut Vec<(Option<&'s str>, &'s str)>) -> Element {
+        // the elements being copied, how many of their children are done, and the scope outside them
+        let mut open = vec![(self, 0, self.shallow(scope), 0)];
+        loop {
+            let (borrowed, done, _, _) = open.last_mut().unwrap();
+            let borrowed: &'s BorrowedElement<'a> = borrowed;
+            match borrowed.children.get(*done) {
+                Some(child) => {
+                    *done += 1;
+                    let declared = scope.len();
+                    open.push((child, 0, child.shallow(scope), declared));
+                }
+                None => {
+                    let (_, _, element, declared) = open.pop().unwrap();
+                    scope.truncate(declared);
+                    match open.last_mut() {
+                        Some((_, _, parent, _)) => parent.children.push(element),
+                        None => return element,
+                    }
+                }
+            }
+        }
+    }
+
+    ///Copies the element without its children, adding its namespace declarations to `scope`
+    fn shallow<'s>(&'s self, scope: &mut Vec<(Option<&'s str>, &'s str)>) -> Element {
+        for attr in &self.attributes {
+            match (attr.prefix, attr.name) {
+                (None, NS_XMLNS_PREFIX) => scope.push((None, attr.value.as_ref())),
+                (Some(NS_XMLNS_PREFIX), prefix) => scope.push((Some(prefix), attr.value.as_ref())),
+                _ => {}
+            }
+        }
+        let attributes: Attributes = self
+            .attributes
+            .iter()
+            .map(|attr| {
+                let namespace = match attr.prefix {
+                    Some(NS_XML_PREFIX) => Some(NS_XML_URI.to_string()),
+                    Some(NS_XMLNS_PREFIX) => Some(NS_XMLNS_URI.to_string()),
+                    None if attr.name == NS_XMLNS_PREFIX => Some(NS_XMLNS_URI.to_string()),
+                    Some(prefix) => resolve(scope, Some(prefix)),
+                    None => None,
+                };
+                Attribute {
+                    prefix: attr.prefix.map(str::to_string),
+                    name: attr.name.to_string(),
+                    namespace,
+                    value: attr.value.to_string(),
+                    span: Some(attr.span),
+                }
+            })
+            .collect();
+
+        Element {
+            namespace: self.prefix.map(str::to_string),
+            tag: self.tag.to_string(),
+            attributes,
+            children: Vec::with_capacity(self.children.len()),
+            text: self.text.as_ref().map(|text| text.to_string()),
+            nodes: self.nodes.iter().map(BorrowedNode::to_node).collect(),
+            span: Some(self.span),
+        }
+    }
+
+    ///Adds a run of character data to the content
+    pub(crate) fn push_text(&mut self, value: Cow<'a, str>, span: Span) {
+        match self.text {
+            Some(ref mut text) => text.to_mut().push_str(&value),
+            None => self.text = Some(value.clone()),
+        }
+        // adjacent runs, such as text around a CDATA section, are joined
+        match self.nodes.last_mut() {
+            Some(BorrowedNode::Text(last, last_span)) => {
+                last.to_mut().push_str(&value);
+                last_span.end = span.end;
+            }
+            _ => self.nodes.push(BorrowedNode::Text(value, span)),
+        }
+    }
+}
+
+///The namespace URI `prefix` is bound to in `scope`
+fn resolve(scope: &[(Option<&str>, &str)], prefix: Option<&str>) -> Option<String> {
+    scope
+        .iter()
+        .rev()
+        .find(|(declared, _)| *declared == prefix)
+        .map(|(_, uri)| uri.to_string())
+        .filter(|uri| !uri.is_empty())
+}
+
+/// A document parsed straight from a string, without copying names or text.
+///
+/// This backend does not go through xml-rs; use `to_tree` to get an `ElementTree`.
+/// A DOCTYPE is skipped, so the entities it declares cannot be used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BorrowedDocument<'a> {
+    /// The version from the XML declaration
+    pub version: Option<&'a str>,
+    /// The encoding from the XML declaration
+    pub encoding: Option<&'a str>,
+    /// The root element
+    pub root: BorrowedElement<'a>,
+    /// Comments and processing instructions before the root element
+    pub prolog: Vec<BorrowedNode<'a>>,
+    /// Comments and processing instructions after the root element
+    pub epilog: Vec<BorrowedNode<'a>>,
+}
+
+impl<'a> BorrowedDocument<'a> {
+    ///Parses the document held in `input`, within the default `ParseOptions`
+    pub fn parse(input: &'a str) -> Result<BorrowedDocument<'a>, XmlErrors> {
+        BorrowedDocument::parse_with_options(input, &DEFAULT_OPTIONS)
+    }
+
+    ///Parses the document held in `input`, within the limits of `options`
+    pub fn parse_with_options(input: &'a str, options: &ParseOptions) -> Result<BorrowedDocument<'a>, XmlErrors> {
+        parse_content(input, None, &[], &mut Budget::new(options))
+    }
+
+    ///Parses a document held in a byte buffer, such as a memory-mapped file
+    pub fn parse_bytes(input: &'a [u8]) -> Result<BorrowedDocument<'a>, XmlErrors> {
+        match std::str::from_utf8(input) {
+            Ok(input) => BorrowedDocument::parse(input),
+            Err(err) => {
+                let valid = &input[..err.valid_up_to()];
+                let location = Lexer::new(std::str::from_utf8(valid).unwrap()).locate(valid.len());
+                Err(ParseError::new(ParseErrorKind::Encoding, Some(location)).into())
+            }
+        }
+    }
+
+    ///Copies the document into an owned `ElementTree`
+    pub fn to_tree(&self) -> ElementTree {
+        let mut tree = ElementTree::new_with_root(self.root.to_element());
+        tree.prolog = self.prolog.iter().map(BorrowedNode::to_node).collect();
+        tree.epilog = self.epilog.iter().map(BorrowedNode::to_node).collect();
+        if self.version == Some("1.1") {
+            tree.version = XmlVersion::Version11;
+        }
+        if let Some(encoding) = self.encoding {
+            tree.encoding = encoding.to_string();
+        }
+        tree
+    }
+}
+
+///Parses a document, or with a `container` element the content of a fragment into that element,
+///which is then returned as the root, within `budget`. `bound` holds the prefixes declared outside.
+pub(crate) fn parse_content<'a>(
+    input: &'a str,
+    container: Option<BorrowedElement<'a>>,
+    bound: &[&str],
+    budget: &mut Budget,
+) -> Result<BorrowedDocument<'a>, XmlErrors> {
+    let base = usize::from(container.is_some());
+    let mut lexer = Lexer::new(input);
+    let mut version = None;
+    let mut encoding = None;
+    let mut root = None;
+    let mut prolog = Vec::new();
+    let mut epilog = Vec::new();
+    let mut open: Vec<BorrowedElement<'a>> = container.into_iter().collect();
+    // the prefixes declared by the open elements, and where the declarations of each start
+    let mut scope: Vec<&'a str> = Vec::new();
+    let mut declared = Vec::new();
+
+    while let Some(lexeme) = lexer.next() {
+        let lexeme = lexeme.map_err(|err| within(err, &open[base..]))?;
+        match lexeme {
+            Lexeme::Declaration {
+                version: v,
+                encoding: e,
+                ..
+            } => {
+                version = Some(v);
+                encoding = e;
+            }
+            Lexeme::StartTag {
+                prefix,
+                local,
+                attributes,
+                empty,
+                span,
+            } => {
+                if root.is_some() {
+                    let err = ParseError::new(
+                        ParseErrorKind::Syntax("Unexpected element after the root element".to_string()),
+                        Some(span.start),
+                    );
+                    return Err(err.into());
+                }
+                let read = span.end.offset;
+                let within_budget = budget
+                    .enter(&qualified(prefix, local), attributes.len(), span.start)
+                    .and_then(|_| {
+                        attributes
+                            .iter()
+                            .try_for_each(|attr| budget.attribute(&attr.qname(), &attr.value, attr.span.start, read))
+                    });
+                within_budget.map_err(|err| within(err, &open[base..]))?;
+                let outer = scope.len();
+                scope.extend(
+                    attributes
+                        .iter()
+                        .filter(|attr| attr.prefix == Some(NS_XMLNS_PREFIX))
+                        .map(|attr| attr.name),
+                );
+                let unbound = |prefix| is_unbound(prefix, &scope, bound);
+                if unbound(prefix) {
+                    let msg = format!("Element {} prefix is unbound", qualified(prefix, local));
+                    let err = ParseError::new(ParseErrorKind::Syntax(msg), Some(span.start));
+                    return Err(within(err, &open[base..]).into());
+                }
+                if let Some(attr) = attributes.iter().find(|attr| unbound(attr.prefix)) {
+                    let msg = format!("Attribute {} prefix is unbound", attr.qname());
+                    let err = ParseError::new(ParseErrorKind::Syntax(msg), Some(attr.span.start));
+                    return Err(within(err, &open[base..]).into());
+                }
+                let element = BorrowedElement {
+                    prefix,
+                    tag: local,
+                    attributes,
+                    children: Vec::new(),
+                    text: None,
+                    nodes: Vec::new(),
+                    span,
+                };
+                if empty {
+                    budget.leave();
+                    scope.truncate(outer);
+                    close(element, &mut open, &mut root);
+                } else {
+                    declared.push(outer);
+                    open.push(element);
+                }
+            }
+            Lexeme::EndTag { prefix, local, span } => match if open.len() > base { open.pop() } else { None } {
+                Some(mut element) if element.prefix == prefix && element.tag == local => {
+                    element.span.end = span.end;
+                    budget.leave();
+                    scope.truncate(declared.pop().unwrap());
+                    close(element, &mut open, &mut root);
+                }
+                Some(element) => {
+                    let kind = ParseErrorKind::MismatchedTag {
+                        expected: qualified(element.prefix, element.tag),
+                        found: qualified(prefix, local),
+                    };
+                    open.push(element);
+                    return Err(within(ParseError::new(kind, Some(span.start)), &open[base..]).into());
+                }
+                None => {
+                    let err = ParseError::new(
+                        ParseErrorKind::Syntax(format!("Unexpected closing tag: {}", qualified(prefix, local))),
+                        Some(span.start),
+                    );
+                    return Err(err.into());
+                }
+            },
+            Lexeme::Text { value, span } => match open.last() {
+                Some(_) => {
+                    // whitespace between tags is not content, as with xml-rs
+                    if !value.chars().all(is_space) {
+                        budget.text(&value, span.start, span.end.offset).map_err(|err| within(err, &open[base..]))?;
+                        open.last_mut().unwrap().push_text(value, span);
+                    }
+                }
+                None if value.chars().all(is_space) => {}
+                None => {
+                    let err = ParseError::new(
+                        ParseErrorKind::Syntax("Unexpected characters outside the root element".to_string()),
+                        Some(span.start),
+                    );
+                    return Err(err.into());
+                }
+            },
+            Lexeme::CData { value, span } => match open.last() {
+                Some(_) => {
+                    budget.text(value, span.start, span.end.offset).map_err(|err| within(err, &open[base..]))?;
+                    open.last_mut().unwrap().push_text(Cow::Borrowed(value), span);
+                }
+                None => {
+                    let err = ParseError::new(
+                        ParseErrorKind::Syntax("Unexpected CDATA outside the root element".to_string()),
+                        Some(span.start),
+                    );
+                    return Err(err.into());
+                }
+            },
+            Lexeme::Comment { value, span } => {
+                let node = BorrowedNode::Comment(value, span);
+                match open.last_mut() {
+                    Some(element) => element.nodes.push(node),
+                    None if root.is_some() => epilog.push(node),
+                    None => prolog.push(node),
+                }
+            }
+            Lexeme::ProcessingInstruction { target, data, span } => {
+                let node = BorrowedNode::ProcessingInstruction(target, data, span);
+                match open.last_mut() {
+                    Some(element) => element.nodes.push(node),
+                    None if root.is_some() => epilog.push(node),
+                    None => prolog.push(node),
+                }
+            }
+            Lexeme::Doctype { .. } => {}
+        }
+    }
+
+    if open.len() > base {
+        let location = lexer.locate(input.len());
+        return Err(within(ParseError::new(ParseErrorKind::UnexpectedEof, Some(location)), &open[base..]).into());
+    }
+    match root.or_else(|| open.pop()) {
+        Some(root) => Ok(BorrowedDocument {
+            version,
+            encoding,
+            root,
+            prolog,
+            epilog,
+        }),
+        None => {
+            let location = lexer.locate(input.len());
+            Err(ParseError::new(ParseErrorKind::UnexpectedEof, Some(location)).into())
+        }
+    }
+}
+
+///Whether `prefix` is neither declared in `scope` nor `bound` outside the input
+fn is_unbound(prefix: Option<&str>, scope: &[&str], bound: &[&str]) -> bool {
+    match prefix {
+        None | Some(NS_XML_PREFIX) | Some(NS_XMLNS_PREFIX) => false,
+        Some(prefix) => !scope.contains(&prefix) && !bound.contains(&prefix),
+    }
+}
+
+///Attaches a finished element to its parent, or makes it the root
+pub(crate) fn close<'a>(element: BorrowedElement<'a>, open: &mut [BorrowedElement<'a>], root: &mut Option<BorrowedElement<'a>>) {
+    match open.last_mut() {
+        Some(parent) => {
+            parent.nodes.push(BorrowedNode::Element(parent.children.len()));
+            parent.children.push(element);
+        }
+        None => *root = Some(element),
+    }
+}
+
+///Records the open elements on an error
+pub(crate) fn within(mut err: ParseError, open: &[BorrowedElement]) -> ParseError {
+    err.open_elements = open
+        .iter()
+        .map(|element| qualified(element.prefix, element.tag))
+        .collect();
+    err
+}
@@ -0,0 +1,211 @@
+use crate::attribute::Attribute;
+use crate::element::{Element, Node, Text};
+
+/// Fluent builder for an `Element`, created with `Element::builder`
+#[derive(Debug, Clone)]
+pub struct ElementBuilder {
+    element: Element,
+}
+
+impl ElementBuilder {
+    ///Creates a builder for an element named `name`, which may be prefixed (`h:price`)
+    pub fn new<S>(name: S) -> ElementBuilder
+    where
+        S: ToString,
+    {
+        ElementBuilder {
+            element: Element::new(name),
+        }
+    }
+
+    ///Sets an attribute, keyed by its qualified name (`prefix:local`)
+    pub fn attr<K, V>(mut self, name: K, value: V) -> ElementBuilder
+    where
+        K: ToString,
+        V: ToString,
+    {
+        self.element
+            .attributes
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
+    ///Sets an attribute in the namespace `uri`
+    pub fn attr_ns<U, K, V>(mut self, uri: U, name: K, value: V) -> ElementBuilder
+    where
+        U: ToString,
+        K: ToString,
+        V: ToString,
+    {
+        self.element.attributes.push(Attribute::new_ns(uri, name, value));
+        self
+    }
+
+    ///Declares `prefix` as bound to `uri` on this element, without putting the element itself
+    ///in that namespace. An empty prefix declares the default namespace.
+    pub fn xmlns<P, U>(self, prefix: P, uri: U) -> ElementBuilder
+    where
+        P: ToString,
+        U: ToString,
+    {
+        let prefix = prefix.to_string();
+        if prefix.is_empty() {
+            self.attr("xmlns", uri)
+        } else {
+            self.attr(format!("xmlns:{}", prefix), uri)
+        }
+    }
+
+    ///Sets the prefix of the element's own name, which must be declared on it or an ancestor.
+    ///An empty prefix leaves the name unprefixed.
+    pub fn prefix<P>(mut self, prefix: P) -> ElementBuilder
+    where
+        P: ToString,
+    {
+        let prefix = prefix.to_string();
+        self.element.namespace = if prefix.is_empty() { None } else { Some(prefix) };
+        self
+    }
+
+    ///Appends a child element
+    pub fn child<E>(mut self, child: E) -> ElementBuilder
+    where
+        E: Into<Element>,
+    {
+        self.element
+            .nodes
+            .push(Node::Element(self.element.children.len()));
+        self.element.children.push(child.into());
+        self
+    }
+
+    ///Appends every element of `children`
+    pub fn children<I, E>(mut self, children: I) -> ElementBuilder
+    where
+        I: IntoIterator<Item = E>,
+        E: Into<Element>,
+    {
+        for child in children {
+            self = self.child(child);
+        }
+        self
+    }
+
+    ///Appends to the text content of the element
+    pub fn text<S>(mut self, text: S) -> ElementBuilder
+    where
+        S: ToString,
+    {
+        let text = text.to_string();
+        self.element.text = match self.element.text.take() {
+            Some(old) => Some(old + &text),
+            None => Some(text.clone()),
+        };
+        self.element.nodes.push(Node::Text(Text {
+            value: text,
+            span: None,
+        }));
+        self
+    }
+
+    ///Returns the finished element
+    pub fn build(self) -> Element {
+        self.element
+    }
+}
+
+impl From<ElementBuilder> for Element {
+    fn from(builder: ElementBuilder) -> Self {
+        builder.build()
+    }
+}
+
+/// Builds an `Element` from an inline tree.
+///
+/// An element is written as its (optionally prefixed) name, followed by optional
+/// `[name = value, ...]` attributes and an optional `{ ... }` body. The body holds
+/// child elements, string literals for text, `(expr)` for text from an expression and
+/// `{expr}` to splice in an `Element` or `ElementBuilder`.
+///
+/// ```
+/// use XMLParserNav::xml;
+///
+/// let book = xml!(book[id = "second", "xmlns:h" = "Some_Prefix"] {
+///     title { "Midnight Rain" }
+///     h:price { (5.95) }
+/// });
+/// assert_eq!(book.children[1].text.as_deref(), Some("5.95"));
+/// ```
+#[macro_export]
+macro_rules! xml {
+    ($name:ident $(: $local:ident)? $([$($attrs:tt)*])? $({$($body:tt)*})?) => {{
+        #[allow(unused_mut)]
+        let mut builder = $crate::ElementBuilder::new($crate::xml!(@name $name $(: $local)?));
+        $($crate::xml!(@attrs builder; $($attrs)*);)?
+        $($crate::xml!(@body builder; $($body)*);)?
+        builder.build()
+    }};
+
+    (@name $p:ident : $l:ident) => { concat!(stringify!($p), ":", stringify!($l)) };
+    (@name $n:ident) => { stringify!($n) };
+
+    (@attrs $b:ident;) => {};
+    (@attrs $b:ident; $p:ident : $l:ident = $v:expr $(, $($rest:tt)*)?) => {
+        $b = $b.attr(concat!(stringify!($p), ":", stringify!($l)), $v);
+        $crate::xml!(@attrs $b; $($($rest)*)?);
+    };
+    (@attrs $b:ident; $k:ident = $v:expr $(, $($rest:tt)*)?) => {
+        $b = $b.attr(stringify!($k), $v);
+        $crate::xml!(@attrs $b; $($($rest)*)?);
+    };
+    (@attrs $b:ident; $k:literal = $v:expr $(, $($rest:tt)*)?) => {
+        $b = $b.attr($k, $v);
+        $crate::xml!(@attrs $b; $($($rest)*)?);
+    };
+
+    (@body $b:ident;) => {};
+    (@body $b:ident; $t:literal $($rest:tt)*) => {
+        $b = $b.text($t);
+        $crate::xml!(@body $b; $($rest)*);
+    };
+    (@body $b:ident; ($t:expr) $($rest:tt)*) => {
+        $b = $b.text($t);
+        $crate::xml!(@body $b; $($rest)*);
+    };
+    (@body $b:ident; {$e:expr} $($rest:tt)*) => {
+        $b = $b.child($e);
+        $crate::xml!(@body $b; $($rest)*);
+    };
+    (@body $b:ident; $p:ident : $l:ident [$($a:tt)*] {$($c:tt)*} $($rest:tt)*) => {
+        $b = $b.child($crate::xml!($p:$l [$($a)*] {$($c)*}));
+        $crate::xml!(@body $b; $($rest)*);
+    };
+    (@body $b:ident; $p:ident : $l:ident [$($a:tt)*] $($rest:tt)*) => {
+        $b = $b.child($crate::xml!($p:$l [$($a)*]));
+        $crate::xml!(@body $b; $($rest)*);
+    };
+    (@body $b:ident; $p:ident : $l:ident {$($c:tt)*} $($rest:tt)*) => {
+        $b = $b.child($crate::xml!($p:$l {$($c)*}));
+        $crate::xml!(@body $b; $($rest)*);
+    };
+    (@body $b:ident; $p:ident : $l:ident $($rest:tt)*) => {
+        $b = $b.child($crate::xml!($p:$l));
+        $crate::xml!(@body $b; $($rest)*);
+    };
+    (@body $b:ident; $n:ident [$($a:tt)*] {$($c:tt)*} $($rest:tt)*) => {
+        $b = $b.child($crate::xml!($n [$($a)*] {$($c)*}));
+        $crate::xml!(@body $b; $($rest)*);
+    };
+    (@body $b:ident; $n:ident [$($a:tt)*] $($rest:tt)*) => {
+        $b = $b.child($crate::xml!($n [$($a)*]));
+        $crate::xml!(@body $b; $($rest)*);
+    };
+    (@body $b:ident; $n:ident {$($c:tt)*} $($rest:tt)*) => {
+        $b = $b.child($crate::xml!($n {$($c)*}));
+        $crate::xml!(@body $b; $($rest)*);
+    };
+    (@body $b:ident; $n:ident $($rest:tt)*) => {
+        $b = $b.child($crate::xml!($n));
+        $crate::xml!(@body $b; $($rest)*);
+    };
+}
@@ -0,0 +1,238 @@
+use xml::namespace::{NS_XMLNS_PREFIX, NS_XML_PREFIX, NS_XML_URI};
+use crate::element::{Element, Node};
+use crate::tree::ElementTree;
+
+/// Which canonical form `ElementTree::canonicalize` writes: Canonical XML 1.0 by default,
+/// or Exclusive XML Canonicalization 1.0.
+///
+/// The output reflects the tree, so parse with `ParseOptions::preserve_whitespace` to
+/// canonicalize a document as it was written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Canonicalization {
+    /// Whether to use exclusive canonicalization, declaring only the namespaces an element uses
+    pub exclusive: bool,
+    /// Whether comments are kept
+    pub with_comments: bool,
+    /// Prefixes that exclusive canonicalization still declares the inclusive way,
+    /// `#default` standing for the default namespace (the InclusiveNamespaces PrefixList)
+    pub inclusive_prefixes: Vec<String>,
+}
+
+impl Canonicalization {
+    ///Canonical XML 1.0 without comments
+    pub fn new() -> Canonicalization {
+        Canonicalization::default()
+    }
+}
+
+/// Namespace bindings, innermost last; `None` is the default namespace
+type Bindings<'e> = Vec<(Option<&'e str>, &'e str)>;
+
+fn lookup<'e>(bindings: &Bindings<'e>, prefix: Option<&str>) -> Option<&'e str> {
+    bindings.iter().rev().find(|&&(p, _)| p == prefix).map(|&(_, uri)| uri)
+}
+
+struct Canonicalizer<'e> {
+    options: &'e Canonicalization,
+    /// Declarations made by the element and its ancestors
+    in_scope: Bindings<'e>,
+    /// Declarations written out for the element and its ancestors
+    rendered: Bindings<'e>,
+    out: String,
+}
+
+///Writes the canonical form of `tree`
+pub(crate) fn canonicalize(tree: &ElementTree, options: &Canonicalization) -> String {
+    let mut c14n = Canonicalizer {
+        options,
+        in_scope: Vec::new(),
+        rendered: Vec::new(),
+        out: String::new(),
+    };
+    for node in &tree.prolog {
+        if c14n.misc(node) {
+            c14n.out.push('\n');
+        }
+    }
+    if let Some(ref root) = tree.root {
+        c14n.element(root);
+    }
+    for node in &tree.epilog {
+        let len = c14n.out.len();
+        c14n.out.push('\n');
+        if !c14n.misc(node) {
+            c14n.out.truncate(len);
+        }
+    }
+    c14n.out
+}
+
+impl<'e> Canonicalizer<'e> {
+    ///Writes a comment or processing instruction, returning whether anything was written
+    fn misc(&mut self, node: &Node) -> bool {
+        match *node {
+            Node::Comment(ref comment) if self.options.with_comments => {
+                self.out.push_str("<!--");
+                self.out.push_str(&comment.value);
+                self.out.push_str("-->");
+                true
+            }
+            Node::ProcessingInstruction(ref pi) => {
+                self.out.push_str("<?");
+                self.out.push_str(&pi.target);
+                // the whitespace after the target separates it from the data
+                if let Some(data) = pi.data.as_deref().map(str::trim_start).filter(|data| !data.is_empty()) {
+                    self.out.push(' ');
+                    self.out.push_str(data);
+                }
+                self.out.push_str("?>");
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn element(&mut self, element: &'e Element) {
+        let (in_scope, rendered) = (self.in_scope.len(), self.rendered.len());
+        for attr in &element.attributes {
+            match (attr.prefix.as_deref(), attr.name.as_str()) {
+                (None, NS_XMLNS_PREFIX) => self.in_scope.push((None, &attr.value)),
+                (Some(NS_XMLNS_PREFIX), prefix) => self.in_scope.push((Some(prefix), &attr.value)),
+                _ => {}
+            }
+        }
+
+        let declarations = self.declarations(element);
+        let mut attributes: Vec<_> = element
+            .attributes
+            .iter()
+            .filter(|attr| match attr.prefix.as_deref() {
+                None => attr.name != NS_XMLNS_PREFIX,
+                Some(prefix) => prefix != NS_XMLNS_PREFIX,
+            })
+            .map(|attr| {
+                let uri = match attr.prefix.as_deref() {
+                    None => "",
+                    Some(NS_XML_PREFIX) => NS_XML_URI,
+                    Some(prefix) => attr
+                        .namespace
+                        .as_deref()
+                        .or_else(|| lookup(&self.in_scope, Some(prefix)))
+                        .unwrap_or(""),
+                };
+                (uri, attr)
+            })
+            .collect();
+        attributes.sort_by(|a, b| (a.0, &a.1.name).cmp(&(b.0, &b.1.name)));
+
+        let qname = match element.namespace {
+            Some(ref prefix) => format!("{}:{}", prefix, element.tag),
+            None => element.tag.clone(),
+        };
+        self.out.push('<');
+        self.out.push_str(&qname);
+        for (prefix, uri) in declarations {
+            self.out.push_str(" xmlns");
+            if let Some(prefix) = prefix {
+                self.out.push(':');
+                self.out.push_str(prefix);
+            }
+            self.attribute_value(uri);
+        }
+        for (_, attr) in attributes {
+            self.out.push(' ');
+            self.out.push_str(&attr.qname());
+            self.attribute_value(&attr.value);
+        }
+        self.out.push('>');
+
+        if element.nodes_match_content() {
+            for node in &element.nodes {
+                match *node {
+                    Node::Element(index) => self.element(&element.children[index]),
+                    Node::Text(ref text) => self.text(&text.value),
+                    _ => {
+                        self.misc(node);
+                    }
+                }
+            }
+        } else {
+            if let Some(ref text) = element.text {
+                self.text(text);
+            }
+            for child in &element.children {
+                self.element(child);
+            }
+        }
+
+        self.out.push_str("</");
+        self.out.push_str(&qname);
+        self.out.push('>');
+        self.in_scope.truncate(in_scope);
+        self.rendered.truncate(rendered);
+    }
+
+    ///The namespace declarations to write on `element`, default namespace first, then by prefix
+    fn declarations(&mut self, element: &'e Element) -> Bindings<'e> {
+        let mut prefixes: Vec<Option<&str>> = if self.options.exclusive {
+            // the namespaces the element visibly uses
+            let mut used = vec![element.namespace.as_deref()];
+            used.extend(element.attributes.iter().filter_map(|attr| attr.prefix.as_deref()).map(Some));
+            for prefix in &self.options.inclusive_prefixes {
+                let prefix = if prefix == "#default" { None } else { Some(prefix.as_str()) };
+                if lookup(&self.in_scope, prefix).is_some() {
+                    used.push(prefix);
+                }
+            }
+            used
+        } else {
+            self.in_scope.iter().map(|&(prefix, _)| prefix).collect()
+        };
+        prefixes.retain(|&prefix| prefix != Some(NS_XML_PREFIX) && prefix != Some(NS_XMLNS_PREFIX));
+        prefixes.sort();
+        prefixes.dedup();
+
+        let mut declarations = Vec::new();
+        for prefix in prefixes {
+            let uri = match (lookup(&self.in_scope, prefix), prefix) {
+                (Some(uri), _) => uri,
+                // no default namespace is the same as an empty one
+                (None, None) => "",
+                (None, Some(_)) => continue,
+            };
+            if lookup(&self.rendered, prefix).unwrap_or("") != uri {
+                self.rendered.push((prefix, uri));
+                declarations.push((prefix, uri));
+            }
+        }
+        declarations
+    }
+
+    fn attribute_value(&mut self, value: &str) {
+        self.out.push_str("=\"");
+        for c in value.chars() {
+            match c {
+                '&' => self.out.push_str("&amp;"),
+                '<' => self.out.push_str("&lt;"),
+                '"' => self.out.push_str("&quot;"),
+                '\t' => self.out.push_str("&#x9;"),
+                '\n' => self.out.push_str("&#xA;"),
+                '\r' => self.out.push_str("&#xD;"),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+
+    fn text(&mut self, value: &str) {
+        for c in value.chars() {
+            match c {
+                '&' => self.out.push_str("&amp;"),
+                '<' => self.out.push_str("&lt;"),
+                '>' => self.out.push_str("&gt;"),
+                '\r' => self.out.push_str("&#xD;"),
+                c => self.out.push(c),
+            }
+        }
+    }
+}
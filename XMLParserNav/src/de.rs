@@ -0,0 +1,543 @@
+use std::str::FromStr;
+use serde::de::{self, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, Unexpected, VariantAccess, Visitor};
+use serde::Deserialize;
+use xml::namespace::NS_XMLNS_PREFIX;
+use crate::element::Element;
+use crate::errors::XmlErrors;
+use crate::tree::ElementTree;
+
+/// The field name, given with `#[serde(rename = "$text")]`, that maps to the text of an element.
+///
+/// Fields renamed `@name` map to attributes, other fields to child elements of the same
+/// name, several of them making a sequence.
+pub const TEXT: &str = "$text";
+
+///Reads a `T` from `element`, which may be one returned by a query
+pub fn from_element<'de, T: Deserialize<'de>>(element: &'de Element) -> Result<T, XmlErrors> {
+    T::deserialize(ElementDeserializer { element })
+}
+
+///Reads a `T` from the root element of `tree`
+pub fn from_tree<'de, T: Deserialize<'de>>(tree: &'de ElementTree) -> Result<T, XmlErrors> {
+    match tree.root {
+        Some(ref root) => from_element(root),
+        None => Err(XmlErrors::ElementNotFound { t: "root".to_string() }),
+    }
+}
+
+fn qname(element: &Element) -> String {
+    match element.namespace {
+        Some(ref prefix) => format!("{}:{}", prefix, element.tag),
+        None => element.tag.clone(),
+    }
+}
+
+/// Deserializes an attribute value or the text of an element
+struct TextDeserializer<'de>(&'de str);
+
+impl<'de> TextDeserializer<'de> {
+    fn parse<T: FromStr>(&self) -> Result<T, XmlErrors> {
+        self.0.trim().parse().map_err(|_| XmlErrors::ValueFromStr { t: self.0.to_string() })
+    }
+}
+
+/// Methods that parse the text as the value they ask for
+macro_rules! parse_text {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for TextDeserializer<'de> {
+    type Error = XmlErrors;
+
+    parse_text! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        // the lexical forms of xs:boolean
+        match self.0.trim() {
+            "true" | "1" => visitor.visit_bool(true),
+            "false" | "0" => visitor.visit_bool(false),
+            _ => Err(XmlErrors::ValueFromStr { t: self.0.to_string() }),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_borrowed_bytes(self.0.as_bytes())
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_borrowed_bytes(self.0.as_bytes())
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        // a whitespace-separated list, as in xs:list
+        visitor.visit_seq(Words(self.0.split_whitespace()))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, XmlErrors> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, XmlErrors> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        Err(de::Error::invalid_type(Unexpected::Str(self.0), &visitor))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, XmlErrors> {
+        Err(de::Error::invalid_type(Unexpected::Str(self.0), &visitor))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, XmlErrors> {
+        visitor.visit_enum(self.0.trim().into_deserializer())
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_unit()
+    }
+}
+
+/// The items of a whitespace-separated list
+struct Words<'de>(std::str::SplitWhitespace<'de>);
+
+impl<'de> SeqAccess<'de> for Words<'de> {
+    type Error = XmlErrors;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, XmlErrors> {
+        self.0.next().map(|word| seed.deserialize(TextDeserializer(word))).transpose()
+    }
+}
+
+/// Deserializes an element: a struct or map from its attributes, text and children,
+/// or a plain value from its text
+struct ElementDeserializer<'de> {
+    element: &'de Element,
+}
+
+impl<'de> ElementDeserializer<'de> {
+    fn text(&self) -> TextDeserializer<'de> {
+        TextDeserializer(self.element.text.as_deref().unwrap_or(""))
+    }
+}
+
+/// Methods that read the element's text
+macro_rules! forward_to_text {
+    ($($method:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+                self.text().$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ElementDeserializer<'de> {
+    type Error = XmlErrors;
+
+    forward_to_text! {
+        deserialize_bool,
+        deserialize_i8,
+        deserialize_i16,
+        deserialize_i32,
+        deserialize_i64,
+        deserialize_i128,
+        deserialize_u8,
+        deserialize_u16,
+        deserialize_u32,
+        deserialize_u64,
+        deserialize_u128,
+        deserialize_f32,
+        deserialize_f64,
+        deserialize_char,
+        deserialize_str,
+        deserialize_string,
+        deserialize_bytes,
+        deserialize_byte_buf,
+        deserialize_identifier,
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        if self.element.attributes.is_empty() && self.element.children.is_empty() {
+            self.text().deserialize_any(visitor)
+        } else {
+            self.deserialize_map(visitor)
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_seq(Elements(self.element.children.iter()))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, XmlErrors> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, XmlErrors> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_map(Entries::new(self.element, None))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, XmlErrors> {
+        visitor.visit_map(Entries::new(self.element, Some(fields)))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, XmlErrors> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_unit()
+    }
+}
+
+/// The variant of an enum is the name of the element's first child, or its text for a unit variant
+impl<'de> EnumAccess<'de> for ElementDeserializer<'de> {
+    type Error = XmlErrors;
+    type Variant = Variant<'de>;
+
+    fn variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<(T::Value, Variant<'de>), XmlErrors> {
+        match self.element.children.first() {
+            Some(child) => {
+                let variant = seed.deserialize(IntoDeserializer::<XmlErrors>::into_deserializer(qname(child)))?;
+                Ok((variant, Variant(Some(child))))
+            }
+            None => Ok((seed.deserialize(TextDeserializer(self.text().0.trim()))?, Variant(None))),
+        }
+    }
+}
+
+/// The content of an enum variant, the child element it was named by
+struct Variant<'de>(Option<&'de Element>);
+
+impl<'de> Variant<'de> {
+    fn element(self) -> Result<ElementDeserializer<'de>, XmlErrors> {
+        match self.0 {
+            Some(element) => Ok(ElementDeserializer { element }),
+            None => Err(de::Error::invalid_type(Unexpected::UnitVariant, &"a variant with content")),
+        }
+    }
+}
+
+impl<'de> VariantAccess<'de> for Variant<'de> {
+    type Error = XmlErrors;
+
+    fn unit_variant(self) -> Result<(), XmlErrors> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, XmlErrors> {
+        seed.deserialize(self.element()?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, XmlErrors> {
+        de::Deserializer::deserialize_seq(self.element()?, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, XmlErrors> {
+        de::Deserializer::deserialize_struct(self.element()?, "", fields, visitor)
+    }
+}
+
+/// Elements read one after another
+struct Elements<I>(I);
+
+impl<'de, I: Iterator<Item = &'de Element>> SeqAccess<'de> for Elements<I> {
+    type Error = XmlErrors;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, XmlErrors> {
+        self.0.next().map(|element| seed.deserialize(ElementDeserializer { element })).transpose()
+    }
+}
+
+/// What a key of an element's map stands for
+enum Entry<'de> {
+    /// An attribute value or the text
+    Text(&'de str),
+    /// The children with the key as their name
+    Children(Vec<&'de Element>),
+}
+
+/// The attributes, text and children of an element as a map
+struct Entries<'de> {
+    entries: std::vec::IntoIter<(String, Entry<'de>)>,
+    value: Option<Entry<'de>>,
+}
+
+impl<'de> Entries<'de> {
+    ///The entries of `element`, for a struct with `fields` or for a map if there are none
+    fn new(element: &'de Element, fields: Option<&'static [&'static str]>) -> Entries<'de> {
+        let wants = |key: &str| fields.is_some_and(|fields| fields.contains(&key));
+        let mut children: Vec<(String, Entry<'de>)> = Vec::new();
+        for child in &element.children {
+            let name = qname(child);
+            match children.iter_mut().find(|(key, _)| *key == name) {
+                Some((_, Entry::Children(group))) => group.push(child),
+                _ => children.push((name, Entry::Children(vec![child]))),
+            }
+        }
+
+        let mut entries = Vec::new();
+        for attr in &element.attributes {
+            let name = attr.qname();
+            if name == NS_XMLNS_PREFIX || attr.prefix.as_deref() == Some(NS_XMLNS_PREFIX) {
+                continue;
+            }
+            let key = format!("@{}", name);
+            // a field without the `@` is filled from an attribute too, unless a child has its name
+            let key = if !wants(&key) && wants(&name) && !children.iter().any(|(child, _)| *child == name) {
+                name
+            } else {
+                key
+            };
+            entries.push((key, Entry::Text(&attr.value)));
+        }
+        let text = element.text.as_deref();
+        if wants(TEXT) || (fields.is_none() && text.is_some_and(|text| !text.trim().is_empty())) {
+            entries.push((TEXT.to_string(), Entry::Text(text.unwrap_or(""))));
+        }
+        entries.extend(children);
+        Entries {
+            entries: entries.into_iter(),
+            value: None,
+        }
+    }
+}
+
+impl<'de> MapAccess<'de> for Entries<'de> {
+    type Error = XmlErrors;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, XmlErrors> {
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(key.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, XmlErrors> {
+        match self.value.take() {
+            Some(Entry::Text(text)) => seed.deserialize(TextDeserializer(text)),
+            Some(Entry::Children(elements)) => seed.deserialize(Children(elements)),
+            None => Err(de::Error::custom("value asked for before its key")),
+        }
+    }
+}
+
+/// The children of an element that share a name: a sequence, or a single value
+struct Children<'de>(Vec<&'de Element>);
+
+impl<'de> Children<'de> {
+    fn one(self) -> Result<ElementDeserializer<'de>, XmlErrors> {
+        match *self.0 {
+            [element] => Ok(ElementDeserializer { element }),
+            ref elements => Err(de::Error::custom(format!(
+                "expected one <{}> element, found {}",
+                qname(elements[0]),
+                elements.len()
+            ))),
+        }
+    }
+}
+
+/// Methods that read the only element of the group
+macro_rules! forward_to_one {
+    ($($method:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+                self.one()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Children<'de> {
+    type Error = XmlErrors;
+
+    forward_to_one! {
+        deserialize_bool,
+        deserialize_i8,
+        deserialize_i16,
+        deserialize_i32,
+        deserialize_i64,
+        deserialize_i128,
+        deserialize_u8,
+        deserialize_u16,
+        deserialize_u32,
+        deserialize_u64,
+        deserialize_u128,
+        deserialize_f32,
+        deserialize_f64,
+        deserialize_char,
+        deserialize_str,
+        deserialize_string,
+        deserialize_bytes,
+        deserialize_byte_buf,
+        deserialize_unit,
+        deserialize_map,
+        deserialize_identifier,
+        deserialize_ignored_any,
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        if self.0.len() > 1 {
+            self.deserialize_seq(visitor)
+        } else {
+            self.one()?.deserialize_any(visitor)
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value, XmlErrors> {
+        self.one()?.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, XmlErrors> {
+        visitor.visit_seq(Elements(self.0.into_iter()))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, XmlErrors> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, XmlErrors> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, XmlErrors> {
+        self.one()?.deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, XmlErrors> {
+        self.one()?.deserialize_enum(name, variants, visitor)
+    }
+}
@@ -0,0 +1,725 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::ops::Range;
+use xml::reader::ParserConfig;
+use crate::attribute::{Attribute, Attributes};
+use crate::element::Element;
+use crate::errors::{ParseError, ParseErrorKind, XmlErrors};
+use crate::options::{Limit, ParseOptions};
+use crate::position::{location_after, Location};
+
+/// The `<!DOCTYPE>` of a document, with what was declared in its internal subset.
+/// External subsets are recorded but not fetched.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Doctype {
+    /// The name of the root element
+    pub name: String,
+    /// The public identifier of the external subset
+    pub public_id: Option<String>,
+    /// The system identifier (URI) of the external subset
+    pub system_id: Option<String>,
+    /// The internal subset as written, without the brackets
+    pub internal_subset: Option<String>,
+    /// General and parameter entities, in declaration order
+    pub entities: Vec<EntityDecl>,
+    /// Attribute declarations from `ATTLIST`s
+    pub attributes: Vec<AttributeDecl>,
+    /// Element declarations, with their content model as written
+    pub elements: Vec<(String, String)>,
+}
+
+/// An `<!ENTITY>` declaration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityDecl {
+    /// The entity name
+    pub name: String,
+    /// True for a parameter entity (`<!ENTITY % name ...>`)
+    pub parameter: bool,
+    /// The replacement text of an internal entity, with parameter entities and
+    /// character references already expanded. Text holding markup is not supported.
+    pub value: Option<String>,
+    /// The public identifier of an external entity
+    pub public_id: Option<String>,
+    /// The system identifier of an external entity
+    pub system_id: Option<String>,
+}
+
+/// One attribute of an `<!ATTLIST>` declaration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeDecl {
+    /// The element the attribute belongs to
+    pub element: String,
+    /// The attribute name
+    pub name: String,
+    /// The attribute type as written, such as `CDATA`, `ID` or `(a|b)`
+    pub kind: String,
+    /// Whether the attribute is required and its default value
+    pub default: AttributeDefault,
+}
+
+/// The default declaration of an attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeDefault {
+    /// `#REQUIRED`
+    Required,
+    /// `#IMPLIED`
+    Implied,
+    /// `#FIXED "value"`
+    Fixed(String),
+    /// A plain default value
+    Value(String),
+}
+
+impl Doctype {
+    ///Parses a whole `<!DOCTYPE ...>` declaration
+    pub fn parse(decl: &str) -> Result<Doctype, String> {
+        let mut cur = Cursor::new(decl);
+        cur.expect("<!DOCTYPE")?;
+        cur.space()?;
+        let mut doctype = Doctype {
+            name: cur.name()?.to_string(),
+            ..Doctype::default()
+        };
+        cur.skip_space();
+        if cur.eat("PUBLIC") {
+            cur.space()?;
+            doctype.public_id = Some(cur.quoted()?.to_string());
+            cur.space()?;
+            doctype.system_id = Some(cur.quoted()?.to_string());
+        } else if cur.eat("SYSTEM") {
+            cur.space()?;
+            doctype.system_id = Some(cur.quoted()?.to_string());
+        }
+        cur.skip_space();
+        if cur.eat("[") {
+            let start = cur.pos;
+            let end = subset_end(decl, start).ok_or("Unterminated internal subset")?;
+            let subset = &decl[start..end];
+            doctype.internal_subset = Some(subset.to_string());
+            doctype.declarations(subset, 0)?;
+            cur.pos = end + 1;
+            cur.skip_space();
+        }
+        cur.expect(">")?;
+        if cur.pos != decl.len() {
+            return Err("Unexpected content after the DOCTYPE".to_string());
+        }
+        Ok(doctype)
+    }
+
+    ///Reads the markup declarations of (part of) the internal subset
+    fn declarations(&mut self, subset: &str, depth: usize) -> Result<(), String> {
+        if depth > 16 {
+            return Err("Parameter entities nest too deeply".to_string());
+        }
+        let mut cur = Cursor::new(subset);
+        loop {
+            cur.skip_space();
+            if cur.rest().is_empty() {
+                return Ok(());
+            } else if cur.eat("%") {
+                let name = cur.name()?;
+                cur.expect(";")?;
+                let value = self.parameter(name)?.to_string();
+                self.declarations(&value, depth + 1)?;
+            } else if cur.eat("<!--") {
+                cur.until("-->")?;
+            } else if cur.eat("<?") {
+                cur.until("?>")?;
+            } else if cur.rest().starts_with("<!") {
+                let decl = cur.declaration()?;
+                let decl = self.expand_parameters(decl, depth)?;
+                self.declaration(&decl)?;
+            } else {
+                return Err(format!("Unexpected content in the internal subset: {}", cur.rest().chars().next().unwrap()));
+            }
+        }
+    }
+
+    fn declaration(&mut self, decl: &str) -> Result<(), String> {
+        let mut cur = Cursor::new(decl);
+        if cur.eat("<!ENTITY") {
+            cur.space()?;
+            let parameter = cur.eat("%");
+            if parameter {
+                cur.space()?;
+            }
+            let name = cur.name()?.to_string();
+            cur.space()?;
+            let mut entity = EntityDecl {
+                name,
+                parameter,
+                value: None,
+                public_id: None,
+                system_id: None,
+            };
+            if cur.eat("PUBLIC") {
+                cur.space()?;
+                entity.public_id = Some(cur.quoted()?.to_string());
+                cur.space()?;
+                entity.system_id = Some(cur.quoted()?.to_string());
+            } else if cur.eat("SYSTEM") {
+                cur.space()?;
+                entity.system_id = Some(cur.quoted()?.to_string());
+            } else {
+                entity.value = Some(char_refs(cur.quoted()?)?);
+            }
+            // an NDATA notation or anything else up to `>` is not needed
+            // the first declaration of an entity is binding
+            if !self.entities.iter().any(|e| e.name == entity.name && e.parameter == entity.parameter) {
+                self.entities.push(entity);
+            }
+        } else if cur.eat("<!ATTLIST") {
+            cur.space()?;
+            let element = cur.name()?.to_string();
+            loop {
+                cur.skip_space();
+                if cur.rest().starts_with('>') {
+                    break;
+                }
+                let name = cur.name()?.to_string();
+                cur.space()?;
+                let start = cur.pos;
+                if cur.rest().starts_with('(') {
+                    cur.until(")")?;
+                } else if cur.name()? == "NOTATION" {
+                    cur.skip_space();
+                    cur.expect("(")?;
+                    cur.until(")")?;
+                }
+                let kind = decl[start..cur.pos].to_string();
+                cur.space()?;
+                let default = if cur.eat("#REQUIRED") {
+                    AttributeDefault::Required
+                } else if cur.eat("#IMPLIED") {
+                    AttributeDefault::Implied
+                } else if cur.eat("#FIXED") {
+                    cur.space()?;
+                    AttributeDefault::Fixed(cur.quoted()?.to_string())
+                } else {
+                    AttributeDefault::Value(cur.quoted()?.to_string())
+                };
+                if !self.attributes.iter().any(|a| a.element == element && a.name == name) {
+                    self.attributes.push(AttributeDecl {
+                        element: element.clone(),
+                        name,
+                        kind,
+                        default,
+                    });
+                }
+            }
+        } else if cur.eat("<!ELEMENT") {
+            cur.space()?;
+            let name = cur.name()?.to_string();
+            let content = cur.rest().trim_end_matches('>').trim().to_string();
+            self.elements.push((name, content));
+        } else if !cur.eat("<!NOTATION") {
+            return Err(format!("Unknown markup declaration: {}", decl));
+        }
+        Ok(())
+    }
+
+    fn parameter(&self, name: &str) -> Result<&str, String> {
+        match self.entities.iter().find(|e| e.parameter && e.name == name) {
+            Some(EntityDecl { value: Some(value), .. }) => Ok(value),
+            Some(_) => Err(format!("External parameter entity %{}; is not supported", name)),
+            None => Err(format!("Undeclared parameter entity %{};", name)),
+        }
+    }
+
+    ///Replaces parameter entity references outside of literals in a declaration
+    fn expand_parameters(&self, decl: &str, depth: usize) -> Result<String, String> {
+        if depth > 16 {
+            return Err("Parameter entities nest too deeply".to_string());
+        }
+        let mut out = String::with_capacity(decl.len());
+        let mut quote = None;
+        let mut rest = decl;
+        while let Some(c) = rest.chars().next() {
+            match (quote, c) {
+                (Some(q), _) if q == c => quote = None,
+                (None, '"' | '\'') => quote = Some(c),
+                // inside entity values, parameter entities are expanded too
+                (_, '%') if rest[1..].starts_with(|c: char| c.is_alphabetic() || c == '_') => {
+                    let end = rest.find(';').ok_or("Unterminated parameter entity reference")?;
+                    let value = self.parameter(&rest[1..end])?;
+                    out.push_str(&self.expand_parameters(value, depth + 1)?);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+                _ => {}
+            }
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+        Ok(out)
+    }
+
+    ///The replacement text of every general entity that can be expanded, with nested entity
+    ///references expanded, within the entity limits of `options`. No replacement text may be
+    ///more than `max_entity_amplification` times as long as the DOCTYPE.
+    ///
+    ///The values are substituted as text and never parsed, so entities whose replacement text
+    ///holds markup are left out like unresolved external ones, making any use of them an error.
+    pub fn entity_values(&self, options: &ParseOptions) -> Result<HashMap<String, String>, ParseErrorKind> {
+        let mut values = HashMap::new();
+        let mut expansion = Expansion::new(options, self);
+        for entity in self.entities.iter().filter(|e| !e.parameter) {
+            if entity.value.is_none() && !options.resolve_external_entities {
+                // left out, so that using it is an error
+                continue;
+            }
+            match self.expand(&format!("&{};", entity.name), &mut expansion) {
+                Ok(value) => {
+                    values.insert(entity.name.clone(), value);
+                }
+                Err(_) if expansion.markup => expansion.markup = false,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(values)
+    }
+
+    ///The replacement text of `entity` before expansion, reading external entities if allowed
+    fn replacement<'d>(&self, entity: &'d EntityDecl, options: &ParseOptions) -> Result<Cow<'d, str>, ParseErrorKind> {
+        if let Some(ref value) = entity.value {
+            return Ok(Cow::Borrowed(value));
+        }
+        let system_id = match entity.system_id {
+            Some(ref system_id) if options.resolve_external_entities => system_id,
+            _ => return Err(ParseErrorKind::ExternalEntity(entity.name.clone())),
+        };
+        let path = system_id.strip_prefix("file://").unwrap_or(system_id);
+        let text = fs::read_to_string(path)
+            .map_err(|err| ParseErrorKind::Syntax(format!("Cannot read external entity '{}': {}", entity.name, err)))?;
+        if text.len() > options.max_text_length {
+            return Err(ParseErrorKind::LimitExceeded(Limit::TextLength));
+        }
+        // drop the text declaration
+        let text = match text.strip_prefix("<?xml").and_then(|rest| rest.find("?>").map(|end| &rest[end + 2..])) {
+            Some(rest) => rest.to_string(),
+            None => text,
+        };
+        Ok(Cow::Owned(text))
+    }
+
+    ///Expands the entity and character references in `text`
+    fn expand(&self, text: &str, expansion: &mut Expansion) -> Result<String, ParseErrorKind> {
+        let syntax = |msg: &str| ParseErrorKind::Syntax(msg.to_string());
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(index) = rest.find('&') {
+            out.push_str(&rest[..index]);
+            rest = &rest[index..];
+            let end = rest.find(';').ok_or_else(|| syntax("Unterminated entity reference"))?;
+            let name = &rest[1..end];
+            rest = &rest[end + 1..];
+            if let Some(c) = predefined(name) {
+                out.push(c);
+                continue;
+            }
+            if name.starts_with('#') {
+                out.push_str(&char_refs(&format!("&{};", name)).map_err(ParseErrorKind::Syntax)?);
+                continue;
+            }
+            if expansion.open.iter().any(|o| o == name) {
+                return Err(syntax(&format!("Entity &{}; refers to itself", name)));
+            }
+            expansion.count += 1;
+            if expansion.count > expansion.options.max_entity_expansions {
+                return Err(ParseErrorKind::LimitExceeded(Limit::EntityExpansions));
+            }
+            let entity = match self.entities.iter().find(|e| !e.parameter && e.name == name) {
+                Some(entity) => entity,
+                None => return Err(syntax(&format!("Unexpected entity: {}", name))),
+            };
+            let value = self.replacement(entity, expansion.options)?;
+            if value.contains('<') {
+                expansion.markup = true;
+                return Err(markup_entity(name));
+            }
+            expansion.open.push(name.to_string());
+            out.push_str(&self.expand(&value, expansion)?);
+            expansion.open.pop();
+            if out.len() > expansion.options.max_text_length {
+                return Err(ParseErrorKind::LimitExceeded(Limit::TextLength));
+            }
+            if out.len() > expansion.amplified {
+                return Err(ParseErrorKind::LimitExceeded(Limit::EntityAmplification));
+            }
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    ///Adds the declared default values of attributes missing from `element` and its descendants,
+    ///expanding the entities in them within the limits of `options`
+    pub fn add_default_attributes(&self, element: &mut Element, options: &ParseOptions) -> Result<(), ParseError> {
+        if self.attributes.is_empty() {
+            return Ok(());
+        }
+        let qname = element.qname();
+        let location = element.span.map(|span| span.start);
+        self.add_defaults(&qname, &mut element.attributes, location, options)?;
+        for child in element.children.iter_mut() {
+            self.add_default_attributes(child, options)
+                .map_err(|err| err.inside(qname.clone()))?;
+        }
+        Ok(())
+    }
+
+    ///Adds the defaulted attributes declared for elements named `qname` that `attributes` lacks
+    pub(crate) fn add_defaults(&self, qname: &str, attributes: &mut Attributes, location: Option<Location>, options: &ParseOptions) -> Result<(), ParseError> {
+        for decl in self.attributes.iter().filter(|a| a.element == qname) {
+            let value = match decl.default {
+                AttributeDefault::Fixed(ref value) | AttributeDefault::Value(ref value) => value,
+                _ => continue,
+            };
+            if !attributes.contains(&decl.name) {
+                let value = self
+                    .expand(value, &mut Expansion::new(options, self))
+                    .map_err(|kind| ParseError::new(kind, location).inside(qname.to_string()))?;
+                attributes.push(Attribute::new(&decl.name, value));
+            }
+        }
+        Ok(())
+    }
+}
+
+///The error for using an entity whose replacement text holds markup
+pub(crate) fn markup_entity(name: &str) -> ParseErrorKind {
+    ParseErrorKind::Syntax(format!("Entity &{}; contains markup, which is not supported", name))
+}
+
+impl fmt::Display for Doctype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<!DOCTYPE {}", self.name)?;
+        match (&self.public_id, &self.system_id) {
+            (Some(public), Some(system)) => write!(f, " PUBLIC {} {}", quote(public), quote(system))?,
+            (None, Some(system)) => write!(f, " SYSTEM {}", quote(system))?,
+            _ => {}
+        }
+        if let Some(ref subset) = self.internal_subset {
+            write!(f, " [{}]", subset)?;
+        }
+        write!(f, ">")
+    }
+}
+
+/// Entity expansions done so far while computing replacement texts
+struct Expansion<'o> {
+    options: &'o ParseOptions,
+    count: usize,
+    /// The entities being expanded, to catch recursion
+    open: Vec<String>,
+    /// Whether the last error came from an entity holding markup
+    markup: bool,
+    /// Longest replacement text within `max_entity_amplification` of the DOCTYPE it comes from
+    amplified: usize,
+}
+
+impl<'o> Expansion<'o> {
+    fn new(options: &'o ParseOptions, doctype: &Doctype) -> Expansion<'o> {
+        // the expansions are built whole before the parse sees them, so they are checked
+        // against what declared them rather than against the whole input
+        let declared = doctype.to_string().len();
+        Expansion {
+            options,
+            count: 0,
+            open: Vec::new(),
+            markup: false,
+            amplified: declared.saturating_mul(options.max_entity_amplification),
+        }
+    }
+}
+
+fn quote(literal: &str) -> String {
+    if literal.contains('"') {
+        format!("'{}'", literal)
+    } else {
+        format!("\"{}\"", literal)
+    }
+}
+
+fn predefined(name: &str) -> Option<char> {
+    match name {
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "amp" => Some('&'),
+        "apos" => Some('\''),
+        "quot" => Some('"'),
+        _ => None,
+    }
+}
+
+///Expands the character references in an entity value, leaving entity references alone
+fn char_refs(value: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(index) = rest.find("&#") {
+        out.push_str(&rest[..index]);
+        rest = &rest[index..];
+        let end = rest.find(';').ok_or("Unterminated character reference")?;
+        let code = match rest[2..end].strip_prefix('x') {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => rest[2..end].parse().ok(),
+        };
+        match code.and_then(char::from_u32) {
+            Some(c) if c != '\0' => out.push(c),
+            _ => return Err(format!("Invalid character reference {}", &rest[..=end])),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+///Offset of the `]` closing the internal subset that starts at `start`
+fn subset_end(decl: &str, start: usize) -> Option<usize> {
+    let bytes = decl.as_bytes();
+    let mut i = start;
+    let mut quote = None;
+    while i < bytes.len() {
+        let b = bytes[i];
+        match quote {
+            Some(q) if q == b => quote = None,
+            Some(_) => {}
+            None if decl[i..].starts_with("<!--") => {
+                i += decl[i..].find("-->")? + 3;
+                continue;
+            }
+            None if b == b'"' || b == b'\'' => quote = Some(b),
+            None if b == b']' => return Some(i),
+            None => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Reads the prolog (declaration, comments, processing instructions and DOCTYPE) from
+/// `read`, returning the bytes read, which may run past the prolog, and where the
+/// DOCTYPE is among them
+pub(crate) fn read_prolog<R: Read>(read: &mut R) -> io::Result<(Vec<u8>, Option<Range<usize>>)> {
+    let mut buf = Vec::new();
+    let mut pos = 0;
+    let mut eof = false;
+    loop {
+        while pos < buf.len() && matches!(buf[pos], b' ' | b'\t' | b'\r' | b'\n') {
+            pos += 1;
+        }
+        if buf.len() - pos < 9 && !eof {
+            eof = !fill(read, &mut buf)?;
+            continue;
+        }
+        let rest = &buf[pos..];
+        let found = if rest.starts_with(b"<!DOCTYPE") {
+            doctype_len(rest).map(|len| (len, true))
+        } else if rest.starts_with(b"<?") {
+            find(&rest[2..], b"?>").map(|i| (i + 4, false))
+        } else if rest.starts_with(b"<!--") {
+            find(&rest[4..], b"-->").map(|i| (i + 7, false))
+        } else {
+            return Ok((buf, None));
+        };
+        match found {
+            Some((len, true)) => return Ok((buf, Some(pos..pos + len))),
+            Some((len, false)) => pos += len,
+            // leave the error to the parser
+            None if eof => return Ok((buf, None)),
+            None => eof = !fill(read, &mut buf)?,
+        }
+    }
+}
+
+fn fill<R: Read>(read: &mut R, buf: &mut Vec<u8>) -> io::Result<bool> {
+    let len = buf.len();
+    buf.resize(len + 8192, 0);
+    let read = read.read(&mut buf[len..])?;
+    buf.truncate(len + read);
+    Ok(read > 0)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+///Length of the DOCTYPE at the start of `bytes`, if it is complete
+fn doctype_len(bytes: &[u8]) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        match quote {
+            Some(q) if q == b => quote = None,
+            Some(_) => {}
+            None if depth > 0 && bytes[i..].starts_with(b"<!--") => {
+                i += find(&bytes[i..], b"-->")? + 3;
+                continue;
+            }
+            None if b == b'"' || b == b'\'' => quote = Some(b),
+            None if b == b'[' => depth += 1,
+            None if b == b']' => depth -= 1,
+            None if b == b'>' && depth == 0 => return Some(i + 1),
+            None => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Reads the prolog from `read` and parses its DOCTYPE, giving `config` the entities it
+/// declares. Returns the bytes read, with the DOCTYPE blanked out, for the parser to read
+/// ahead of the rest of `read`
+pub(crate) fn read_doctype<R: Read>(read: &mut R, config: &mut ParserConfig, options: &ParseOptions) -> Result<(Vec<u8>, Option<Doctype>), XmlErrors> {
+    // the DOCTYPE is read here, xml-rs only gets the entities it declares
+    let (mut prolog, range) = read_prolog(read).map_err(|err| match err.kind() {
+        io::ErrorKind::InvalidData => ParseError::new(ParseErrorKind::Encoding, None).into(),
+        _ => XmlErrors::from(err),
+    })?;
+    let range = match range {
+        Some(range) => range,
+        None => return Ok((prolog, None)),
+    };
+    let location = location_after(&prolog[..range.start]);
+    let error = |kind| ParseError::new(kind, Some(location));
+    let decl = String::from_utf8_lossy(&prolog[range.clone()]).into_owned();
+    let doctype = Doctype::parse(&decl).map_err(|msg| error(ParseErrorKind::Syntax(msg)))?;
+    config.extra_entities = doctype.entity_values(options).map_err(error)?;
+    blank(&mut prolog[range]);
+    Ok((prolog, Some(doctype)))
+}
+
+///Tells the use of an external entity that was not resolved, or of one holding markup,
+///apart from an undeclared one
+pub(crate) fn unresolved_entity(err: XmlErrors, doctype: Option<&Doctype>) -> XmlErrors {
+    match (err, doctype) {
+        (XmlErrors::ParseError { mut source }, Some(doctype)) => {
+            if let ParseErrorKind::Syntax(ref msg) = source.kind {
+                if let Some(name) = msg.strip_prefix("Unexpected entity: ") {
+                    match doctype.entities.iter().find(|e| !e.parameter && e.name == name) {
+                        Some(entity) if entity.value.is_none() => {
+                            source.kind = ParseErrorKind::ExternalEntity(name.to_string());
+                        }
+                        // declared and internal, so left out for its markup
+                        Some(_) => source.kind = markup_entity(name),
+                        None => {}
+                    }
+                }
+            }
+            XmlErrors::ParseError { source }
+        }
+        (err, _) => err,
+    }
+}
+
+///Overwrites `bytes` with spaces, keeping line breaks so positions after it stay right
+pub(crate) fn blank(bytes: &mut [u8]) {
+    for b in bytes {
+        if *b != b'\n' {
+            *b = b' ';
+        }
+    }
+}
+
+/// Small scanner over a declaration
+struct Cursor<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(s: &'a str) -> Cursor<'a> {
+        Cursor { s, pos: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn eat(&mut self, literal: &str) -> bool {
+        if self.rest().starts_with(literal) {
+            self.pos += literal.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), String> {
+        if self.eat(literal) {
+            Ok(())
+        } else {
+            Err(format!("Expected '{}' in DOCTYPE", literal))
+        }
+    }
+
+    fn skip_space(&mut self) -> bool {
+        let rest = self.rest();
+        let trimmed = rest.trim_start();
+        self.pos += rest.len() - trimmed.len();
+        trimmed.len() != rest.len()
+    }
+
+    fn space(&mut self) -> Result<(), String> {
+        if self.skip_space() {
+            Ok(())
+        } else {
+            Err("Expected whitespace in DOCTYPE".to_string())
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, String> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | ':' | '-' | '.')))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err("Expected a name in DOCTYPE".to_string());
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    fn quoted(&mut self) -> Result<&'a str, String> {
+        let quote = match self.rest().chars().next() {
+            Some(q @ ('"' | '\'')) => q,
+            _ => return Err("Expected a quoted literal in DOCTYPE".to_string()),
+        };
+        self.pos += 1;
+        let rest = self.rest();
+        let end = rest.find(quote).ok_or("Unterminated literal in DOCTYPE")?;
+        self.pos += end + 1;
+        Ok(&rest[..end])
+    }
+
+    fn until(&mut self, end: &str) -> Result<&'a str, String> {
+        let rest = self.rest();
+        let index = rest.find(end).ok_or_else(|| format!("Expected '{}' in DOCTYPE", end))?;
+        self.pos += index + end.len();
+        Ok(&rest[..index])
+    }
+
+    ///Reads a whole `<!...>` markup declaration, skipping over literals
+    fn declaration(&mut self) -> Result<&'a str, String> {
+        let rest = self.rest();
+        let mut quote = None;
+        for (i, c) in rest.char_indices() {
+            match quote {
+                Some(q) if q == c => quote = None,
+                Some(_) => {}
+                None if c == '"' || c == '\'' => quote = Some(c),
+                None if c == '>' => {
+                    self.pos += i + 1;
+                    return Ok(&rest[..=i]);
+                }
+                None => {}
+            }
+        }
+        Err("Unterminated markup declaration in DOCTYPE".to_string())
+    }
+}
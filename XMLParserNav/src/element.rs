@@ -1,26 +1,115 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
 use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::io::{Read, Write};
+use std::io::Read;
 use std::iter::Filter;
 use std::slice::Iter;
-use crate::errors::XmlErrors;
+use std::str::FromStr;
+use xml::common::Position;
+use xml::name::OwnedName;
+use xml::namespace::{Namespace, NS_EMPTY_URI, NS_NO_PREFIX, NS_XMLNS_PREFIX, NS_XML_PREFIX};
+use xml::reader::EventReader;
+use crate::attribute::{Attribute, Attributes};
+use crate::builder::ElementBuilder;
+use crate::errors::{qualified, ParseError, ParseErrorKind, XmlErrors};
+use crate::options::Budget;
+use crate::serialize::{self, WriteOptions};
+use crate::position::{attribute_offsets, next_event, Location, SourceReader, Span};
+use crate::translator::{evaluate, evaluate_iter, tokenize, ReturnEnum, TreeMatches};
 use crate::tree::ElementTree;
 
 /// An XML element
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Eq)]
 pub struct Element {
     /// The namespace of the element
     pub namespace: Option<String>,
     /// The tag name of the element
     pub tag: String,
-    /// The attributes of the element
-    pub attributes: HashMap<String, String>,
+    /// The attributes of the element, in document order
+    pub attributes: Attributes,
     /// The child elements of the element
     pub children: Vec<Element>,
     /// The text content of the element
     pub text: Option<String>,
+    /// The content of the element in document order
+    pub nodes: Vec<Node>,
+    /// Where the element was found in the source, if it was parsed
+    pub span: Option<Span>,
+}
+
+/// A piece of an element's content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    /// A child element, by its index in `children`
+    Element(usize),
+    /// A run of character data
+    Text(Text),
+    /// A comment
+    Comment(Comment),
+    /// A processing instruction
+    ProcessingInstruction(ProcessingInstruction),
+}
+
+/// Character data inside an element
+#[derive(Debug, Clone, Eq)]
+pub struct Text {
+    /// The text, with entities resolved
+    pub value: String,
+    /// Where the text was found in the source, if it was parsed
+    pub span: Option<Span>,
+}
+
+impl PartialEq for Text {
+    // source positions do not take part in comparisons
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+/// A comment inside an element, or before or after the root element
+#[derive(Debug, Clone, Eq)]
+pub struct Comment {
+    /// The text between `<!--` and `-->`
+    pub value: String,
+    /// Where the comment was found in the source, if it was parsed
+    pub span: Option<Span>,
+}
+
+impl PartialEq for Comment {
+    // source positions do not take part in comparisons
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+/// A processing instruction inside an element, or before or after the root element
+#[derive(Debug, Clone, Eq)]
+pub struct ProcessingInstruction {
+    /// The target, the name following `<?`
+    pub target: String,
+    /// What follows the target, if anything
+    pub data: Option<String>,
+    /// Where the processing instruction was found in the source, if it was parsed
+    pub span: Option<Span>,
+}
+
+impl PartialEq for ProcessingInstruction {
+    // source positions do not take part in comparisons
+    fn eq(&self, other: &Self) -> bool {
+        self.target == other.target && self.data == other.data
+    }
+}
+
+impl PartialEq for Element {
+    // source positions do not take part in comparisons
+    fn eq(&self, other: &Self) -> bool {
+        self.namespace == other.namespace
+            && self.tag == other.tag
+            && self.attributes == other.attributes
+            && self.children == other.children
+            && self.text == other.text
+            && self.nodes == other.nodes
+    }
 }
 
 impl Hash for Element {
@@ -35,121 +124,304 @@ impl Default for Element {
         Element {
             namespace: None,
             tag: "tag".to_owned(),
-            attributes: HashMap::new(),
+            attributes: Attributes::new(),
             children: vec![],
             text: None,
+            nodes: vec![],
+            span: None,
         }
     }
 }
 
 impl Element {
 
- /*   ///Creates a new element by taking in, it's name
+    ///Creates a new element by taking in its name, which may be prefixed (`h:price`)
     pub fn new<S>(name: S) -> Element
-        where
-            S: ToString,
+    where
+        S: ToString,
     {
+        let name = name.to_string();
+        let (namespace, tag) = match name.split_once(':') {
+            Some((prefix, local)) => (Some(prefix.to_string()), local.to_string()),
+            None => (None, name),
+        };
         Element {
-            tag: name.to_string(),
+            namespace,
+            tag,
             // call implemented default within new.
             ..Element::default()
         }
-    }*/
+    }
 
-    /// Parse the contents of an element
-    pub(crate) fn parse<R: Read>(
-        &mut self,
-        mut xml_reader: &mut xml::reader::EventReader<R>,
-    ) -> Result<(), XmlErrors> {
+    ///Returns the qualified name, `prefix:local` or just `local`
+    pub fn qname(&self) -> String {
+        match self.namespace {
+            Some(ref prefix) => format!("{}:{}", prefix, self.tag),
+            None => self.tag.clone(),
+        }
+    }
+
+    ///Starts an `ElementBuilder` for an element named `name`
+    pub fn builder<S>(name: S) -> ElementBuilder
+    where
+        S: ToString,
+    {
+        ElementBuilder::new(name)
+    }
+
+    /// Create the element for a `StartElement` event found at `start`, then parse its contents.
+    ///
+    /// Open elements are kept on a stack rather than the call stack, so deep nesting
+    /// is bounded by `max_depth` and not by the thread's stack size.
+    /// The bindings in `scope`, which xml-rs reports on the start tag, that are not inherited
+    /// from the document around the element are declared on it.
+    pub(crate) fn parse_started<R: Read>(
+        namespace: Option<String>,
+        name: OwnedName,
+        attributes: Attributes,
+        scope: Namespace,
+        start: Location,
+        xml_reader: &mut EventReader<SourceReader<R>>,
+        budget: &mut Budget,
+    ) -> Result<Element, XmlErrors> {
+        let mut open: Vec<(String, Element, Namespace)> = Vec::new();
+        let result = Element::parse_open(namespace, name, attributes, scope, start, xml_reader, budget, &mut open);
+        for _ in 0..open.len() {
+            budget.leave();
+        }
+        match result {
+            Err(XmlErrors::ParseError { source }) => {
+                Err(open.into_iter().rev().fold(*source, |err, (qname, _, _)| err.inside(qname)).into())
+            }
+            result => result,
+        }
+    }
+
+    /// Parse elements until the first one on `open` is closed, leaving the unclosed ones on error
+    #[allow(clippy::too_many_arguments)]
+    fn parse_open<R: Read>(
+        namespace: Option<String>,
+        name: OwnedName,
+        attributes: Attributes,
+        scope: Namespace,
+        start: Location,
+        xml_reader: &mut EventReader<SourceReader<R>>,
+        budget: &mut Budget,
+        open: &mut Vec<(String, Element, Namespace)>,
+    ) -> Result<Element, XmlErrors> {
         use xml::reader::XmlEvent;
 
+        Element::open(namespace, name, attributes, scope, start, xml_reader, budget, open)?;
+        let mut open_text: Option<usize> = None;
         loop {
-            let xml_event_type = xml_reader.next()?;
+            let xml_event_type = next_event(xml_reader)?;
+            let start = xml_reader.source().locate(xml_reader.position());
+            xml_reader.source_mut().discard_before(start);
+            let (_, current, _) = open.last_mut().unwrap();
+            if let Some(index) = open_text.take() {
+                if let Node::Text(Text { span: Some(ref mut span), .. }) = current.nodes[index] {
+                    span.end = start;
+                }
+            }
+
             match xml_event_type {
                 XmlEvent::StartElement {
                     name,
                     attributes,
-                    ..
+                    namespace: scope,
                 } => {
-                    let mut attr_map = HashMap::new();
-                    for attr in attributes {
-                        let attr_name = match attr.name.prefix {
-                            Some(prefix) => format!("{}:{}", prefix, attr.name.local_name),
-                            None => attr.name.local_name,
-                        };
-                        attr_map.insert(attr_name, attr.value);
-                    }
-
-                    let mut child = Element {
-                        namespace: name.prefix,
-                        tag: name.local_name,
-                        attributes: attr_map,
-                        ..Element::default()
-                    };
-                    child.parse(&mut xml_reader)?;
-                    self.children.push(child);
+                    let namespace = name.prefix.clone();
+                    let attributes = Attributes::from(attributes);
+                    Element::open(namespace, name, attributes, scope, start, xml_reader, budget, open)?;
                 }
                 XmlEvent::EndElement { name } => {
-                    if name.prefix == self.namespace && name.local_name == self.tag {
-                        return Ok(());
+                    if name.prefix == current.namespace && name.local_name == current.tag {
+                        if let Some(ref mut span) = current.span {
+                            span.end = xml_reader.source().current();
+                        }
+                        let (_, element, _) = open.pop().unwrap();
+                        budget.leave();
+                        match open.last_mut() {
+                            Some((_, parent, _)) => {
+                                parent.nodes.push(Node::Element(parent.children.len()));
+                                parent.children.push(element);
+                            }
+                            None => return Ok(element),
+                        }
                     } else {
-                        panic!("Unexpected closing tag: {}, expected {}", name, self.tag);
+                        let kind = ParseErrorKind::MismatchedTag {
+                            expected: current.qname(),
+                            found: qualified(name.prefix.as_deref(), &name.local_name),
+                        };
+                        return Err(ParseError::new(kind, Some(start)).into());
                     }
                 }
-                XmlEvent::Characters(s) => {
-                    let text = match &self.text {
-                        Some(v) => v.clone(),
-                        None => String::new(),
-                    };
-                    self.text = Some(text + &s);
+                XmlEvent::Whitespace(_) if !budget.options.preserve_whitespace => {}
+                XmlEvent::Characters(s) | XmlEvent::CData(s) | XmlEvent::Whitespace(s) => {
+                    budget.text(&s, start, xml_reader.source().current().offset)?;
+                    match current.text {
+                        Some(ref mut text) => text.push_str(&s),
+                        None => current.text = Some(s.clone()),
+                    }
+                    // adjacent runs, such as text around a CDATA section, are joined
+                    match current.nodes.last_mut() {
+                        Some(Node::Text(last)) => last.value.push_str(&s),
+                        _ => current.nodes.push(Node::Text(Text {
+                            value: s,
+                            span: Some(Span { start, end: start }),
+                        })),
+                    }
+                    open_text = Some(current.nodes.len() - 1);
+                }
+                XmlEvent::Comment(value) => {
+                    let span = Some(Span { start, end: xml_reader.source().current() });
+                    current.nodes.push(Node::Comment(Comment { value, span }));
                 }
-                XmlEvent::StartDocument { .. }
-                | XmlEvent::EndDocument
-                | XmlEvent::ProcessingInstruction { .. }
-                | XmlEvent::Whitespace(_)
-                | XmlEvent::Comment(_) => {}
-                XmlEvent::CData(_) => {}
+                XmlEvent::ProcessingInstruction { name, data } => {
+                    let span = Some(Span { start, end: xml_reader.source().current() });
+                    current.nodes.push(Node::ProcessingInstruction(ProcessingInstruction {
+                        target: name,
+                        data,
+                        span,
+                    }));
+                }
+                XmlEvent::StartDocument { .. } | XmlEvent::EndDocument => {}
             }
         }
     }
 
-    /// Write an element and its contents to `writer`
-    pub(crate) fn write<W: Write>(
-        &self,
-        writer: &mut xml::writer::EventWriter<W>,
+    /// Check a start tag against the budget and push its element onto `open`
+    #[allow(clippy::too_many_arguments)]
+    fn open<R: Read>(
+        namespace: Option<String>,
+        name: OwnedName,
+        attributes: Attributes,
+        scope: Namespace,
+        start: Location,
+        xml_reader: &mut EventReader<SourceReader<R>>,
+        budget: &mut Budget,
+        open: &mut Vec<(String, Element, Namespace)>,
     ) -> Result<(), XmlErrors> {
-        use xml::attribute::Attribute;
-        use xml::name::Name;
-        use xml::namespace::Namespace;
-        use xml::writer::XmlEvent;
-
-        let name = Name::local(&self.tag);
-        let mut attributes = Vec::with_capacity(self.attributes.len());
-        for (k, v) in &self.attributes {
-            attributes.push(Attribute {
-                name: Name::local(k),
-                value: v,
+        // xml-rs may place the start of the root past its start tag
+        let start = xml_reader.source().tag_start(start);
+        let qname = match name.prefix {
+            Some(ref prefix) => format!("{}:{}", prefix, name.local_name),
+            None => name.local_name.clone(),
+        };
+        let mut element = Element {
+            namespace,
+            tag: name.local_name,
+            attributes,
+            span: Some(Span { start, end: start }),
+            ..Element::default()
+        };
+        let checked = budget.enter(&qname, element.attributes.len(), start);
+        let mut declared = declarations(&scope, open.last().map(|(_, _, parent)| parent));
+        open.push((qname, Element::default(), scope));
+        checked?;
+
+        let source = xml_reader.source();
+        let read = source.current().offset;
+        // xml-rs keeps namespace declarations apart, they are put back in source order
+        let tag = source.bytes_from(start.offset);
+        let scope = &open.last().unwrap().2;
+        let mut ordered = Attributes::new();
+        for (qname, from, to) in attribute_offsets(tag, start.offset) {
+            let span = Some(Span {
+                start: source.locate_offset(from),
+                end: source.locate_offset(to),
             });
+            let prefix = match qname.strip_prefix("xmlns") {
+                Some("") => Some(NS_NO_PREFIX),
+                Some(rest) => rest.strip_prefix(':'),
+                None => None,
+            };
+            if let Some(prefix) = prefix {
+                // declarations repeating an inherited binding are kept as written too
+                declared.retain(|(name, _)| *name != qname);
+                let uri = scope.get(prefix).unwrap_or(NS_EMPTY_URI);
+                ordered.push(Attribute { span, ..Attribute::new(qname, uri) });
+            } else if let Some(mut attr) = element.attributes.remove(&qname) {
+                attr.span = span;
+                ordered.push(attr);
+            }
+        }
+        for (qname, uri) in declared {
+            ordered.push(Attribute::new(qname, uri));
+        }
+        for attr in &element.attributes {
+            ordered.push(attr.clone());
+        }
+        element.attributes = ordered;
+        for attr in &element.attributes {
+            let location = attr.span.map_or(start, |span| span.start);
+            budget.attribute(&attr.qname(), &attr.value, location, read)?;
         }
+        open.last_mut().unwrap().1 = element;
+        Ok(())
+    }
 
-        let namespace = Namespace::empty();
+    /// Move the spans of the element and its subtree, parsed from a part of the input starting at `base`
+    pub(crate) fn shift_spans(&mut self, base: Location) {
+        let shift = |span: &mut Option<Span>| {
+            if let Some(span) = span {
+                span.start = span.start.from_base(base);
+                span.end = span.end.from_base(base);
+            }
+        };
+        let mut stack = vec![self];
+        while let Some(element) = stack.pop() {
+            shift(&mut element.span);
+            for attr in element.attributes.iter_mut() {
+                shift(&mut attr.span);
+            }
+            for node in &mut element.nodes {
+                match node {
+                    Node::Element(_) => {}
+                    Node::Text(text) => shift(&mut text.span),
+                    Node::Comment(comment) => shift(&mut comment.span),
+                    Node::ProcessingInstruction(pi) => shift(&mut pi.span),
+                }
+            }
+            stack.extend(element.children.iter_mut());
+        }
+    }
 
-        writer.write(XmlEvent::StartElement {
-            name,
-            attributes: Cow::Owned(attributes),
-            namespace: Cow::Owned(namespace),
-        })?;
+    ///Writes the element and its contents as `options` lay them out. Namespaces declared
+    ///by ancestors are not in scope, so a prefix only they bind is an error
+    pub fn to_string_with(&self, options: &WriteOptions) -> Result<String, XmlErrors> {
+        serialize::element(self, options)
+    }
 
-        if let Some(ref text) = self.text {
-            writer.write(XmlEvent::Characters(&text[..]))?;
-        }
-        for e in &self.children {
-            e.write(writer)?;
+    /// Whether `nodes` still agrees with `text` and `children`, which may have been changed on their own
+    pub(crate) fn nodes_match_content(&self) -> bool {
+        let mut texts = String::new();
+        let mut elements = 0;
+        for node in &self.nodes {
+            match *node {
+                Node::Element(index) if index == elements => elements += 1,
+                Node::Element(_) => return false,
+                Node::Text(ref text) => texts.push_str(&text.value),
+                Node::Comment(_) | Node::ProcessingInstruction(_) => {}
+            }
         }
+        !self.nodes.is_empty() && elements == self.children.len() && texts == self.text.as_deref().unwrap_or("")
+    }
+
+    /// Iterate over this element and all its descendants in document order
+    pub fn iter(&self) -> Descendants<'_> {
+        Descendants { stack: vec![self] }
+    }
 
-        writer.write(XmlEvent::EndElement { name: Some(name) })?;
+    /// Run the XPath `xpath` against the tree under this element
+    pub fn query(&self, xpath: &str) -> Result<Vec<ReturnEnum<'_>>, XmlErrors> {
+        evaluate(self, &tokenize(xpath)?)
+    }
 
-        Ok(())
+    /// Run the XPath `xpath` against the tree under this element like `query`, looking for the
+    /// matches of its last step only as they are asked for
+    pub fn query_iter(&self, xpath: &str) -> Result<TreeMatches<'_>, XmlErrors> {
+        evaluate_iter(self, &tokenize(xpath)?)
     }
 
     /// Find a single child of the current `Element`, given a predicate
@@ -169,15 +441,60 @@ impl Element {
     }
 }
 
+/// Iterator over an element and its descendants in document order, see `Element::iter`
+pub struct Descendants<'a> {
+    stack: Vec<&'a Element>,
+}
+
+impl<'a> Descendants<'a> {
+    pub(crate) fn empty() -> Descendants<'a> {
+        Descendants { stack: Vec::new() }
+    }
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = &'a Element;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let element = self.stack.pop()?;
+        self.stack.extend(element.children.iter().rev());
+        Some(element)
+    }
+}
+
+///The namespace declarations of an element whose bindings are `scope`, inside an element
+///whose bindings are `parent`, or at the top of the document without one
+fn declarations(scope: &Namespace, parent: Option<&Namespace>) -> Vec<(String, String)> {
+    let inherited = |prefix: &str| match parent {
+        Some(parent) => parent.get(prefix),
+        None if prefix == NS_NO_PREFIX => Some(NS_EMPTY_URI),
+        None => None,
+    };
+    scope
+        .into_iter()
+        .filter(|&(prefix, _)| prefix != NS_XML_PREFIX && prefix != NS_XMLNS_PREFIX)
+        .filter(|&(prefix, uri)| inherited(prefix) != Some(uri))
+        .map(|(prefix, uri)| match prefix {
+            NS_NO_PREFIX => (NS_XMLNS_PREFIX.to_string(), uri.to_string()),
+            prefix => (format!("{}:{}", NS_XMLNS_PREFIX, prefix), uri.to_string()),
+        })
+        .collect()
+}
+
+impl FromStr for Element {
+    type Err = XmlErrors;
+
+    fn from_str(doc: &str) -> Result<Self, Self::Err> {
+        ElementTree::parse_str(doc)?
+            .root
+            .ok_or(XmlErrors::ElementNotFound { t: "root".to_string() })
+    }
+}
+
+/// Writes the element as `WriteOptions::pretty` lays it out, failing with `fmt::Error`
+/// where `to_string_with` would return an error
 impl fmt::Display for Element {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let doc = ElementTree {
-            root: Some(self.clone()),
-            ..ElementTree::default()
-        };
-        let mut v = Vec::<u8>::new();
-        doc.write_with(&mut v, false, "  ", true)?;
-        let s = String::from_utf8(v).unwrap();
-        f.write_str(&s[..])
+        f.write_str(&self.to_string_with(&WriteOptions::pretty()).map_err(|_| fmt::Error)?)
     }
 }
@@ -0,0 +1,218 @@
+use std::io::{self, Read, Write};
+use encoding_rs::{DecoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use xml::writer::Error as EmitterError;
+use crate::errors::XmlErrors;
+
+/// How many bytes are looked at to find the encoding declaration
+const SNIFF_LEN: usize = 1024;
+
+/// Reader turning a document in any supported encoding into UTF-8.
+///
+/// The encoding comes from a byte order mark, the `<?xml` of a UTF-16 document without one,
+/// or the `encoding` of the XML declaration, in that order, and is UTF-8 otherwise.
+/// Byte offsets of parsed nodes refer to the UTF-8 text.
+pub struct Transcoder<R> {
+    inner: R,
+    state: State,
+    /// Undecoded input, from `start`
+    input: Vec<u8>,
+    start: usize,
+    /// Decoded output, from `out_pos`
+    output: Vec<u8>,
+    out_pos: usize,
+    eof: bool,
+}
+
+enum State {
+    Sniffing,
+    Utf8,
+    Decoding(encoding_rs::Decoder),
+    Done(&'static Encoding),
+}
+
+impl<R: Read> Transcoder<R> {
+    ///Wraps `inner`; the encoding is found on the first read
+    pub fn new(inner: R) -> Transcoder<R> {
+        Transcoder {
+            inner,
+            state: State::Sniffing,
+            input: Vec::new(),
+            start: 0,
+            output: Vec::new(),
+            out_pos: 0,
+            eof: false,
+        }
+    }
+
+    ///The encoding of the input, once something has been read
+    pub fn encoding(&self) -> Option<&'static Encoding> {
+        match self.state {
+            State::Sniffing => None,
+            State::Utf8 => Some(UTF_8),
+            State::Decoding(ref decoder) => Some(decoder.encoding()),
+            State::Done(encoding) => Some(encoding),
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        let len = self.input.len();
+        self.input.resize(len + 8192, 0);
+        let read = self.inner.read(&mut self.input[len..])?;
+        self.input.truncate(len + read);
+        self.eof = read == 0;
+        Ok(())
+    }
+
+    fn sniff(&mut self) -> io::Result<()> {
+        while !self.eof && self.input.len() < SNIFF_LEN && !self.input.contains(&b'>') {
+            self.fill()?;
+        }
+        let (encoding, skip) = match Encoding::for_bom(&self.input) {
+            Some((encoding, bom)) => (encoding, bom),
+            None if self.input.starts_with(&[0x3c, 0, 0x3f, 0]) => (UTF_16LE, 0),
+            None if self.input.starts_with(&[0, 0x3c, 0, 0x3f]) => (UTF_16BE, 0),
+            None => match declared_encoding(&self.input) {
+                Some(label) => match Encoding::for_label(label.as_bytes()) {
+                    // a UTF-16 label on ASCII-compatible bytes is wrong, the bytes win
+                    Some(encoding) if encoding == UTF_16LE || encoding == UTF_16BE => (UTF_8, 0),
+                    Some(encoding) => (encoding, 0),
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("unsupported encoding '{}'", label),
+                        ))
+                    }
+                },
+                None => (UTF_8, 0),
+            },
+        };
+        self.start = skip;
+        self.state = if encoding == UTF_8 {
+            State::Utf8
+        } else {
+            State::Decoding(encoding.new_decoder_without_bom_handling())
+        };
+        Ok(())
+    }
+
+    fn decode(&mut self) -> io::Result<()> {
+        let decoder = match self.state {
+            State::Decoding(ref mut decoder) => decoder,
+            _ => return Ok(()),
+        };
+        self.output.clear();
+        self.out_pos = 0;
+        let capacity = decoder
+            .max_utf8_buffer_length_without_replacement(self.input.len() - self.start)
+            .unwrap_or(8192)
+            .max(16);
+        self.output.resize(capacity, 0);
+        let (result, read, written) = decoder.decode_to_utf8_without_replacement(
+            &self.input[self.start..],
+            &mut self.output,
+            self.eof,
+        );
+        self.output.truncate(written);
+        self.start += read;
+        match result {
+            DecoderResult::Malformed(..) => {
+                let msg = format!("malformed {} input", decoder.encoding().name());
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+            DecoderResult::InputEmpty if self.eof => {
+                let encoding = decoder.encoding();
+                self.state = State::Done(encoding);
+            }
+            _ => {}
+        }
+        self.input.drain(..self.start);
+        self.start = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Transcoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if let State::Sniffing = self.state {
+            self.sniff()?;
+        }
+        loop {
+            if self.out_pos < self.output.len() {
+                let len = out.len().min(self.output.len() - self.out_pos);
+                out[..len].copy_from_slice(&self.output[self.out_pos..self.out_pos + len]);
+                self.out_pos += len;
+                return Ok(len);
+            }
+            match self.state {
+                State::Utf8 => {
+                    if self.start < self.input.len() {
+                        let len = out.len().min(self.input.len() - self.start);
+                        out[..len].copy_from_slice(&self.input[self.start..self.start + len]);
+                        self.start += len;
+                        return Ok(len);
+                    }
+                    return self.inner.read(out);
+                }
+                State::Decoding(_) => {
+                    if self.start == self.input.len() && !self.eof {
+                        self.fill()?;
+                    }
+                    self.decode()?;
+                }
+                State::Sniffing | State::Done(_) => return Ok(0),
+            }
+        }
+    }
+}
+
+///The `encoding` pseudo-attribute of an XML declaration at the start of `bytes`
+fn declared_encoding(bytes: &[u8]) -> Option<String> {
+    let rest = bytes.strip_prefix(b"<?xml")?;
+    let end = rest.windows(2).position(|w| w == b"?>").unwrap_or(rest.len());
+    let decl = std::str::from_utf8(&rest[..end]).ok()?;
+    let after = &decl[decl.find("encoding")? + "encoding".len()..];
+    let after = after.trim_start().strip_prefix('=')?.trim_start();
+    let quote = after.chars().next().filter(|&c| c == '"' || c == '\'')?;
+    let value = &after[1..];
+    Some(value[..value.find(quote)?].to_string())
+}
+
+///Looks up the encoding for `label`, as accepted in an XML declaration
+pub fn for_label(label: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+}
+
+///Whether `encoding` has the character `c`
+pub(crate) fn can_encode(encoding: &'static Encoding, c: char) -> bool {
+    if c.is_ascii() || encoding == UTF_8 || encoding == UTF_16LE || encoding == UTF_16BE {
+        return true;
+    }
+    let (_, _, unmappable) = encoding.encode(c.encode_utf8(&mut [0; 4]));
+    !unmappable
+}
+
+///Writes the UTF-8 text `utf8` to `t` in `encoding`, with a byte order mark for UTF-16.
+///Characters the encoding lacks must already be references, which only text and attribute
+///values can hold; any left over, in a name, comment, CDATA section or processing
+///instruction, are a write error and nothing is written. Failures of `t` are io errors.
+pub(crate) fn write_encoded<T: Write>(t: &mut T, utf8: &str, encoding: &'static Encoding) -> Result<(), XmlErrors> {
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let mut bytes = Vec::with_capacity(utf8.len() * 2 + 2);
+        for unit in std::iter::once(0xfeff).chain(utf8.encode_utf16()) {
+            if encoding == UTF_16LE {
+                bytes.extend_from_slice(&unit.to_le_bytes());
+            } else {
+                bytes.extend_from_slice(&unit.to_be_bytes());
+            }
+        }
+        t.write_all(&bytes)?;
+    } else {
+        if let Some(c) = utf8.chars().find(|&c| !can_encode(encoding, c)) {
+            let msg = format!("'{}' cannot be written in {} outside text and attribute values", c, encoding.name());
+            return Err(EmitterError::Io(io::Error::new(io::ErrorKind::InvalidData, msg)).into());
+        }
+        let (bytes, _, _) = encoding.encode(utf8);
+        t.write_all(&bytes)?;
+    }
+    Ok(())
+}
@@ -1,7 +1,21 @@
+use std::error;
 use std::fmt;
 use std::fmt::{Display, Formatter};
+use std::io;
+use xml::reader::ErrorKind;
+use crate::options::Limit;
+use crate::position::Location;
 
-/// Custom error types for XML-related errors
+/// The error type of the crate, covering parsing, querying and writing
+pub type Error = XmlErrors;
+
+/// Result type whose error is the crate-wide `Error`
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Custom error types for XML-related errors.
+///
+/// An error wrapping another shows only its own context, leaving the rest to `source()`;
+/// the alternate form, `{:#}`, follows the chain of sources as well.
 #[derive(Debug)]
 pub enum XmlErrors {
     /// Error indicating that an element was not found
@@ -9,9 +23,51 @@ pub enum XmlErrors {
     /// Error indicating that a value cannot be parsed
     ValueFromStr { t: String },
     /// Error indicating a parse error
-    ParseError { source: xml::reader::Error },
+    ParseError { source: Box<ParseError> },
+    /// Error indicating an invalid query or a query that cannot be answered
+    QueryError { source: QueryError },
+    /// Error indicating a failure to read or write a file or stream
+    IoError { source: io::Error },
     /// Error indicating a write error
     WriteError { source: xml::writer::Error },
+    /// Error indicating that a value does not have the shape needed to convert it to or from a tree
+    ConversionError { t: String },
+    /// Error indicating that what the XPath of a `FromXml` field selected could not be read as its type
+    FieldError { path: String, ty: String, source: Box<XmlErrors> },
+}
+
+/// Broad categories of `XmlErrors`, for callers that only need to know what failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XmlErrorKind {
+    /// The document is not well-formed or could not be read while parsing
+    Parse,
+    /// The XPath query is malformed
+    QuerySyntax,
+    /// The query is valid but its result does not have the requested shape or type
+    Evaluation,
+    /// A file or stream could not be read or written
+    Io,
+    /// The tree could not be serialized
+    Serialization,
+    /// A value could not be converted to or from a tree
+    Conversion,
+}
+
+impl XmlErrors {
+    ///Returns the category of the error
+    pub fn kind(&self) -> XmlErrorKind {
+        match self {
+            XmlErrors::ElementNotFound { .. } | XmlErrors::ValueFromStr { .. } => XmlErrorKind::Evaluation,
+            XmlErrors::ParseError { .. } => XmlErrorKind::Parse,
+            XmlErrors::QueryError { source } => match source {
+                QueryError::NoNamespace | QueryError::MultipleMatches(_) => XmlErrorKind::Evaluation,
+                _ => XmlErrorKind::QuerySyntax,
+            },
+            XmlErrors::IoError { .. } => XmlErrorKind::Io,
+            XmlErrors::WriteError { .. } => XmlErrorKind::Serialization,
+            XmlErrors::ConversionError { .. } | XmlErrors::FieldError { .. } => XmlErrorKind::Conversion,
+        }
+    }
 }
 
 impl fmt::Display for XmlErrors {
@@ -19,15 +75,53 @@ impl fmt::Display for XmlErrors {
         match self {
             XmlErrors::ElementNotFound { t } => write!(f, "Element '{}' not found", t),
             XmlErrors::ValueFromStr { t } => write!(f, "Value '{}' cannot be parsed", t),
-            XmlErrors::ParseError { source } => write!(f, "Parse Error: {}", source),
-            XmlErrors::WriteError { source } => write!(f, "Write Error: {}", source),
+            XmlErrors::ParseError { .. } => write!(f, "Parse Error"),
+            XmlErrors::QueryError { .. } => write!(f, "Query Error"),
+            XmlErrors::IoError { .. } => write!(f, "IO Error"),
+            XmlErrors::WriteError { .. } => write!(f, "Write Error"),
+            XmlErrors::ConversionError { t } => write!(f, "Conversion Error: {}", t),
+            XmlErrors::FieldError { path, ty, .. } => write!(f, "Cannot read '{}' as {}", path, ty),
+        }?;
+        if f.alternate() {
+            let mut source = error::Error::source(self);
+            while let Some(err) = source {
+                write!(f, ": {}", err)?;
+                source = err.source();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for XmlErrors {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            XmlErrors::ElementNotFound { .. } | XmlErrors::ValueFromStr { .. } => None,
+            XmlErrors::ConversionError { .. } => None,
+            XmlErrors::ParseError { source } => Some(source.as_ref()),
+            XmlErrors::QueryError { source } => Some(source),
+            XmlErrors::IoError { source } => Some(source),
+            XmlErrors::WriteError { source } => Some(source),
+            XmlErrors::FieldError { source, .. } => Some(source.as_ref()),
         }
     }
 }
 
-impl From<xml::reader::Error> for XmlErrors {
-    fn from(err: xml::reader::Error) -> Self {
-        XmlErrors::ParseError { source: err }
+impl From<ParseError> for XmlErrors {
+    fn from(err: ParseError) -> Self {
+        XmlErrors::ParseError { source: Box::new(err) }
+    }
+}
+
+impl From<QueryError> for XmlErrors {
+    fn from(err: QueryError) -> Self {
+        XmlErrors::QueryError { source: err }
+    }
+}
+
+impl From<io::Error> for XmlErrors {
+    fn from(err: io::Error) -> Self {
+        XmlErrors::IoError { source: err }
     }
 }
 
@@ -37,15 +131,161 @@ impl From<xml::writer::Error> for XmlErrors {
     }
 }
 
-impl From<XmlErrors> for std::fmt::Error {
-    fn from(_: XmlErrors) -> fmt::Error {
-        fmt::Error {}
+#[cfg(feature = "serde")]
+impl serde::de::Error for XmlErrors {
+    fn custom<T: Display>(msg: T) -> Self {
+        XmlErrors::ConversionError { t: msg.to_string() }
     }
 }
 
-/// Custom error types for XML-related errors
-#[derive(Debug)]
-pub enum Error {
+#[cfg(feature = "serde")]
+impl serde::ser::Error for XmlErrors {
+    fn custom<T: Display>(msg: T) -> Self {
+        XmlErrors::ConversionError { t: msg.to_string() }
+    }
+}
+
+/// The kinds of problems found while parsing a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// A closing tag does not match the element that is open
+    MismatchedTag { expected: String, found: String },
+    /// The input ended before the document was complete
+    UnexpectedEof,
+    /// The input is not valid in its encoding, or the encoding is not supported
+    Encoding,
+    /// The input could not be read
+    Io,
+    /// A limit of the `ParseOptions` was exceeded
+    LimitExceeded(Limit),
+    /// An external entity was referenced while external entities are not resolved
+    ExternalEntity(String),
+    /// Any other well-formedness error, with the parser's message
+    Syntax(String),
+}
+
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::MismatchedTag { expected, found } => {
+                write!(f, "Unexpected closing tag '{}', expected '{}'", found, expected)
+            }
+            ParseErrorKind::UnexpectedEof => write!(f, "Unexpected end of input"),
+            ParseErrorKind::Encoding => write!(f, "Invalid or unsupported character encoding"),
+            ParseErrorKind::Io => write!(f, "Could not read input"),
+            ParseErrorKind::LimitExceeded(limit) => write!(f, "Limit exceeded: {}", limit),
+            ParseErrorKind::ExternalEntity(name) => write!(f, "External entity '&{};' is not resolved", name),
+            ParseErrorKind::Syntax(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+/// Error found while parsing a document
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    /// What went wrong
+    pub kind: ParseErrorKind,
+    /// Where in the input it went wrong
+    pub location: Option<Location>,
+    /// Qualified names of the elements open at that point, outermost first
+    pub open_elements: Vec<String>,
+    /// The underlying xml-rs error, if there is one
+    pub cause: Option<xml::reader::Error>,
+}
+
+impl ParseError {
+    ///Creates an error of `kind` at `location`
+    pub fn new(kind: ParseErrorKind, location: Option<Location>) -> ParseError {
+        ParseError {
+            kind,
+            location,
+            open_elements: Vec::new(),
+            cause: None,
+        }
+    }
+
+    ///Classifies an xml-rs error found at `location`
+    pub(crate) fn from_xml(err: xml::reader::Error, location: Location) -> ParseError {
+        let kind = match err.kind() {
+            ErrorKind::Syntax(msg) => classify_syntax(msg),
+            ErrorKind::UnexpectedEof => ParseErrorKind::UnexpectedEof,
+            ErrorKind::Utf8(_) => ParseErrorKind::Encoding,
+            // raised by the transcoder for undecodable input
+            ErrorKind::Io(io) if io.kind() == io::ErrorKind::InvalidData => ParseErrorKind::Encoding,
+            ErrorKind::Io(_) => ParseErrorKind::Io,
+        };
+        ParseError {
+            cause: Some(err),
+            ..ParseError::new(kind, Some(location))
+        }
+    }
+
+    ///Records that the error happened inside the element `qname`
+    pub(crate) fn inside(mut self, qname: String) -> ParseError {
+        self.open_elements.insert(0, qname);
+        self
+    }
+}
+
+///Classifies an xml-rs syntax error by its message, which is the only place xml-rs
+///reports the names of mismatched tags
+pub(crate) fn classify_syntax(msg: &str) -> ParseErrorKind {
+    let mismatched = msg
+        .strip_prefix("Unexpected closing tag: ")
+        .and_then(|rest| rest.split_once(", expected "));
+    match mismatched {
+        Some((found, expected)) => ParseErrorKind::MismatchedTag {
+            expected: strip_uri(expected),
+            found: strip_uri(found),
+        },
+        None if msg.starts_with("Unexpected end of stream") => ParseErrorKind::UnexpectedEof,
+        None => ParseErrorKind::Syntax(msg.to_string()),
+    }
+}
+
+///Drops the `{uri}` xml-rs puts in front of resolved names, leaving `prefix:local`
+fn strip_uri(name: &str) -> String {
+    match name.find('}') {
+        Some(end) if name.starts_with('{') => name[end + 1..].to_string(),
+        _ => name.to_string(),
+    }
+}
+
+///Joins a prefix and local name the way errors report element names
+pub(crate) fn qualified(prefix: Option<&str>, local: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}:{}", prefix, local),
+        None => local.to_string(),
+    }
+}
+
+impl error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match (&self.kind, &self.cause) {
+            // made from the cause's message, which would only be repeated
+            (ParseErrorKind::Syntax(_) | ParseErrorKind::MismatchedTag { .. } | ParseErrorKind::UnexpectedEof, _) => None,
+            (_, Some(cause)) => Some(cause),
+            (_, None) => None,
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(location) = self.location {
+            write!(f, "{}: ", location)?;
+        }
+        write!(f, "{}", self.kind)?;
+        if !self.open_elements.is_empty() {
+            write!(f, " (inside {})", self.open_elements.join("/"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Errors in the syntax or evaluation of an XPath query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryError {
     /// Error indicating an invalid token
     Token,
     /// Error indicating an incorrect path
@@ -54,15 +294,20 @@ pub enum Error {
     NoNamespace,
     /// Error indicating no matching open bracket
     XPathOpenBracket,
+    /// Error indicating that a query meant to select one node selected this many
+    MultipleMatches(usize),
 }
 
-impl Display for Error {
+impl Display for QueryError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            Error::Token => write!(f, "Invalid Token"),
-            Error::XPath => write!(f, "Incorrect path"),
-            Error::NoNamespace => write!(f, "No Namespace"),
-            Error::XPathOpenBracket => write!(f, "No matching open bracket"),
+            QueryError::Token => write!(f, "Invalid Token"),
+            QueryError::XPath => write!(f, "Incorrect path"),
+            QueryError::NoNamespace => write!(f, "No Namespace"),
+            QueryError::XPathOpenBracket => write!(f, "No matching open bracket"),
+            QueryError::MultipleMatches(count) => write!(f, "Expected one match, found {}", count),
         }
     }
 }
+
+impl error::Error for QueryError {}
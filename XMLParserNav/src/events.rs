@@ -0,0 +1,283 @@
+use std::io::{Chain, Cursor, Read};
+use std::ops::ControlFlow;
+use xml::common::Position;
+use xml::name::OwnedName;
+use xml::reader::{EventReader, ParserConfig, XmlEvent};
+use crate::attribute::Attributes;
+use crate::dtd::{self, Doctype};
+use crate::encoding::Transcoder;
+use crate::errors::XmlErrors;
+use crate::options::{Budget, ParseOptions, DEFAULT_OPTIONS};
+use crate::position::{next_event, Location, SourceReader, Span};
+
+/// The name of an element, with its prefix resolved to a namespace URI
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Name {
+    /// The prefix of the name
+    pub prefix: Option<String>,
+    /// The local name
+    pub local: String,
+    /// The namespace URI the name is in
+    pub namespace: Option<String>,
+}
+
+impl Name {
+    ///Returns the qualified name, `prefix:local` or just `local`
+    pub fn qname(&self) -> String {
+        match self.prefix {
+            Some(ref prefix) => format!("{}:{}", prefix, self.local),
+            None => self.local.clone(),
+        }
+    }
+}
+
+impl From<OwnedName> for Name {
+    fn from(name: OwnedName) -> Self {
+        Name {
+            prefix: name.prefix,
+            local: name.local_name,
+            namespace: name.namespace,
+        }
+    }
+}
+
+/// A piece of a document, as read by `Events`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A start tag, `span` covering the tag itself
+    StartElement {
+        name: Name,
+        attributes: Attributes,
+        span: Span,
+    },
+    /// An end tag, or the end of an empty element tag
+    EndElement { name: Name, span: Span },
+    /// Character data, CDATA sections included, with entities resolved
+    Text { value: String, span: Span },
+    /// A comment
+    Comment { value: String, span: Span },
+    /// A processing instruction
+    ProcessingInstruction {
+        target: String,
+        data: Option<String>,
+        span: Span,
+    },
+}
+
+impl Event {
+    ///Where the event was found in the source
+    pub fn span(&self) -> Span {
+        match *self {
+            Event::StartElement { span, .. }
+            | Event::EndElement { span, .. }
+            | Event::Text { span, .. }
+            | Event::Comment { span, .. }
+            | Event::ProcessingInstruction { span, .. } => span,
+        }
+    }
+}
+
+/// Pull parser yielding the `Event`s of a document in order, without building `Element`s.
+///
+/// Whitespace between tags is skipped, as it is when building a tree. Entities declared in
+/// the DOCTYPE are expanded and its attribute defaults added, as they are for `ElementTree::parse`.
+pub struct Events<'o, R: Read> {
+    reader: EventReader<SourceReader<Chain<Cursor<Vec<u8>>, Transcoder<R>>>>,
+    doctype: Option<Doctype>,
+    budget: Budget<'o>,
+    /// An event read ahead to find where a text run ends
+    pending: Option<(XmlEvent, Location)>,
+    /// An error to return once the events before it are out
+    error: Option<XmlErrors>,
+    done: bool,
+}
+
+impl<R: Read> Events<'static, R> {
+    ///Starts reading the document from `read`, within the default `ParseOptions`
+    pub fn new(read: R) -> Events<'static, R> {
+        Events::with_options(read, &DEFAULT_OPTIONS)
+    }
+}
+
+impl<'o, R: Read> Events<'o, R> {
+    ///Starts reading the document from `read`, within the limits of `options`.
+    ///The prolog is read here, an error in it is returned as the first event
+    pub fn with_options(read: R, options: &'o ParseOptions) -> Events<'o, R> {
+        let mut read = Transcoder::new(read);
+        let mut config = ParserConfig::new()
+            .ignore_comments(false)
+            .cdata_to_characters(true);
+        let (prolog, doctype, error) = match dtd::read_doctype(&mut read, &mut config, options) {
+            Ok((prolog, doctype)) => (prolog, doctype, None),
+            Err(err) => (Vec::new(), None, Some(err)),
+        };
+        Events {
+            reader: EventReader::new_with_config(SourceReader::new(Cursor::new(prolog).chain(read)), config),
+            doctype,
+            budget: Budget::new(options),
+            pending: None,
+            error,
+            done: false,
+        }
+    }
+
+    fn read(&mut self) -> Result<(XmlEvent, Location), XmlErrors> {
+        if let Some(pending) = self.pending.take() {
+            return Ok(pending);
+        }
+        let event = next_event(&mut self.reader).map_err(|err| dtd::unresolved_entity(err.into(), self.doctype.as_ref()))?;
+        let start = self.reader.source().locate(self.reader.position());
+        self.reader.source_mut().discard_before(start);
+        Ok((event, start))
+    }
+
+    fn advance(&mut self) -> Result<Option<Event>, XmlErrors> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        loop {
+            let (event, start) = self.read()?;
+            let event = match event {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    let name = Name::from(name);
+                    let qname = name.qname();
+                    let mut attributes = Attributes::from(attributes);
+                    if let Some(ref doctype) = self.doctype {
+                        doctype.add_defaults(&qname, &mut attributes, Some(start), self.budget.options)?;
+                    }
+                    self.budget.enter(&qname, attributes.len(), start)?;
+                    let read = self.reader.source().current().offset;
+                    for attr in &attributes {
+                        self.budget.attribute(&attr.qname(), &attr.value, start, read)?;
+                    }
+                    Event::StartElement {
+                        name,
+                        attributes,
+                        span: self.span_from(start),
+                    }
+                }
+                XmlEvent::EndElement { name } => {
+                    self.budget.leave();
+                    Event::EndElement {
+                        name: Name::from(name),
+                        span: self.span_from(start),
+                    }
+                }
+                XmlEvent::Characters(value) => {
+                    self.budget.text(&value, start, self.reader.source().current().offset)?;
+                    // the text runs up to the start of whatever follows it, or to where reading failed
+                    let end = match self.read() {
+                        Ok(next) => {
+                            let end = next.1;
+                            self.pending = Some(next);
+                            end
+                        }
+                        Err(err) => {
+                            self.error = Some(err);
+                            self.reader.source().current()
+                        }
+                    };
+                    Event::Text {
+                        value,
+                        span: Span { start, end },
+                    }
+                }
+                XmlEvent::Comment(value) => Event::Comment {
+                    value,
+                    span: self.span_from(start),
+                },
+                XmlEvent::ProcessingInstruction { name, data } => Event::ProcessingInstruction {
+                    target: name,
+                    data,
+                    span: self.span_from(start),
+                },
+                XmlEvent::EndDocument => return Ok(None),
+                _ => continue,
+            };
+            return Ok(Some(event));
+        }
+    }
+
+    fn span_from(&self, start: Location) -> Span {
+        Span {
+            start,
+            end: self.reader.source().current(),
+        }
+    }
+}
+
+impl<'o, R: Read> Iterator for Events<'o, R> {
+    type Item = Result<Event, XmlErrors>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.advance() {
+            Ok(Some(event)) => Some(Ok(event)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Callbacks for `parse_with`, each doing nothing unless overridden.
+/// Returning `ControlFlow::Break` stops the parse, without reading the rest of the document.
+pub trait Handler {
+    ///Called for every start tag
+    fn start_element(&mut self, _name: &Name, _attributes: &Attributes, _span: Span) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    ///Called for every end tag, and after the start of an empty element tag
+    fn end_element(&mut self, _name: &Name, _span: Span) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    ///Called for character data
+    fn text(&mut self, _value: &str, _span: Span) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    ///Called for comments
+    fn comment(&mut self, _value: &str, _span: Span) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    ///Called for processing instructions
+    fn processing_instruction(&mut self, _target: &str, _data: Option<&str>, _span: Span) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+
+///Reads the document from `read`, passing each event to `handler` until it asks to stop,
+///within the default `ParseOptions`.
+///Errors past the point where the handler stopped are not seen.
+pub fn parse_with<R: Read, H: Handler>(read: R, handler: &mut H) -> Result<(), XmlErrors> {
+    parse_with_options(read, &DEFAULT_OPTIONS, handler)
+}
+
+///Reads the document from `read` as `parse_with` does, within the limits of `options`
+pub fn parse_with_options<R: Read, H: Handler>(read: R, options: &ParseOptions, handler: &mut H) -> Result<(), XmlErrors> {
+    for event in Events::with_options(read, options) {
+        let flow = match event? {
+            Event::StartElement { name, attributes, span } => handler.start_element(&name, &attributes, span),
+            Event::EndElement { name, span } => handler.end_element(&name, span),
+            Event::Text { value, span } => handler.text(&value, span),
+            Event::Comment { value, span } => handler.comment(&value, span),
+            Event::ProcessingInstruction { target, data, span } => {
+                handler.processing_instruction(&target, data.as_deref(), span)
+            }
+        };
+        if flow.is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
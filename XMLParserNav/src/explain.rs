@@ -0,0 +1,371 @@
+use std::fmt::{self, Display, Formatter};
+use crate::element::Element;
+use crate::errors::{QueryError, XmlErrors};
+use crate::parser::{Token, AXES};
+use crate::tokens::{AxesName, ValidToken};
+use crate::translator::{evaluate_traced, tokenize, Bridge};
+use crate::tree::ElementTree;
+
+/// How a query was read and what the query engine did with it, for finding out why a query
+/// does not return what was expected. Its `Display` lays all of it out.
+#[derive(Debug)]
+pub struct Explain {
+    /// The query as given
+    pub xpath: String,
+    /// The tokens of the query, before abbreviations are expanded
+    pub tokens: Vec<ValidToken>,
+    /// The location steps as written
+    pub ast: Vec<AstStep>,
+    /// The steps with every abbreviation expanded, `//` becoming `/descendant-or-self::node()/`
+    pub steps: Vec<Step>,
+    /// The steps the query engine took on the sample tree, if one was given, up to the one
+    /// it failed at
+    pub evaluation: Option<Vec<Evaluated>>,
+    /// How many nodes the query engine returned for the sample, or why it failed
+    pub returned: Option<Result<usize, XmlErrors>>,
+}
+
+/// A step the query engine took, with how many elements it had after it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evaluated {
+    /// The expanded tokens of the step, `descendant-or-self::book`
+    pub step: String,
+    pub selected: usize,
+}
+
+/// How the axis of a step was written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AstAxis {
+    /// No axis, meaning `child::`
+    Implicit,
+    /// `axis::`
+    Explicit(AxesName),
+    /// `@`
+    AtSign,
+    /// `.`
+    Period,
+    /// `..`
+    Parent,
+}
+
+/// A location step as written in the query
+#[derive(Debug, Clone, PartialEq)]
+pub struct AstStep {
+    /// `/`, `//`, or empty for the first step of a relative path
+    pub separator: &'static str,
+    pub axis: AstAxis,
+    pub test: NodeTest,
+    pub predicates: Vec<Predicate>,
+}
+
+/// A location step with its axis spelled out
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub axis: AxesName,
+    pub test: NodeTest,
+    pub predicates: Vec<Predicate>,
+}
+
+/// What a step selects along its axis
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeTest {
+    /// A name, prefixed or not
+    Name(String),
+    /// `*`, any element, or any attribute on the attribute axis
+    Any,
+    /// `node()`, any node
+    Node,
+}
+
+/// A predicate of a step
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    /// `[n]`, counting from 1 along the axis
+    Position(usize),
+    /// `[@name]`
+    HasAttribute(String),
+    /// `[@name='value']`
+    AttributeEquals(String, String),
+}
+
+///Reads `xpath` without evaluating it
+pub fn explain(xpath: &str) -> Result<Explain, XmlErrors> {
+    let mut token = Token::new(xpath.to_string());
+    let mut tokens = Vec::new();
+    while !token.is_end() {
+        tokens.push(token.next_func()?);
+    }
+    let ast = parse(&tokens)?;
+    let steps = normalise(&ast);
+    Ok(Explain {
+        xpath: xpath.to_string(),
+        tokens,
+        ast,
+        steps,
+        evaluation: None,
+        returned: None,
+    })
+}
+
+impl Element {
+    ///Explains `xpath`, running it under this element and counting what each step of the
+    ///query engine leaves
+    pub fn explain(&self, xpath: &str) -> Result<Explain, XmlErrors> {
+        let mut explain = explain(xpath)?;
+        let tokens = tokenize(xpath)?;
+        let mut trace = Vec::new();
+        let returned = evaluate_traced(self, &tokens, &mut trace).map(|found| found.len());
+        explain.evaluation = Some(
+            trace
+                .into_iter()
+                .map(|traced| Evaluated {
+                    step: tokens[traced.tokens].iter().map(engine_token).collect(),
+                    selected: traced.selected,
+                })
+                .collect(),
+        );
+        explain.returned = Some(returned);
+        Ok(explain)
+    }
+}
+
+impl ElementTree {
+    ///Explains `xpath`, counting what each step selects in this tree
+    pub fn explain(&self, xpath: &str) -> Result<Explain, XmlErrors> {
+        match self.root {
+            Some(ref root) => root.explain(xpath),
+            None => Err(XmlErrors::ElementNotFound { t: "root".to_string() }),
+        }
+    }
+}
+
+impl Bridge {
+    ///Explains the query of the bridge against its tree
+    pub fn explain(&self) -> Result<Explain, XmlErrors> {
+        self.tree.explain(self.token.xpath())
+    }
+}
+
+///Groups the tokens into steps
+fn parse(tokens: &[ValidToken]) -> Result<Vec<AstStep>, QueryError> {
+    let mut steps = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let separator = match tokens[i] {
+            ValidToken::ForwardSlash => "/",
+            ValidToken::DoubleForwardSlash => "//",
+            _ if steps.is_empty() => "",
+            _ => return Err(QueryError::XPath),
+        };
+        if !separator.is_empty() {
+            i += 1;
+        }
+
+        let (axis, test) = match tokens.get(i) {
+            Some(ValidToken::Period) => {
+                i += 1;
+                (AstAxis::Period, NodeTest::Node)
+            }
+            Some(ValidToken::Parent) => {
+                i += 1;
+                (AstAxis::Parent, NodeTest::Node)
+            }
+            Some(ValidToken::AtSign) => {
+                i += 1;
+                (AstAxis::AtSign, node_test(tokens, &mut i)?)
+            }
+            Some(ValidToken::Axes(name)) => {
+                i += 1;
+                if tokens.get(i) == Some(&ValidToken::LocationStep) {
+                    i += 1;
+                }
+                (AstAxis::Explicit(*name), node_test(tokens, &mut i)?)
+            }
+            _ => (AstAxis::Implicit, node_test(tokens, &mut i)?),
+        };
+
+        let mut predicates = Vec::new();
+        while tokens.get(i) == Some(&ValidToken::LeftBracket) {
+            let end = tokens[i..]
+                .iter()
+                .position(|token| *token == ValidToken::RightBracket)
+                .ok_or(QueryError::XPathOpenBracket)?;
+            predicates.push(predicate(&tokens[i + 1..i + end])?);
+            i += end + 1;
+        }
+        steps.push(AstStep {
+            separator,
+            axis,
+            test,
+            predicates,
+        });
+    }
+    if steps.is_empty() {
+        return Err(QueryError::XPath);
+    }
+    Ok(steps)
+}
+
+fn node_test(tokens: &[ValidToken], i: &mut usize) -> Result<NodeTest, QueryError> {
+    let test = match tokens.get(*i) {
+        Some(ValidToken::Asterisk) => NodeTest::Any,
+        Some(ValidToken::Literal(name)) => {
+            if tokens.get(*i + 1) == Some(&ValidToken::LeftParen) {
+                // `node()` is the only node type test the engine knows
+                match tokens.get(*i + 2) {
+                    Some(ValidToken::RightParen) if name == "node" => {
+                        *i += 3;
+                        return Ok(NodeTest::Node);
+                    }
+                    _ => return Err(QueryError::XPath),
+                }
+            }
+            NodeTest::Name(name.clone())
+        }
+        _ => return Err(QueryError::XPath),
+    };
+    *i += 1;
+    Ok(test)
+}
+
+fn predicate(tokens: &[ValidToken]) -> Result<Predicate, QueryError> {
+    use ValidToken::*;
+    let attribute = match tokens {
+        [Number(n)] if *n >= 1.0 && n.fract() == 0.0 => return Ok(Predicate::Position(*n as usize)),
+        [AtSign, rest @ ..] | [Axes(AxesName::Attribute), LocationStep, rest @ ..] => rest,
+        _ => return Err(QueryError::XPath),
+    };
+    match attribute {
+        [Literal(name)] => Ok(Predicate::HasAttribute(name.clone())),
+        [Literal(name), Equal, Literal(value)] => Ok(Predicate::AttributeEquals(name.clone(), value.clone())),
+        [Literal(name), Equal, Number(value)] => Ok(Predicate::AttributeEquals(name.clone(), value.to_string())),
+        _ => Err(QueryError::XPath),
+    }
+}
+
+///Expands the abbreviations of the steps
+fn normalise(ast: &[AstStep]) -> Vec<Step> {
+    let mut steps = Vec::new();
+    for step in ast {
+        if step.separator == "//" {
+            steps.push(Step {
+                axis: AxesName::DescendantOrSelf,
+                test: NodeTest::Node,
+                predicates: Vec::new(),
+            });
+        }
+        let axis = match step.axis {
+            AstAxis::Implicit => AxesName::Child,
+            AstAxis::Explicit(axis) => axis,
+            AstAxis::AtSign => AxesName::Attribute,
+            AstAxis::Period => AxesName::SelfAxis,
+            AstAxis::Parent => AxesName::Parent,
+        };
+        steps.push(Step {
+            axis,
+            test: step.test.clone(),
+            predicates: step.predicates.clone(),
+        });
+    }
+    steps
+}
+
+///The name of `axis` in XPath
+fn axis_name(axis: AxesName) -> &'static str {
+    AXES.iter().find(|(_, name)| *name == axis).map_or("", |(id, _)| id)
+}
+
+///An expanded token as it is shown in an evaluation step
+fn engine_token(token: &ValidToken) -> String {
+    match token {
+        ValidToken::Axes(axis) => format!("{}::", axis_name(*axis)),
+        ValidToken::LocationStep => String::new(),
+        token => token.to_string(),
+    }
+}
+
+impl Display for NodeTest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeTest::Name(name) => write!(f, "{}", name),
+            NodeTest::Any => write!(f, "*"),
+            NodeTest::Node => write!(f, "node()"),
+        }
+    }
+}
+
+impl Predicate {
+    ///The predicate as it may be written, `[@id='b1']`
+    fn abbreviated(&self) -> String {
+        match self {
+            Predicate::Position(n) => format!("[{}]", n),
+            Predicate::HasAttribute(name) => format!("[@{}]", name),
+            Predicate::AttributeEquals(name, value) => format!("[@{}='{}']", name, value),
+        }
+    }
+}
+
+impl Display for Predicate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Predicate::Position(n) => write!(f, "[position() = {}]", n),
+            Predicate::HasAttribute(name) => write!(f, "[attribute::{}]", name),
+            Predicate::AttributeEquals(name, value) => write!(f, "[attribute::{} = '{}']", name, value),
+        }
+    }
+}
+
+impl Display for AstStep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.separator)?;
+        match self.axis {
+            AstAxis::Implicit => write!(f, "{}", self.test)?,
+            AstAxis::Explicit(axis) => write!(f, "{}::{}", axis_name(axis), self.test)?,
+            AstAxis::AtSign => write!(f, "@{}", self.test)?,
+            AstAxis::Period => write!(f, ".")?,
+            AstAxis::Parent => write!(f, "..")?,
+        }
+        for predicate in &self.predicates {
+            write!(f, "{}", predicate.abbreviated())?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{}", axis_name(self.axis), self.test)?;
+        for predicate in &self.predicates {
+            write!(f, "{}", predicate)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Explain {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "query: {}", self.xpath)?;
+        let tokens: Vec<String> = self.tokens.iter().map(|token| token.to_string()).collect();
+        writeln!(f, "tokens: {}", tokens.join(" "))?;
+        writeln!(f, "ast:")?;
+        for step in &self.ast {
+            writeln!(f, "  {}", step)?;
+        }
+        writeln!(f, "steps:")?;
+        for (i, step) in self.steps.iter().enumerate() {
+            writeln!(f, "  {}. {}", i + 1, step)?;
+        }
+        if let Some(ref evaluation) = self.evaluation {
+            writeln!(f, "evaluation:")?;
+            let width = evaluation.iter().map(|evaluated| evaluated.step.len()).max().unwrap_or(0);
+            for evaluated in evaluation {
+                writeln!(f, "  {:<width$}  {} selected", evaluated.step, evaluated.selected, width = width)?;
+            }
+        }
+        match self.returned {
+            Some(Ok(n)) => writeln!(f, "engine returned: {}", n),
+            Some(Err(ref err)) => writeln!(f, "engine returned: {:#}", err),
+            None => Ok(()),
+        }
+    }
+}
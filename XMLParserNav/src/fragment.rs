@@ -0,0 +1,263 @@
+use std::io::Read;
+use crate::borrowed::{parse_content, BorrowedElement};
+use crate::element::{Element, Node};
+use crate::errors::XmlErrors;
+use crate::options::{Budget, ParseOptions};
+use crate::position::{Location, Span};
+use crate::tree::ElementTree;
+
+/// A piece of XML that need not have a single root, such as `<a/>text<b/>`.
+///
+/// The top-level content is kept the way an `Element` keeps its content.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fragment {
+    /// The top-level elements
+    pub children: Vec<Element>,
+    /// The top-level content in document order
+    pub nodes: Vec<Node>,
+}
+
+impl Fragment {
+    ///Parses the fragment held in `s`
+    pub fn parse(s: &str) -> Result<Fragment, XmlErrors> {
+        Fragment::parse_with_namespaces(s, &[])
+    }
+
+    ///Parses the fragment held in `s` as if it were inside elements declaring `namespaces`,
+    ///given as `(prefix, uri)` pairs with an empty prefix for the default namespace
+    pub fn parse_with_namespaces(s: &str, namespaces: &[(&str, &str)]) -> Result<Fragment, XmlErrors> {
+        Fragment::parse_with_options(s, namespaces, &ParseOptions::default())
+    }
+
+    ///Parses the fragment held in `s` like `parse_with_namespaces`, within the limits of `options`
+    pub fn parse_with_options(
+        s: &str,
+        namespaces: &[(&str, &str)],
+        options: &ParseOptions,
+    ) -> Result<Fragment, XmlErrors> {
+        let container = BorrowedElement {
+            prefix: None,
+            tag: "",
+            attributes: Vec::new(),
+            children: Vec::new(),
+            text: None,
+            nodes: Vec::new(),
+            span: Span::default(),
+        };
+        let bound: Vec<&str> = namespaces
+            .iter()
+            .map(|&(prefix, _)| prefix)
+            .filter(|prefix| !prefix.is_empty())
+            .collect();
+        let container = parse_content(s, Some(container), &bound, &mut Budget::new(options))?.root;
+        let mut scope = namespaces
+            .iter()
+            .map(|&(prefix, uri)| (if prefix.is_empty() { None } else { Some(prefix) }, uri))
+            .collect();
+        let content = container.to_element_in(&mut scope);
+        Ok(Fragment {
+            children: content.children,
+            nodes: content.nodes,
+        })
+    }
+
+    ///Iterates over every element of the fragment in document order
+    pub fn iter(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().flat_map(Element::iter)
+    }
+}
+
+/// Iterator over the documents of an input holding several one after another, such as a log file.
+///
+/// A document ends where the next XML declaration, DOCTYPE or root element starts, so comments
+/// and processing instructions after a root element stay with it. Source locations are counted
+/// from the start of the whole input.
+pub struct Documents<R: Read> {
+    read: R,
+    options: ParseOptions,
+    /// Input not yet handed out, starting at `base`
+    buf: Vec<u8>,
+    base: Location,
+    /// How far `buf` was scanned
+    pos: usize,
+    /// How many elements of the current document are open
+    depth: usize,
+    /// Whether the root element of the current document was closed
+    closed: bool,
+    eof: bool,
+    done: bool,
+}
+
+/// What a piece of markup does to the nesting of a document
+enum Markup {
+    Start,
+    Empty,
+    End,
+    Declaration,
+    Doctype,
+    Other,
+}
+
+impl<R: Read> Documents<R> {
+    ///Reads documents from `read`
+    pub fn new(read: R) -> Documents<R> {
+        Documents::with_options(read, ParseOptions::default())
+    }
+
+    ///Reads documents from `read`, parsing each within the limits of `options`
+    pub fn with_options(read: R, options: ParseOptions) -> Documents<R> {
+        Documents {
+            read,
+            options,
+            buf: Vec::new(),
+            base: Location::start(),
+            pos: 0,
+            depth: 0,
+            closed: false,
+            eof: false,
+            done: false,
+        }
+    }
+
+    ///Scans on, returning where the current document ends once the next one is seen to start
+    fn scan(&mut self) -> Option<usize> {
+        while self.pos < self.buf.len() {
+            let rest = &self.buf[self.pos..];
+            if rest[0] != b'<' {
+                self.pos += rest.iter().position(|&b| b == b'<').unwrap_or(rest.len());
+                continue;
+            }
+            // enough to tell `<!--` from `<![CDATA[` and the like
+            if rest.len() < 9 && !self.eof {
+                return None;
+            }
+            let (len, markup) = markup(rest)?;
+            if self.closed && matches!(markup, Markup::Start | Markup::Empty | Markup::Declaration | Markup::Doctype) {
+                return Some(self.pos);
+            }
+            match markup {
+                Markup::Start => self.depth += 1,
+                Markup::Empty if self.depth == 0 => self.closed = true,
+                Markup::End => {
+                    self.depth = self.depth.saturating_sub(1);
+                    self.closed = self.depth == 0;
+                }
+                _ => {}
+            }
+            self.pos += len;
+        }
+        None
+    }
+
+    fn fill(&mut self) -> std::io::Result<()> {
+        // grow with the unfinished part, so long markup is not rescanned over and over
+        let len = self.buf.len();
+        self.buf.resize(len + (len - self.pos).max(8192), 0);
+        let read = self.read.read(&mut self.buf[len..])?;
+        self.buf.truncate(len + read);
+        self.eof = read == 0;
+        Ok(())
+    }
+
+    ///Parses the first `end` bytes as a document
+    fn take(&mut self, end: usize) -> Result<ElementTree, XmlErrors> {
+        let chunk: Vec<u8> = self.buf.drain(..end).collect();
+        let base = self.base;
+        for &byte in &chunk {
+            self.base.advance(byte);
+        }
+        self.pos = 0;
+        self.depth = 0;
+        self.closed = false;
+
+        match ElementTree::parse_with_options(&chunk[..], &self.options) {
+            Ok(mut tree) => {
+                if let Some(ref mut root) = tree.root {
+                    root.shift_spans(base);
+                }
+                Ok(tree)
+            }
+            Err(XmlErrors::ParseError { mut source }) => {
+                source.location = source.location.map(|location| location.from_base(base));
+                Err(XmlErrors::ParseError { source })
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl<R: Read> Iterator for Documents<R> {
+    type Item = Result<ElementTree, XmlErrors>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            if let Some(end) = self.scan() {
+                return Some(self.take(end));
+            }
+            if !self.eof {
+                if let Err(err) = self.fill() {
+                    self.done = true;
+                    return Some(Err(err.into()));
+                }
+                continue;
+            }
+            self.done = true;
+            if self.buf.iter().all(u8::is_ascii_whitespace) {
+                return None;
+            }
+            let end = self.buf.len();
+            return Some(self.take(end));
+        }
+    }
+}
+
+///The length and kind of the markup `rest` starts with, or `None` if it is cut short
+fn markup(rest: &[u8]) -> Option<(usize, Markup)> {
+    let find = |from: usize, end: &[u8]| {
+        rest[from..]
+            .windows(end.len())
+            .position(|w| w == end)
+            .map(|index| from + index + end.len())
+    };
+    if rest.starts_with(b"<!--") {
+        Some((find(4, b"-->")?, Markup::Other))
+    } else if rest.starts_with(b"<![CDATA[") {
+        Some((find(9, b"]]>")?, Markup::Other))
+    } else if rest.starts_with(b"<?") {
+        let len = find(2, b"?>")?;
+        let declaration = rest.starts_with(b"<?xml") && rest.get(5).is_some_and(u8::is_ascii_whitespace);
+        Some((len, if declaration { Markup::Declaration } else { Markup::Other }))
+    } else if rest.starts_with(b"</") {
+        Some((find(2, b">")?, Markup::End))
+    } else {
+        // skip quoted values, and the internal subset of a DOCTYPE
+        let (mut quote, mut depth) = (None, 0);
+        for (index, &byte) in rest.iter().enumerate().skip(1) {
+            match (quote, byte) {
+                (Some(q), _) if q == byte => quote = None,
+                (Some(_), _) => {}
+                (None, b'"' | b'\'') => quote = Some(byte),
+                (None, b'[') => depth += 1,
+                (None, b']') => depth -= 1,
+                (None, b'>') if depth <= 0 => {
+                    let markup = if rest.starts_with(b"<!DOCTYPE") {
+                        Markup::Doctype
+                    } else if rest.starts_with(b"<!") {
+                        Markup::Other
+                    } else if rest[index - 1] == b'/' {
+                        Markup::Empty
+                    } else {
+                        Markup::Start
+                    };
+                    return Some((index + 1, markup));
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
@@ -0,0 +1,136 @@
+use crate::element::Element;
+use crate::errors::XmlErrors;
+use crate::select::{one, parse, parse_bool, resolve, split_attribute};
+use crate::translator::ReturnEnum;
+use crate::tree::ElementTree;
+
+/// A type read from an element by running an XPath for each of its fields, usually with
+/// `#[derive(FromXml)]` and `#[xpath("...")]` on the fields.
+///
+/// A field is a `String`, a number, a `bool` or a `char` read from the one node its path
+/// selects, a struct that derives `FromXml` read from the one element, an `Option` of these
+/// for a path that may select nothing, or a `Vec` for every node selected. Values are read
+/// as by `Element::select_one` and `select_bool`, with the same errors.
+pub trait FromXml: Sized {
+    ///Reads the value from `element`, under which the paths of the fields are run
+    fn from_xml(element: &Element) -> Result<Self, XmlErrors>;
+
+    ///Reads the value from the root element of `tree`
+    fn from_tree(tree: &ElementTree) -> Result<Self, XmlErrors> {
+        match tree.root {
+            Some(ref root) => Self::from_xml(root),
+            None => Err(XmlErrors::ElementNotFound { t: "root".to_string() }),
+        }
+    }
+}
+
+/// A node selected by the XPath of a field
+#[derive(Debug, Clone)]
+pub enum Match<'a> {
+    Element(&'a Element),
+    /// An attribute value, or a namespace prefix
+    Value(String),
+}
+
+impl<'a> Match<'a> {
+    ///The text of the element, or the value
+    pub fn text(&self) -> &str {
+        match *self {
+            Match::Element(element) => element.text.as_deref().unwrap_or(""),
+            Match::Value(ref value) => value,
+        }
+    }
+}
+
+/// A field type of a `FromXml` struct, made from the nodes its XPath `path` selected
+pub trait FromMatches: Sized {
+    fn from_matches(path: &str, matches: Vec<Match<'_>>) -> Result<Self, XmlErrors>;
+}
+
+///Runs `path` under `element`.
+///
+///Paths without a leading `/` are relative to the element, and a final `@name` step selects
+///that attribute of the elements before it.
+pub fn select<'a>(element: &'a Element, path: &str) -> Result<Vec<Match<'a>>, XmlErrors> {
+    let (steps, attribute) = split_attribute(path);
+
+    let mut matches: Vec<Match> = resolve(element, steps)?
+        .into_iter()
+        .map(|found| match found {
+            ReturnEnum::ElementNode(element) => Match::Element(element),
+            ReturnEnum::ElementName(name) => Match::Value(name),
+        })
+        .collect();
+    if let Some(attribute) = attribute {
+        matches = matches
+            .into_iter()
+            .filter_map(|found| match found {
+                Match::Element(element) => element.attributes.get(attribute).map(|value| Match::Value(value.to_string())),
+                Match::Value(_) => None,
+            })
+            .collect();
+    }
+    Ok(matches)
+}
+
+///Reads the field of type `ty` that `path` selects under `element`, for `#[derive(FromXml)]`
+pub fn field<T: FromMatches>(element: &Element, path: &str, ty: &str) -> Result<T, XmlErrors> {
+    select(element, path).and_then(|matches| T::from_matches(path, matches)).map_err(|err| XmlErrors::FieldError {
+        path: path.to_string(),
+        ty: ty.to_string(),
+        source: Box::new(err),
+    })
+}
+
+///Reads a struct that derives `FromXml` from the one element selected
+pub fn nested<T: FromXml>(path: &str, matches: Vec<Match<'_>>) -> Result<T, XmlErrors> {
+    match one(path, matches)? {
+        Match::Element(element) => T::from_xml(element),
+        Match::Value(value) => Err(XmlErrors::ConversionError {
+            t: format!("expected an element, found the value '{}'", value),
+        }),
+    }
+}
+
+impl FromMatches for String {
+    fn from_matches(path: &str, matches: Vec<Match<'_>>) -> Result<Self, XmlErrors> {
+        one(path, matches).map(|found| found.text().to_string())
+    }
+}
+
+impl FromMatches for bool {
+    fn from_matches(path: &str, matches: Vec<Match<'_>>) -> Result<Self, XmlErrors> {
+        parse_bool(one(path, matches)?.text().to_string())
+    }
+}
+
+/// Types read by parsing the trimmed text with `FromStr`
+macro_rules! from_str_matches {
+    ($($t:ty),*) => {
+        $(
+            impl FromMatches for $t {
+                fn from_matches(path: &str, matches: Vec<Match<'_>>) -> Result<Self, XmlErrors> {
+                    parse(one(path, matches)?.text().to_string())
+                }
+            }
+        )*
+    };
+}
+
+from_str_matches!(char, i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64);
+
+impl<T: FromMatches> FromMatches for Option<T> {
+    fn from_matches(path: &str, matches: Vec<Match<'_>>) -> Result<Self, XmlErrors> {
+        if matches.is_empty() {
+            Ok(None)
+        } else {
+            T::from_matches(path, matches).map(Some)
+        }
+    }
+}
+
+impl<T: FromMatches> FromMatches for Vec<T> {
+    fn from_matches(path: &str, matches: Vec<Match<'_>>) -> Result<Self, XmlErrors> {
+        matches.into_iter().map(|found| T::from_matches(path, vec![found])).collect()
+    }
+}
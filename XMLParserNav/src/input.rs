@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use crate::errors::XmlErrors;
+
+/// Compression formats recognised from the first bytes of an input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Plain XML
+    None,
+    /// gzip, as in `.xml.gz` (feature `gzip`)
+    Gzip,
+    /// Zstandard (feature `zstd`)
+    Zstd,
+    /// bzip2 (feature `bzip2`)
+    Bzip2,
+}
+
+impl Compression {
+    ///Recognises the format from the magic bytes at the start of `bytes`
+    pub fn detect(bytes: &[u8]) -> Compression {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if bytes.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else {
+            Compression::None
+        }
+    }
+}
+
+///Wraps `read` in the decompressor its magic bytes call for, or returns it as it is
+pub fn decompress<'a, R: BufRead + 'a>(mut read: R) -> Result<Box<dyn Read + 'a>, XmlErrors> {
+    let compression = Compression::detect(read.fill_buf()?);
+    match compression {
+        Compression::None => Ok(Box::new(read)),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Ok(Box::new(flate2::bufread::MultiGzDecoder::new(read))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Ok(Box::new(zstd::stream::read::Decoder::with_buffer(read)?)),
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => Ok(Box::new(bzip2::bufread::MultiBzDecoder::new(read))),
+        #[allow(unreachable_patterns)]
+        _ => Err(unsupported(compression).into()),
+    }
+}
+
+///Opens the file at `path`, decompressing it if needed
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Box<dyn Read>, XmlErrors> {
+    decompress(BufReader::new(File::open(path)?))
+}
+
+fn unsupported(compression: Compression) -> io::Error {
+    let name = format!("{:?}", compression).to_lowercase();
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} input needs the `{}` feature", name, name),
+    )
+}
+
+/// A read-only memory map of a file, usable wherever the document bytes are needed
+#[cfg(feature = "mmap")]
+pub struct MappedFile {
+    map: memmap2::Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl MappedFile {
+    ///Maps the file at `path` into memory
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MappedFile, XmlErrors> {
+        let file = File::open(path)?;
+        // the map is only valid as long as nobody truncates the file, as with any mmap
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(MappedFile { map })
+    }
+
+    ///The bytes of the file
+    pub fn bytes(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl std::ops::Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map
+    }
+}
@@ -0,0 +1,269 @@
+use serde_json::{Map, Value};
+use xml::namespace::NS_XMLNS_PREFIX;
+use crate::builder::ElementBuilder;
+use crate::element::{Element, Node};
+use crate::errors::XmlErrors;
+use crate::lexer::{is_name_char, is_name_start};
+use crate::tree::ElementTree;
+
+/// How elements, attributes and text are laid out in JSON.
+///
+/// Repeated child elements become arrays in every convention. Comments and processing
+/// instructions are left out, as is the order of text relative to child elements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Convention {
+    /// `{"a": {"@id": "1", "$": "hi", "b": [{"$": "x"}, {"$": "y"}]}}`: every element is an
+    /// object and every value a string, text is kept in `$` and namespace declarations in
+    /// `@xmlns`, with `$` for the default namespace
+    BadgerFish,
+    /// `{"b": ["x", 1]}`: the root element and attributes are left out, and text that reads
+    /// as a number or boolean becomes one
+    Parker,
+    /// `{"a": {"@id": "1", "#text": "hi", "b": ["x", "y"]}}`: elements with only text become
+    /// strings and empty ones `null`
+    Simple,
+}
+
+/// How `ElementTree::to_json` and `ElementTree::from_json` convert
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonOptions {
+    /// The layout of the JSON
+    pub convention: Convention,
+    /// The name of the root element made from JSON in the Parker convention, which leaves it out
+    pub root: String,
+}
+
+impl Default for JsonOptions {
+    fn default() -> Self {
+        JsonOptions::new(Convention::Simple)
+    }
+}
+
+impl JsonOptions {
+    ///Converts with `convention`
+    pub fn new(convention: Convention) -> JsonOptions {
+        JsonOptions {
+            convention,
+            root: "root".to_string(),
+        }
+    }
+}
+
+impl ElementTree {
+    ///Converts the document to JSON, `null` if it has no root element
+    pub fn to_json(&self, options: &JsonOptions) -> Value {
+        match self.root {
+            Some(ref root) => root.to_json(options),
+            None => Value::Null,
+        }
+    }
+
+    ///Makes a document of JSON laid out as `options` say
+    pub fn from_json(value: &Value, options: &JsonOptions) -> Result<ElementTree, XmlErrors> {
+        Element::from_json(value, options).map(ElementTree::new_with_root)
+    }
+}
+
+impl Element {
+    ///Converts the element to JSON, keyed by its name unless the convention is Parker
+    pub fn to_json(&self, options: &JsonOptions) -> Value {
+        let value = to_value(self, options.convention);
+        match options.convention {
+            Convention::Parker => value,
+            _ => {
+                let mut object = Map::new();
+                object.insert(qname(self), value);
+                Value::Object(object)
+            }
+        }
+    }
+
+    ///Makes an element of JSON laid out as `options` say
+    pub fn from_json(value: &Value, options: &JsonOptions) -> Result<Element, XmlErrors> {
+        match (options.convention, value) {
+            (Convention::Parker, _) => from_value(&options.root, value, options.convention),
+            (_, Value::Object(object)) if object.len() == 1 => {
+                let (name, value) = object.iter().next().unwrap();
+                from_value(name, value, options.convention)
+            }
+            _ => Err(conversion("expected an object with the root element as its only key")),
+        }
+    }
+}
+
+fn conversion(msg: &str) -> XmlErrors {
+    XmlErrors::ConversionError { t: msg.to_string() }
+}
+
+fn qname(element: &Element) -> String {
+    match element.namespace {
+        Some(ref prefix) => format!("{}:{}", prefix, element.tag),
+        None => element.tag.clone(),
+    }
+}
+
+///The runs of text of `element` that are not only whitespace, joined if it has no child elements
+fn texts(element: &Element) -> Vec<String> {
+    let texts: Vec<&str> = if element.nodes_match_content() {
+        element
+            .nodes
+            .iter()
+            .filter_map(|node| match *node {
+                Node::Text(ref text) => Some(text.value.as_str()),
+                _ => None,
+            })
+            .collect()
+    } else {
+        element.text.as_deref().into_iter().collect()
+    };
+    if element.children.is_empty() {
+        let text = texts.concat();
+        return if text.trim().is_empty() { Vec::new() } else { vec![text] };
+    }
+    texts.into_iter().filter(|text| !text.trim().is_empty()).map(str::to_string).collect()
+}
+
+///One string, or an array of strings for text split up by child elements
+fn text_value(texts: &[String]) -> Option<Value> {
+    match *texts {
+        [] => None,
+        [ref text] => Some(Value::String(text.clone())),
+        _ => Some(Value::Array(texts.iter().cloned().map(Value::String).collect())),
+    }
+}
+
+///Text as the number or boolean it reads as, if it does
+fn typed(text: &str) -> Value {
+    let trimmed = text.trim();
+    match trimmed {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => match serde_json::from_str::<serde_json::Number>(trimmed) {
+            Ok(number) => Value::Number(number),
+            Err(_) => Value::String(text.to_string()),
+        },
+    }
+}
+
+fn to_value(element: &Element, convention: Convention) -> Value {
+    let texts = texts(element);
+    let mut object = Map::new();
+    match convention {
+        Convention::Parker if element.children.is_empty() => {
+            return match texts.first() {
+                Some(text) => typed(text),
+                None => Value::Null,
+            };
+        }
+        Convention::Parker => {}
+        Convention::Simple => {
+            if element.attributes.is_empty() && element.children.is_empty() {
+                return text_value(&texts).unwrap_or(Value::Null);
+            }
+            for attr in &element.attributes {
+                object.insert(format!("@{}", attr.qname()), Value::String(attr.value.clone()));
+            }
+            if let Some(text) = text_value(&texts) {
+                object.insert("#text".to_string(), text);
+            }
+        }
+        Convention::BadgerFish => {
+            let mut namespaces = Map::new();
+            for attr in &element.attributes {
+                let key = match (attr.prefix.as_deref(), attr.name.as_str()) {
+                    (None, NS_XMLNS_PREFIX) => "$".to_string(),
+                    (Some(NS_XMLNS_PREFIX), prefix) => prefix.to_string(),
+                    _ => continue,
+                };
+                namespaces.insert(key, Value::String(attr.value.clone()));
+            }
+            if !namespaces.is_empty() {
+                object.insert("@xmlns".to_string(), Value::Object(namespaces));
+            }
+            for attr in &element.attributes {
+                if attr.prefix.as_deref() != Some(NS_XMLNS_PREFIX) && attr.qname() != NS_XMLNS_PREFIX {
+                    object.insert(format!("@{}", attr.qname()), Value::String(attr.value.clone()));
+                }
+            }
+            if let Some(text) = text_value(&texts) {
+                object.insert("$".to_string(), text);
+            }
+        }
+    }
+
+    // children of the same name are grouped where the first of them is
+    for child in &element.children {
+        let value = to_value(child, convention);
+        match object.get_mut(&qname(child)) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(first) => *first = Value::Array(vec![first.take(), value]),
+            None => {
+                object.insert(qname(child), value);
+            }
+        }
+    }
+    Value::Object(object)
+}
+
+fn from_value(name: &str, value: &Value, convention: Convention) -> Result<Element, XmlErrors> {
+    let mut chars = name.chars();
+    if !chars.next().is_some_and(is_name_start) || !chars.all(is_name_char) {
+        return Err(conversion(&format!("'{}' is not an element name", name)));
+    }
+    let mut builder = ElementBuilder::new(name);
+    let object = match *value {
+        Value::Null => return Ok(builder.build()),
+        Value::Object(ref object) => object,
+        Value::Array(_) => return Err(conversion(&format!("element '{}' cannot hold an array", name))),
+        _ => return Ok(builder.text(scalar(value)?).build()),
+    };
+
+    let mut children = Vec::new();
+    for (key, value) in object {
+        match (convention, key.as_str()) {
+            (Convention::Simple, "#text") | (Convention::BadgerFish, "$") => match *value {
+                // the runs of mixed content stay apart
+                Value::Array(ref values) => {
+                    for value in values {
+                        builder = builder.text(scalar(value)?);
+                    }
+                }
+                _ => builder = builder.text(scalar(value)?),
+            },
+            (Convention::BadgerFish, "@xmlns") => {
+                let namespaces = match *value {
+                    Value::Object(ref namespaces) => namespaces,
+                    _ => return Err(conversion("'@xmlns' must be an object of prefixes")),
+                };
+                for (prefix, uri) in namespaces {
+                    builder = match prefix.as_str() {
+                        "$" => builder.attr(NS_XMLNS_PREFIX, scalar(uri)?),
+                        prefix => builder.attr(format!("{}:{}", NS_XMLNS_PREFIX, prefix), scalar(uri)?),
+                    };
+                }
+            }
+            (Convention::Simple | Convention::BadgerFish, key) if key.starts_with('@') => {
+                builder = builder.attr(&key[1..], scalar(value)?);
+            }
+            (_, key) => match *value {
+                Value::Array(ref values) => {
+                    for value in values {
+                        children.push(from_value(key, value, convention)?);
+                    }
+                }
+                _ => children.push(from_value(key, value, convention)?),
+            },
+        }
+    }
+    Ok(builder.children(children).build())
+}
+
+///The text of a string, number or boolean
+fn scalar(value: &Value) -> Result<String, XmlErrors> {
+    match *value {
+        Value::String(ref s) => Ok(s.clone()),
+        Value::Number(ref n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(conversion(&format!("expected text, found {}", value))),
+    }
+}
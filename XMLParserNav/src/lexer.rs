@@ -0,0 +1,460 @@
+use std::borrow::Cow;
+use crate::errors::{ParseError, ParseErrorKind};
+use crate::position::{Location, Span};
+
+/// A piece of markup or text, borrowing from the input wherever possible
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lexeme<'a> {
+    /// The `<?xml ...?>` declaration
+    Declaration {
+        version: &'a str,
+        encoding: Option<&'a str>,
+        standalone: Option<&'a str>,
+        span: Span,
+    },
+    /// A start tag, `empty` for `<tag/>`
+    StartTag {
+        prefix: Option<&'a str>,
+        local: &'a str,
+        attributes: Vec<BorrowedAttribute<'a>>,
+        empty: bool,
+        span: Span,
+    },
+    /// An end tag
+    EndTag {
+        prefix: Option<&'a str>,
+        local: &'a str,
+        span: Span,
+    },
+    /// Character data up to the next markup, with entities resolved
+    Text { value: Cow<'a, str>, span: Span },
+    /// The content of a CDATA section
+    CData { value: &'a str, span: Span },
+    /// The content of a comment
+    Comment { value: &'a str, span: Span },
+    /// A processing instruction
+    ProcessingInstruction {
+        target: &'a str,
+        data: Option<&'a str>,
+        span: Span,
+    },
+    /// A document type declaration, everything between `<!DOCTYPE` and the final `>`
+    Doctype { value: &'a str, span: Span },
+}
+
+impl<'a> Lexeme<'a> {
+    ///Where the lexeme was found in the input
+    pub fn span(&self) -> Span {
+        match *self {
+            Lexeme::Declaration { span, .. }
+            | Lexeme::StartTag { span, .. }
+            | Lexeme::EndTag { span, .. }
+            | Lexeme::Text { span, .. }
+            | Lexeme::CData { span, .. }
+            | Lexeme::Comment { span, .. }
+            | Lexeme::ProcessingInstruction { span, .. }
+            | Lexeme::Doctype { span, .. } => span,
+        }
+    }
+}
+
+/// An attribute of a start tag, borrowing from the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BorrowedAttribute<'a> {
+    /// The prefix of the attribute name
+    pub prefix: Option<&'a str>,
+    /// The local name of the attribute
+    pub name: &'a str,
+    /// The value, only allocated when entities had to be resolved
+    pub value: Cow<'a, str>,
+    /// Where the attribute was found in the input
+    pub span: Span,
+}
+
+impl<'a> BorrowedAttribute<'a> {
+    ///Returns the qualified name, `prefix:local` or just `local`
+    pub fn qname(&self) -> String {
+        match self.prefix {
+            Some(prefix) => format!("{}:{}", prefix, self.name),
+            None => self.name.to_string(),
+        }
+    }
+}
+
+/// Splits a document held in memory into `Lexeme`s without copying it.
+///
+/// Only the syntax of each piece is checked; nesting is left to the caller.
+pub struct Lexer<'a> {
+    input: &'a str,
+    pos: usize,
+    /// Location of some byte at or before `pos`, moved forward as needed
+    loc: Location,
+    done: bool,
+}
+
+impl<'a> Lexer<'a> {
+    ///Starts lexing `input`
+    pub fn new(input: &'a str) -> Lexer<'a> {
+        // a byte order mark is not part of the document
+        let pos = if input.starts_with('\u{feff}') { 3 } else { 0 };
+        Lexer {
+            input,
+            pos,
+            loc: Location::start(),
+            done: false,
+        }
+    }
+
+    ///Location of the byte `offset`
+    pub(crate) fn locate(&mut self, offset: usize) -> Location {
+        if offset < self.loc.offset {
+            // only errors look back, so starting over is fine
+            self.loc = Location::start();
+        }
+        for &byte in &self.input.as_bytes()[self.loc.offset..offset] {
+            self.loc.advance(byte);
+        }
+        self.loc
+    }
+
+    fn span(&mut self, start: Location) -> Span {
+        Span {
+            start,
+            end: self.locate(self.pos),
+        }
+    }
+
+    fn error(&mut self, msg: String, offset: usize) -> ParseError {
+        let location = self.locate(offset);
+        ParseError::new(ParseErrorKind::Syntax(msg), Some(location))
+    }
+
+    fn eof(&mut self) -> ParseError {
+        let location = self.locate(self.input.len());
+        ParseError::new(ParseErrorKind::UnexpectedEof, Some(location))
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_space(&mut self) -> bool {
+        let rest = self.rest();
+        let trimmed = rest.trim_start_matches(is_space);
+        self.pos += rest.len() - trimmed.len();
+        trimmed.len() != rest.len()
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), ParseError> {
+        if self.rest().starts_with(literal) {
+            self.pos += literal.len();
+            Ok(())
+        } else if self.rest().len() < literal.len() && literal.starts_with(self.rest()) {
+            Err(self.eof())
+        } else {
+            Err(self.error(format!("Expected '{}'", literal), self.pos))
+        }
+    }
+
+    ///Reads up to `end`, returning what came before it and moving past it
+    fn until(&mut self, end: &str) -> Result<&'a str, ParseError> {
+        match self.rest().find(end) {
+            Some(index) => {
+                let found = &self.rest()[..index];
+                self.pos += index + end.len();
+                Ok(found)
+            }
+            None => Err(self.eof()),
+        }
+    }
+
+    ///Reads a name, split at its first colon into prefix and local part
+    fn name(&mut self) -> Result<(Option<&'a str>, &'a str), ParseError> {
+        let rest = self.rest();
+        let len = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+        let name = &rest[..len];
+        match name.chars().next() {
+            Some(c) if is_name_start(c) => {}
+            Some(_) => return Err(self.error(format!("Invalid name '{}'", name), self.pos)),
+            None if rest.is_empty() => return Err(self.eof()),
+            None => return Err(self.error("Expected a name".to_string(), self.pos)),
+        }
+        self.pos += len;
+        match name.split_once(':') {
+            Some((prefix, local)) if !prefix.is_empty() && !local.is_empty() && !local.contains(':') => {
+                Ok((Some(prefix), local))
+            }
+            Some(_) => Err(self.error(format!("Invalid qualified name '{}'", name), self.pos - len)),
+            None => Ok((None, name)),
+        }
+    }
+
+    ///Reads the attributes of a start tag up to its `>` or `/>`, returning whether it was `/>`
+    fn attributes(&mut self, attributes: &mut Vec<BorrowedAttribute<'a>>) -> Result<bool, ParseError> {
+        loop {
+            let spaced = self.skip_space();
+            let rest = self.rest();
+            if rest.starts_with("/>") {
+                self.pos += 2;
+                return Ok(true);
+            } else if rest.starts_with('>') {
+                self.pos += 1;
+                return Ok(false);
+            } else if rest.is_empty() {
+                return Err(self.eof());
+            } else if !spaced {
+                return Err(self.error("Expected whitespace between attributes".to_string(), self.pos));
+            }
+
+            let start = self.pos;
+            let start_loc = self.locate(start);
+            let (prefix, name) = self.name()?;
+            self.skip_space();
+            self.expect("=")?;
+            self.skip_space();
+            let quote = match self.rest().chars().next() {
+                Some(quote @ ('"' | '\'')) => quote,
+                Some(_) => return Err(self.error("Expected a quoted attribute value".to_string(), self.pos)),
+                None => return Err(self.eof()),
+            };
+            self.pos += 1;
+            let value_start = self.pos;
+            let raw = match self.rest().find(quote) {
+                Some(index) => &self.rest()[..index],
+                None => return Err(self.eof()),
+            };
+            if let Some(index) = raw.find('<') {
+                return Err(self.error("Unexpected '<' in attribute value".to_string(), value_start + index));
+            }
+            self.pos += raw.len() + 1;
+            let value = unescape(raw).map_err(|msg| self.error(msg, value_start))?;
+
+            let span = self.span(start_loc);
+            let attr = BorrowedAttribute {
+                prefix,
+                name,
+                value,
+                span,
+            };
+            if attributes.iter().any(|old| (old.prefix, old.name) == (prefix, name)) {
+                return Err(self.error(format!("Duplicate attribute '{}'", attr.qname()), start));
+            }
+            attributes.push(attr);
+        }
+    }
+
+    fn markup(&mut self) -> Result<Lexeme<'a>, ParseError> {
+        let start = self.pos;
+        let start_loc = self.locate(start);
+        let rest = self.rest();
+        if rest.starts_with("<?") {
+            self.pos += 2;
+            let (prefix, target) = self.name()?;
+            if prefix.is_some() {
+                return Err(self.error("Invalid processing instruction target".to_string(), start + 2));
+            }
+            let spaced = self.skip_space();
+            let data = self.until("?>")?;
+            if !spaced && !data.is_empty() {
+                return Err(self.error("Expected whitespace after the target".to_string(), start));
+            }
+            if target == "xml" {
+                return self.declaration(start, start_loc);
+            }
+            let data = if data.is_empty() { None } else { Some(data) };
+            let span = self.span(start_loc);
+            Ok(Lexeme::ProcessingInstruction { target, data, span })
+        } else if rest.starts_with("<!--") {
+            self.pos += 4;
+            let value = self.until("-->")?;
+            if value.contains("--") || value.ends_with('-') {
+                return Err(self.error("'--' is not allowed in comments".to_string(), start));
+            }
+            let span = self.span(start_loc);
+            Ok(Lexeme::Comment { value, span })
+        } else if rest.starts_with("<![CDATA[") {
+            self.pos += 9;
+            let value = self.until("]]>")?;
+            let span = self.span(start_loc);
+            Ok(Lexeme::CData { value, span })
+        } else if rest.starts_with("<!DOCTYPE") {
+            self.pos += 9;
+            let value = self.doctype()?;
+            let span = self.span(start_loc);
+            Ok(Lexeme::Doctype { value, span })
+        } else if rest.starts_with("</") {
+            self.pos += 2;
+            let (prefix, local) = self.name()?;
+            self.skip_space();
+            self.expect(">")?;
+            let span = self.span(start_loc);
+            Ok(Lexeme::EndTag { prefix, local, span })
+        } else if rest.starts_with("<!") {
+            Err(self.error("Unexpected markup declaration".to_string(), start))
+        } else {
+            self.pos += 1;
+            let (prefix, local) = self.name()?;
+            let mut attributes = Vec::new();
+            let empty = self.attributes(&mut attributes)?;
+            let span = self.span(start_loc);
+            Ok(Lexeme::StartTag {
+                prefix,
+                local,
+                attributes,
+                empty,
+                span,
+            })
+        }
+    }
+
+    ///Reads the pseudo-attributes of the XML declaration
+    fn declaration(&mut self, start: usize, start_loc: Location) -> Result<Lexeme<'a>, ParseError> {
+        if start != 0 && !(start == 3 && self.input.starts_with('\u{feff}')) {
+            return Err(self.error("The XML declaration must come first".to_string(), start));
+        }
+        // lex the pseudo-attributes from the space after `xml`, up to the `?>`
+        let mut inner = Lexer::new(&self.input[..self.pos - 2]);
+        inner.pos = start + 5;
+        let mut attributes = Vec::new();
+        inner.attributes(&mut attributes).ok();
+        let pseudo = |name: &str| {
+            attributes
+                .iter()
+                .find(|attr| attr.prefix.is_none() && attr.name == name)
+                .and_then(|attr| match attr.value {
+                    Cow::Borrowed(value) => Some(value),
+                    Cow::Owned(_) => None,
+                })
+        };
+        let version = match pseudo("version") {
+            Some(version) => version,
+            None => return Err(self.error("The XML declaration has no version".to_string(), start)),
+        };
+        let encoding = pseudo("encoding");
+        let standalone = pseudo("standalone");
+        let span = self.span(start_loc);
+        Ok(Lexeme::Declaration {
+            version,
+            encoding,
+            standalone,
+            span,
+        })
+    }
+
+    ///Reads a document type declaration up to its closing `>`, skipping over an internal subset
+    fn doctype(&mut self) -> Result<&'a str, ParseError> {
+        let bytes = self.input.as_bytes();
+        let from = self.pos;
+        let mut depth = 0;
+        let mut quote = None;
+        while self.pos < bytes.len() {
+            let byte = bytes[self.pos];
+            self.pos += 1;
+            match (quote, byte) {
+                (Some(q), _) if q == byte => quote = None,
+                (Some(_), _) => {}
+                (None, b'"' | b'\'') => quote = Some(byte),
+                (None, b'[') => depth += 1,
+                (None, b']') => depth -= 1,
+                (None, b'>') if depth == 0 => return Ok(&self.input[from..self.pos - 1]),
+                _ => {}
+            }
+        }
+        Err(self.eof())
+    }
+
+    fn text(&mut self) -> Result<Lexeme<'a>, ParseError> {
+        let start = self.pos;
+        let start_loc = self.locate(start);
+        let rest = self.rest();
+        let raw = &rest[..rest.find('<').unwrap_or(rest.len())];
+        self.pos += raw.len();
+        if let Some(index) = raw.find("]]>") {
+            return Err(self.error("']]>' is not allowed in text".to_string(), start + index));
+        }
+        let value = unescape(raw).map_err(|msg| self.error(msg, start))?;
+        let span = self.span(start_loc);
+        Ok(Lexeme::Text { value, span })
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Lexeme<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.pos >= self.input.len() {
+            return None;
+        }
+        let lexeme = if self.rest().starts_with('<') {
+            self.markup()
+        } else {
+            self.text()
+        };
+        if lexeme.is_err() {
+            self.done = true;
+        }
+        Some(lexeme)
+    }
+}
+
+///Resolves the character and predefined entity references in `raw` and normalizes line ends,
+///only allocating when something had to change
+pub(crate) fn unescape(raw: &str) -> Result<Cow<'_, str>, String> {
+    if !raw.contains(['&', '\r']) {
+        return Ok(Cow::Borrowed(raw));
+    }
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(index) = rest.find(['&', '\r']) {
+        out.push_str(&rest[..index]);
+        rest = &rest[index..];
+        if let Some(after) = rest.strip_prefix('\r') {
+            out.push('\n');
+            rest = after.strip_prefix('\n').unwrap_or(after);
+            continue;
+        }
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => return Err("Unterminated entity reference".to_string()),
+        };
+        let entity = &rest[1..end];
+        match resolve_entity(entity) {
+            Some(c) => out.push(c),
+            None => return Err(format!("Unexpected entity: {}", entity)),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(Cow::Owned(out))
+}
+
+///The character an entity reference (without `&` and `;`) stands for, if it is predefined
+pub(crate) fn resolve_entity(entity: &str) -> Option<char> {
+    let code = if let Some(hex) = entity.strip_prefix("#x") {
+        u32::from_str_radix(hex, 16).ok()?
+    } else if let Some(dec) = entity.strip_prefix('#') {
+        dec.parse().ok()?
+    } else {
+        return match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "apos" => Some('\''),
+            "quot" => Some('"'),
+            _ => None,
+        };
+    };
+    char::from_u32(code).filter(|&c| c != '\0')
+}
+
+pub(crate) fn is_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\r' | '\n')
+}
+
+pub(crate) fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == ':'
+}
+
+pub(crate) fn is_name_char(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || c == '-' || c == '.' || c == '\u{b7}' || (!c.is_ascii() && c.is_alphanumeric())
+}
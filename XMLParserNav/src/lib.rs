@@ -1,11 +1,88 @@
+//! XML parser and navigator: parse documents into an `ElementTree` and run XPath queries on it.
+//!
+//! ```
+//! use XMLParserNav::prelude::*;
+//!
+//! let doc = ElementTree::parse_str("<catalog><book id=\"first\"><title>Rain</title></book></catalog>").unwrap();
+//! let titles = doc.query("//title").unwrap();
+//! assert_eq!(titles.len(), 1);
+//! ```
 #![allow(warnings)]
+// lets `#[derive(FromXml)]` name this crate from inside it too
+extern crate self as XMLParserNav;
+
+mod attribute;
+mod borrowed;
+mod builder;
+mod c14n;
+#[cfg(feature = "serde")]
+pub mod de;
+mod dtd;
 mod element;
+mod encoding;
 mod errors;
+mod events;
+pub mod explain;
+mod fragment;
+pub mod from_xml;
+pub mod input;
+#[cfg(feature = "json")]
+pub mod json;
+mod lexer;
+mod lossless;
 mod translator;
 mod tree;
+mod options;
 mod parser;
+mod position;
+mod recover;
+mod select;
+#[cfg(feature = "serde")]
+pub mod ser;
+mod serialize;
+pub mod stream;
 mod tokens;
 
+pub use attribute::{Attribute, Attributes};
+pub use borrowed::{BorrowedDocument, BorrowedElement, BorrowedNode};
+pub use lexer::BorrowedAttribute;
+pub use builder::ElementBuilder;
+pub use c14n::Canonicalization;
+pub use dtd::{AttributeDecl, AttributeDefault, Doctype, EntityDecl};
+pub use element::{Comment, Descendants, Element, Node, ProcessingInstruction, Text};
+pub use errors::{Error, ParseError, ParseErrorKind, QueryError, Result, XmlErrorKind, XmlErrors};
+pub use events::{parse_with, parse_with_options, Event, Events, Handler, Name};
+pub use explain::{explain, Evaluated, Explain};
+pub use fragment::{Documents, Fragment};
+pub use from_xml::FromXml;
+#[cfg(feature = "derive")]
+pub use xmlparsernav_derive::FromXml;
+#[cfg(feature = "json")]
+pub use json::{Convention, JsonOptions};
+pub use lossless::Lossless;
+pub use options::{Limit, ParseOptions};
+pub use position::{Location, Span};
+pub use serialize::{LineEnding, Quote, WriteOptions};
+pub use stream::{StreamItem, StreamQuery};
+pub use tokens::{AxesName, ValidToken};
+pub use translator::{Bridge, ReturnEnum, TreeMatches};
+pub use tree::ElementTree;
+
+/// The types needed for everyday parsing, querying and building
+pub mod prelude {
+    pub use crate::attribute::{Attribute, Attributes};
+    pub use crate::builder::ElementBuilder;
+    pub use crate::element::{Element, Node};
+    pub use crate::errors::{XmlErrorKind, XmlErrors};
+    pub use crate::from_xml::FromXml;
+    pub use crate::options::ParseOptions;
+    #[cfg(feature = "derive")]
+    pub use xmlparsernav_derive::FromXml;
+    pub use crate::translator::ReturnEnum;
+    pub use crate::tree::ElementTree;
+    pub use crate::xml;
+}
+
 #[cfg(test)]
 mod test {
 
@@ -55,4 +132,1564 @@ mod test {
             ReturnEnum::ElementName(_) => { }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_builder_and_macro() {
+        use crate::element::Element;
+
+        let built = Element::builder("book")
+            .attr("id", "second")
+            .xmlns("h", "Some_Prefix")
+            .prefix("h")
+            .child(Element::builder("title").text("Midnight Rain"))
+            .child(Element::builder("h:price").text(5.95))
+            .build();
+
+        let price = 5.95;
+        let from_macro = crate::xml!(h:book[id = "second", xmlns:h = "Some_Prefix"] {
+            title { "Midnight Rain" }
+            h:price { (price) }
+        });
+
+        assert_eq!(built, from_macro);
+        assert_eq!(built.namespace.as_deref(), Some("h"));
+        assert_eq!(built.children[1].namespace.as_deref(), Some("h"));
+        assert_eq!(built.children[1].tag, "price");
+        assert_eq!(built.attributes.get("xmlns:h").unwrap(), "Some_Prefix");
+
+        // declaring a prefix does not move the element into it
+        let declared = Element::builder("catalog").xmlns("h", "Some_Prefix").xmlns("", "urn:default").build();
+        assert_eq!(declared.namespace, None);
+        assert_eq!(declared.attributes.get("xmlns"), Some("urn:default"));
+        assert_eq!(Element::builder("h:book").prefix("").build().namespace, None);
+
+        let catalog = crate::xml!(catalog { {built.clone()} book[id = "third"] "catalog text" });
+        assert_eq!(catalog.children.len(), 2);
+        assert_eq!(catalog.text.as_deref(), Some("catalog text"));
+        assert_eq!(catalog.children[1].attributes.get("id").unwrap(), "third");
+    }
+
+    #[test]
+    fn test_ordered_attributes() {
+        let doc_raw = r#"<catalog xmlns:h="Some_Prefix"><book z="1" h:id="second" a="2" m="3"/></catalog>"#;
+        let doc = ElementTree::parse(doc_raw.as_bytes()).unwrap();
+        let book = &doc.root.as_ref().unwrap().children[0];
+
+        let names: Vec<String> = book.attributes.iter().map(|attr| attr.qname()).collect();
+        assert_eq!(names, ["z", "h:id", "a", "m"]);
+        assert_eq!(book.attributes.get("h:id"), Some("second"));
+        assert_eq!(book.attributes.get_ns("Some_Prefix", "id"), Some("second"));
+        assert_eq!(book.attributes.get("id"), None);
+
+        let written = book.to_string();
+        // written on its own, the book declares the prefix it uses
+        assert!(written.contains(r#"<book xmlns:h="Some_Prefix" z="1" h:id="second" a="2" m="3""#), "{}", written);
+    }
+
+    #[test]
+    fn test_source_locations() {
+        use crate::element::Node;
+
+        let doc_raw = "<catalog>\n  <book id=\"first\" name='Rahul'>caf\u{e9} text</book>\n  <book id=\"second\"/>\n</catalog>";
+        let doc = ElementTree::parse(doc_raw.as_bytes()).unwrap();
+        let root = doc.root.as_ref().unwrap();
+        let span = root.span.unwrap();
+        assert_eq!((span.start.line, span.start.column, span.start.offset), (1, 1, 0));
+        assert_eq!(span.end.offset, doc_raw.len());
+
+        let book = &root.children[0];
+        let span = book.span.unwrap();
+        assert_eq!((span.start.line, span.start.column), (2, 3));
+        assert_eq!(&doc_raw[span.start.offset..span.end.offset], "<book id=\"first\" name='Rahul'>caf\u{e9} text</book>");
+
+        let name = book.attributes.get_attribute("name").unwrap().span.unwrap();
+        assert_eq!(&doc_raw[name.start.offset..name.end.offset], "name='Rahul'");
+        assert_eq!((name.start.line, name.start.column), (2, 20));
+
+        match book.nodes[0] {
+            Node::Text(ref text) => {
+                let span = text.span.unwrap();
+                assert_eq!(&doc_raw[span.start.offset..span.end.offset], "caf\u{e9} text");
+                assert_eq!(span.end.column, 42);
+            }
+            _ => panic!("expected a text node"),
+        }
+
+        let mut bridge = Bridge::new(doc, "//book[2]".to_string());
+        bridge.token_step_filler();
+        let result = bridge.produce().unwrap();
+        let span = result[0].span().unwrap();
+        assert_eq!((span.start.line, span.start.column, span.end.line, span.end.column), (3, 3, 3, 22));
+
+        // a root at the very start, without a declaration, keeps its namespace declarations
+        let doc_raw = "<catalog a=\"1\" xmlns:h=\"urn:h\"><h:price h:cur=\"usd\">5</h:price><c/></catalog>";
+        let doc = ElementTree::parse_str(doc_raw).unwrap();
+        let root = doc.root.as_ref().unwrap();
+        assert_eq!(root.span.unwrap().start, crate::position::Location::start());
+        let names: Vec<String> = root.attributes.iter().map(|attr| attr.qname()).collect();
+        assert_eq!(names, ["a", "xmlns:h"]);
+        let xmlns = root.attributes.get_attribute("xmlns:h").unwrap().span.unwrap();
+        assert_eq!(&doc_raw[xmlns.start.offset..xmlns.end.offset], "xmlns:h=\"urn:h\"");
+        assert_eq!(ElementTree::parse_str(&doc.to_string()).unwrap().to_string(), doc.to_string());
+        let mut c14n = Vec::new();
+        doc.canonicalize(&mut c14n, &crate::c14n::Canonicalization::default()).unwrap();
+        assert!(String::from_utf8(c14n).unwrap().starts_with("<catalog xmlns:h=\"urn:h\" a=\"1\"><h:price"));
+        let doc = ElementTree::parse_str("<r><c/></r>").unwrap();
+        assert_eq!(doc.root.unwrap().span.unwrap().start.offset, 0);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        use crate::errors::{ParseErrorKind, XmlErrors};
+
+        let doc_raw = "<catalog>\n  <book id=\"first\">\n    <h:title xmlns:h=\"Some_Prefix\">Rain</title>\n  </book>\n</catalog>";
+        let err = match ElementTree::parse(doc_raw.as_bytes()) {
+            Err(XmlErrors::ParseError { source }) => source,
+            _ => panic!("expected a parse error"),
+        };
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::MismatchedTag {
+                expected: "h:title".to_string(),
+                found: "title".to_string()
+            }
+        );
+        assert_eq!(err.open_elements, ["catalog", "book", "h:title"]);
+        assert_eq!(err.location.unwrap().line, 3);
+        assert!(err.to_string().starts_with("3:"));
+
+        let err = match ElementTree::parse("<catalog><book>".as_bytes()) {
+            Err(XmlErrors::ParseError { source }) => source,
+            _ => panic!("expected a parse error"),
+        };
+        assert_eq!(err.kind, ParseErrorKind::UnexpectedEof);
+        assert_eq!(err.open_elements, ["catalog", "book"]);
+
+        // every backend reports mismatched tags by their qualified names
+        let mismatched = ParseErrorKind::MismatchedTag {
+            expected: "h:title".to_string(),
+            found: "title".to_string(),
+        };
+        let doc_raw = "<h:title xmlns:h=\"Some_Prefix\"></title>";
+        let err = match crate::BorrowedDocument::parse(doc_raw).unwrap_err() {
+            XmlErrors::ParseError { source } => source,
+            _ => panic!("expected a parse error"),
+        };
+        assert_eq!(err.kind, mismatched);
+        let (_, diagnostics) = ElementTree::parse_lenient("<h:a xmlns:h=\"Some_Prefix\"><b></h:a>".as_bytes()).unwrap();
+        let unclosed = ParseErrorKind::MismatchedTag {
+            expected: "b".to_string(),
+            found: "h:a".to_string(),
+        };
+        assert!(diagnostics.iter().any(|err| err.kind == unclosed), "{:?}", diagnostics);
+
+        // the xml-rs messages the errors are recovered from
+        use crate::errors::classify_syntax;
+        assert_eq!(classify_syntax("Unexpected closing tag: {urn:x}h:a, expected {urn:y}g:b"), ParseErrorKind::MismatchedTag {
+            expected: "g:b".to_string(),
+            found: "h:a".to_string(),
+        });
+        assert_eq!(classify_syntax("Unexpected closing tag: a, expected b"), ParseErrorKind::MismatchedTag {
+            expected: "b".to_string(),
+            found: "a".to_string(),
+        });
+        assert_eq!(classify_syntax("Unexpected closing tag: a"), ParseErrorKind::Syntax("Unexpected closing tag: a".to_string()));
+        assert_eq!(classify_syntax("Unexpected end of stream: still inside the root element"), ParseErrorKind::UnexpectedEof);
+        assert_eq!(classify_syntax("Unexpected token: <!"), ParseErrorKind::Syntax("Unexpected token: <!".to_string()));
+    }
+
+    #[test]
+    fn test_unified_errors() {
+        use std::error::Error as _;
+        use crate::errors::{QueryError, XmlErrorKind, XmlErrors};
+
+        fn count(doc_raw: &str, query: &str) -> crate::errors::Result<usize> {
+            let doc = ElementTree::parse(doc_raw.as_bytes())?;
+            let mut bridge = Bridge::new(doc, query.to_string());
+            bridge.token_step_filler();
+            Ok(bridge.produce()?.len())
+        }
+
+        assert_eq!(count("<catalog><book/><book/></catalog>", "//book").unwrap(), 2);
+
+        let err = count("<catalog><book></catalog>", "//book").unwrap_err();
+        assert_eq!(err.kind(), XmlErrorKind::Parse);
+        // the xml-rs error is kept, but not repeated as the source of a message made from it
+        assert!(matches!(err, XmlErrors::ParseError { ref source } if source.cause.is_some()));
+        assert!(err.source().unwrap().source().is_none());
+        // each error shows its own context, and `{:#}` the whole chain
+        assert_eq!(err.to_string(), "Parse Error");
+        assert_eq!(format!("{:#}", err), format!("Parse Error: {}", err.source().unwrap()));
+
+        let err = count("<catalog/>", "book").unwrap_err();
+        assert_eq!(err.kind(), XmlErrorKind::QuerySyntax);
+        assert!(matches!(err, XmlErrors::QueryError { source: QueryError::XPath }));
+
+        let err = count("<catalog/>", "//namespacecatalog").unwrap_err();
+        assert_eq!(err.kind(), XmlErrorKind::Evaluation);
+
+        // a writer failing is an io error, whatever is being written
+        struct Broken;
+        impl std::io::Write for Broken {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "closed"))
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let doc = ElementTree::parse_str("<catalog/>").unwrap();
+        let err = doc.write(&mut Broken).unwrap_err();
+        assert!(matches!(err, XmlErrors::IoError { .. }));
+        assert_eq!(err.kind(), XmlErrorKind::Io);
+        let err = doc.canonicalize(&mut Broken, &crate::c14n::Canonicalization::new()).unwrap_err();
+        assert_eq!(err.kind(), XmlErrorKind::Io);
+        assert_eq!(format!("{:#}", err), "IO Error: closed");
+    }
+
+    #[test]
+    fn test_public_api() {
+        use crate::prelude::*;
+
+        let doc: ElementTree = "<catalog><book id=\"first\"><title>Rain</title></book><book/></catalog>".parse().unwrap();
+        let tags: Vec<&str> = doc.iter().map(|el| el.tag.as_str()).collect();
+        assert_eq!(tags, ["catalog", "book", "title", "book"]);
+        assert_eq!(doc.query("//book").unwrap().len(), 2);
+
+        let book = &doc.get_root().unwrap().children[0];
+        assert_eq!(book.query("/book/title").unwrap().len(), 1);
+
+        let built = ElementTree::new_with_root(Element::builder("catalog").child(Element::new("book")));
+        let mut written = Vec::new();
+        built.write(&mut written).unwrap();
+        let reparsed = ElementTree::parse(written.as_slice()).unwrap();
+        assert_eq!(reparsed.get_root(), built.get_root());
+
+        let missing = ElementTree::parse_path("does/not/exist.xml").err().unwrap();
+        assert_eq!(missing.kind(), XmlErrorKind::Io);
+    }
+
+    #[test]
+    fn test_streaming_query() {
+        use crate::errors::{ParseErrorKind, XmlErrors};
+        use crate::options::{Limit, ParseOptions};
+        use crate::stream::{StreamItem, StreamQuery};
+
+        let doc_raw = r#"<catalog>
+            <book id="first"><title>XML Developer's Guide</title><publish_date>2000-10-01</publish_date></book>
+            <shelf><book id="second"><title>Midnight Rain</title></book><book id="third"/></shelf>
+            <book id="fourth"><title>Maeve Ascendant</title></book>
+        </catalog>"#;
+
+        let titles: Vec<String> = StreamQuery::new("//book/title").unwrap()
+            .stream(doc_raw.as_bytes())
+            .map(|item| match item.unwrap() {
+                StreamItem::Element(el) => el.text.unwrap(),
+                StreamItem::Attribute(_) => panic!("expected an element"),
+            })
+            .collect();
+        assert_eq!(titles, ["XML Developer's Guide", "Midnight Rain", "Maeve Ascendant"]);
+
+        let ids: Vec<String> = StreamQuery::new("/catalog/shelf/book[2]/@id").unwrap()
+            .stream(doc_raw.as_bytes())
+            .map(|item| match item.unwrap() {
+                StreamItem::Attribute(attr) => attr.value,
+                StreamItem::Element(_) => panic!("expected an attribute"),
+            })
+            .collect();
+        assert_eq!(ids, ["third"]);
+
+        let mut matches = StreamQuery::new("//book[@id='fourth']").unwrap().stream(doc_raw.as_bytes());
+        match matches.next().unwrap().unwrap() {
+            StreamItem::Element(book) => {
+                assert_eq!(book.children[0].text.as_deref(), Some("Maeve Ascendant"));
+                assert_eq!(book.span.unwrap().start.line, 4);
+            }
+            StreamItem::Attribute(_) => panic!("expected an element"),
+        }
+        assert!(matches.next().is_none());
+
+        assert_eq!(StreamQuery::new("//book/publish_date").unwrap().stream(doc_raw.as_bytes()).count(), 1);
+        assert!(StreamQuery::new("//book/parent::catalog").is_err());
+        assert!(StreamQuery::new("//book[1").is_err());
+
+        // the caller's limits apply to the whole document, the elements around the matches included
+        let limited = |options: &ParseOptions| match StreamQuery::new("//book/title").unwrap()
+            .stream_with_options(doc_raw.as_bytes(), options)
+            .next()
+        {
+            Some(Err(XmlErrors::ParseError { source })) => source.kind,
+            other => panic!("expected a parse error, found {:?}", other.map(|item| item.is_ok())),
+        };
+        let shallow = ParseOptions { max_depth: 2, ..ParseOptions::new() };
+        assert_eq!(limited(&shallow), ParseErrorKind::LimitExceeded(Limit::Depth));
+        let short = ParseOptions { max_text_length: 10, ..ParseOptions::new() };
+        assert_eq!(limited(&short), ParseErrorKind::LimitExceeded(Limit::TextLength));
+        let few = ParseOptions { max_nodes: 3, ..ParseOptions::new() };
+        let titles = StreamQuery::new("//book/title").unwrap().stream_with_options(doc_raw.as_bytes(), &few);
+        assert!(titles.last().unwrap().is_err());
+
+        // where streaming and the tree agree, and where they differ
+        let xml = "<r><a><b>1</b><b>2</b></a><a><b>3</b><b>4</b></a><s id='2'/><s id='2'/><s id='x'/></r>";
+        let doc = ElementTree::parse_str(xml).unwrap();
+        let tree = |xpath: &str| -> Vec<String> { doc.query(xpath).unwrap().iter().map(|n| n.to_string()).collect() };
+        let streamed = |xpath: &str| -> Vec<String> {
+            StreamQuery::new(xpath).unwrap().stream(xml.as_bytes()).map(|item| match item.unwrap() {
+                StreamItem::Element(element) => element.to_string(),
+                StreamItem::Attribute(attr) => attr.value,
+            }).collect()
+        };
+        for xpath in ["//s[@id='2']", "//s[@id=2]", "/r/s[2]", "//s[@id]", "/r/a/b"] {
+            assert_eq!(streamed(xpath), tree(xpath), "{}", xpath);
+        }
+        assert_eq!(tree("//s[@id=2]").len(), 2);
+        // a position counts each parent's children when streamed, and the whole selection on the tree
+        assert_eq!(streamed("//b[2]"), ["<b>2</b>", "<b>4</b>"]);
+        assert_eq!(tree("//b[2]"), ["<b>2</b>"]);
+        // attribute values are streamed, but on the tree only `select_all` reads them
+        assert_eq!(streamed("/r/s/@id"), ["2", "2", "x"]);
+        assert!(doc.query("/r/s/@id").is_err());
+        assert_eq!(doc.select_all::<String>("/r/s/@id").unwrap(), ["2", "2", "x"]);
+    }
+
+    #[test]
+    fn test_event_api() {
+        use crate::events::{parse_with, parse_with_options, Event, Events, Handler, Name};
+        use crate::errors::{ParseErrorKind, XmlErrors};
+        use crate::options::{Limit, ParseOptions};
+        use crate::attribute::Attributes;
+        use crate::position::Span;
+        use std::ops::ControlFlow;
+
+        let doc_raw = "<?xml version=\"1.0\"?>\n<catalog xmlns:h=\"Some_Prefix\">\n<!-- books -->\n<h:book id=\"first\">Rain &amp; <![CDATA[snow]]></h:book><?sort asc?></catalog>";
+
+        let events: Vec<Event> = Events::new(doc_raw.as_bytes()).map(|e| e.unwrap()).collect();
+        assert_eq!(events.len(), 7);
+        match events[2] {
+            Event::StartElement { ref name, ref attributes, span } => {
+                assert_eq!(name.qname(), "h:book");
+                assert_eq!(name.namespace.as_deref(), Some("Some_Prefix"));
+                assert_eq!(attributes.get("id"), Some("first"));
+                assert_eq!((span.start.line, span.start.column), (4, 1));
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+        match events[3] {
+            Event::Text { ref value, span } => {
+                assert_eq!(value, "Rain & snow");
+                assert_eq!((span.start.column, span.end.column), (20, 47));
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(events[1], Event::Comment { ref value, .. } if value == " books "));
+        assert!(matches!(events[5], Event::ProcessingInstruction { ref target, .. } if target == "sort"));
+
+        #[derive(Default)]
+        struct Depth {
+            depth: usize,
+            deepest: usize,
+            text: String,
+        }
+        impl Handler for Depth {
+            fn start_element(&mut self, name: &Name, _attributes: &Attributes, _span: Span) -> ControlFlow<()> {
+                if name.local == "stop" {
+                    return ControlFlow::Break(());
+                }
+                self.depth += 1;
+                self.deepest = self.deepest.max(self.depth);
+                ControlFlow::Continue(())
+            }
+            fn end_element(&mut self, _name: &Name, _span: Span) -> ControlFlow<()> {
+                self.depth -= 1;
+                ControlFlow::Continue(())
+            }
+            fn text(&mut self, value: &str, _span: Span) -> ControlFlow<()> {
+                self.text.push_str(value);
+                ControlFlow::Continue(())
+            }
+        }
+        let mut handler = Depth::default();
+        parse_with(doc_raw.as_bytes(), &mut handler).unwrap();
+        assert_eq!((handler.depth, handler.deepest), (0, 2));
+        assert_eq!(handler.text, "Rain & snow");
+
+        // nothing after the handler stops is read, not even the broken end tag
+        let mut handler = Depth::default();
+        parse_with("<a>one<stop/>two<b></a>".as_bytes(), &mut handler).unwrap();
+        assert_eq!((handler.depth, handler.text.as_str()), (1, "one"));
+        assert!(parse_with("<a><b></a>".as_bytes(), &mut Depth::default()).is_err());
+
+        // text read before an error is still reported, ending where reading stopped
+        let mut events = Events::new("<a>one</b>".as_bytes());
+        assert!(matches!(events.next(), Some(Ok(Event::StartElement { .. }))));
+        assert!(matches!(events.next(), Some(Ok(Event::Text { ref value, .. })) if value == "one"));
+        assert!(matches!(events.next(), Some(Err(_))));
+        assert!(events.next().is_none());
+
+        // the DOCTYPE is applied as it is when building a tree
+        let doctyped = "<!DOCTYPE a [<!ENTITY who \"world\"><!ATTLIST a lang CDATA \"en\"><!ENTITY ext SYSTEM \"x.xml\">]><a>hello &who;</a>";
+        let events: Vec<Event> = Events::new(doctyped.as_bytes()).map(|e| e.unwrap()).collect();
+        assert!(matches!(events[0], Event::StartElement { ref attributes, .. } if attributes.get("lang") == Some("en")));
+        assert!(matches!(events[1], Event::Text { ref value, .. } if value == "hello world"));
+        let external = doctyped.replace("&who;", "&ext;");
+        match Events::new(external.as_bytes()).find_map(|e| e.err()) {
+            Some(XmlErrors::ParseError { ref source }) => {
+                assert_eq!(source.kind, ParseErrorKind::ExternalEntity("ext".to_string()));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // and the limits of the options, the defaults included
+        let deep = format!("{}{}", "<a>".repeat(300), "</a>".repeat(300));
+        match parse_with(deep.as_bytes(), &mut Depth::default()) {
+            Err(XmlErrors::ParseError { ref source }) => {
+                assert_eq!(source.kind, ParseErrorKind::LimitExceeded(Limit::Depth));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let options = ParseOptions { max_depth: 300, ..ParseOptions::default() };
+        let mut handler = Depth::default();
+        parse_with_options(deep.as_bytes(), &options, &mut handler).unwrap();
+        assert_eq!(handler.deepest, 300);
+        let options = ParseOptions { max_nodes: 2, ..ParseOptions::default() };
+        assert!(Events::with_options("<a><b/>text</a>".as_bytes(), &options).any(|e| e.is_err()));
+    }
+
+    #[test]
+    fn test_borrowed_backend() {
+        use crate::borrowed::BorrowedDocument;
+        use std::borrow::Cow;
+
+        let doc_raw = r#"<?xml version="1.0" encoding="UTF-8"?>
+    <!DOCTYPE catalog [ <!ELEMENT catalog ANY> ]>
+    <catalog> catalog test
+       <book id="first" name="Tom &amp; Jerry">
+          <author>Gambardella, Matthew</author>this is book1 text
+          <title>XML <!-- note --> Developer's Guide</title>
+          <price>44.95</price>
+       </book>
+       <book id="second"><title>Midnight <![CDATA[<Rain>]]></title><empty/></book>
+    </catalog>"#;
+
+        let doc = BorrowedDocument::parse(doc_raw).unwrap();
+        assert_eq!(doc.encoding, Some("UTF-8"));
+        let first = &doc.root.children[0];
+        assert!(matches!(first.children[0].text, Some(Cow::Borrowed("Gambardella, Matthew"))));
+        assert!(matches!(first.attributes[0].value, Cow::Borrowed("first")));
+        assert_eq!(first.attribute("name"), Some("Tom & Jerry"));
+        assert_eq!(first.children[1].text.as_deref(), Some("XML  Developer's Guide"));
+        assert_eq!(doc.root.children[1].children[0].text.as_deref(), Some("Midnight <Rain>"));
+        assert_eq!((first.span.start.line, first.span.start.column), (4, 8));
+
+        // same tree as the xml-rs backend, CDATA aside
+        let without_cdata = doc_raw.replace("<![CDATA[<Rain>]]>", "");
+        let borrowed = BorrowedDocument::parse(&without_cdata).unwrap().to_tree();
+        let owned = ElementTree::parse_str(&without_cdata).unwrap();
+        assert_eq!(borrowed.root, owned.root);
+
+        let err = BorrowedDocument::parse("<a><b></a>").unwrap_err();
+        assert_eq!(format!("{:#}", err), "Parse Error: 1:7: Unexpected closing tag 'a', expected 'b' (inside a/b)");
+        assert!(BorrowedDocument::parse("<a x='1' x='2'/>").is_err());
+        assert!(BorrowedDocument::parse("<a>&nbsp;</a>").is_err());
+        assert!(BorrowedDocument::parse_bytes(b"<a>\xff</a>").is_err());
+
+        // the same limits as the other parsers, and no recursion copying deep trees
+        use crate::errors::{ParseErrorKind, XmlErrors};
+        use crate::options::{Limit, ParseOptions};
+        let deep = format!("{}{}", "<a>".repeat(3000), "</a>".repeat(3000));
+        match BorrowedDocument::parse(&deep) {
+            Err(XmlErrors::ParseError { source }) => {
+                assert_eq!(source.kind, ParseErrorKind::LimitExceeded(Limit::Depth));
+                assert_eq!(source.open_elements.len(), 256);
+            }
+            _ => panic!("expected a depth error"),
+        }
+        let options = ParseOptions { max_depth: 3000, ..ParseOptions::new() };
+        let tree = BorrowedDocument::parse_with_options(&deep, &options).unwrap().to_tree();
+        assert_eq!(tree.root.unwrap().iter().count(), 3000);
+        let options = ParseOptions { max_attributes: 1, ..ParseOptions::new() };
+        assert!(BorrowedDocument::parse_with_options("<a x='1' y='2'/>", &options).is_err());
+    }
+
+    #[test]
+    #[cfg(all(feature = "gzip", feature = "mmap"))]
+    fn test_compressed_inputs() {
+        use crate::input::{Compression, MappedFile};
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let doc_raw = "<catalog><book id=\"first\"><title>Midnight Rain</title></book></catalog>";
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(doc_raw.as_bytes()).unwrap();
+        let gz = encoder.finish().unwrap();
+        assert_eq!(Compression::detect(&gz), Compression::Gzip);
+        assert_eq!(Compression::detect(doc_raw.as_bytes()), Compression::None);
+
+        let plain = ElementTree::parse_bytes(doc_raw.as_bytes()).unwrap();
+        assert_eq!(ElementTree::parse_bytes(&gz).unwrap().root, plain.root);
+
+        let dir = std::env::temp_dir();
+        let gz_path = dir.join(format!("xmlparsernav-{}.xml.gz", std::process::id()));
+        let xml_path = dir.join(format!("xmlparsernav-{}.xml", std::process::id()));
+        std::fs::write(&gz_path, &gz).unwrap();
+        std::fs::write(&xml_path, doc_raw).unwrap();
+        assert_eq!(ElementTree::parse_path(&gz_path).unwrap().root, plain.root);
+        assert_eq!(ElementTree::parse_mmap(&gz_path).unwrap().root, plain.root);
+        assert_eq!(ElementTree::parse_mmap(&xml_path).unwrap().root, plain.root);
+        let map = MappedFile::open(&xml_path).unwrap();
+        let borrowed = crate::borrowed::BorrowedDocument::parse_bytes(&map).unwrap();
+        assert_eq!(borrowed.root.children[0].attribute("id"), Some("first"));
+        std::fs::remove_file(gz_path).unwrap();
+        std::fs::remove_file(xml_path).unwrap();
+
+        #[cfg(not(feature = "zstd"))]
+        assert!(ElementTree::parse_bytes(&[0x28, 0xb5, 0x2f, 0xfd, 0]).is_err());
+    }
+
+    #[test]
+    fn test_encodings() {
+        let latin1 = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><menu><item>caf\xe9</item><item>\x80</item></menu>";
+        let doc = ElementTree::parse(&latin1[..]).unwrap();
+        assert_eq!(doc.encoding, "ISO-8859-1");
+        // ISO-8859-1 is decoded as windows-1252, as browsers do
+        assert_eq!(doc.root.as_ref().unwrap().children[0].text.as_deref(), Some("caf\u{e9}"));
+        assert_eq!(doc.root.as_ref().unwrap().children[1].text.as_deref(), Some("\u{20ac}"));
+
+        let text = "<?xml version=\"1.0\"?><menu><item>\u{3059}\u{3057}</item></menu>";
+        let mut utf16: Vec<u8> = vec![0xff, 0xfe];
+        utf16.extend(text.encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+        let doc = ElementTree::parse(&utf16[..]).unwrap();
+        assert_eq!(doc.encoding, "UTF-16LE");
+        assert_eq!(doc.root.as_ref().unwrap().children[0].text.as_deref(), Some("\u{3059}\u{3057}"));
+
+        let mut sjis = Vec::new();
+        doc.write_with_encoding(&mut sjis, "Shift_JIS").unwrap();
+        assert!(sjis.windows(4).any(|w| w == b"\x82\xb7\x82\xb5"));
+        let reparsed = ElementTree::parse(&sjis[..]).unwrap();
+        assert_eq!(reparsed.encoding, "Shift_JIS");
+        assert_eq!(reparsed.root, doc.root);
+
+        // characters missing from the target encoding become references
+        let mut ascii = Vec::new();
+        doc.write_with_encoding(&mut ascii, "ISO-8859-1").unwrap();
+        let ascii = String::from_utf8(ascii).unwrap();
+        assert!(ascii.contains("encoding=\"ISO-8859-1\""));
+        assert!(ascii.contains("&#12377;&#12375;"));
+
+        // references are only possible in text and attribute values
+        let doc = ElementTree::parse_str("<menu note='\u{3059}'><!--\u{3059}--><item/></menu>").unwrap();
+        let mut latin1 = Vec::new();
+        let err = doc.write_with_encoding(&mut latin1, "ISO-8859-1").unwrap_err();
+        assert!(format!("{:#}", err).contains("cannot be written"), "{:#}", err);
+        assert_eq!(err.kind(), crate::errors::XmlErrorKind::Serialization);
+        assert!(latin1.is_empty());
+        let doc = ElementTree::parse_str("<menu note='\u{3059}'><\u{3059}/></menu>").unwrap();
+        assert!(doc.write_with_encoding(&mut Vec::new(), "ISO-8859-1").is_err());
+        let doc = ElementTree::parse_str("<menu note='\u{3059}'>\u{e9}</menu>").unwrap();
+        let mut latin1 = Vec::new();
+        doc.write_with_encoding(&mut latin1, "ISO-8859-1").unwrap();
+        assert!(latin1.ends_with(b"<menu note=\"&#12377;\">\xe9</menu>"));
+
+        let mut be = Vec::new();
+        doc.write_with_encoding(&mut be, "UTF-16BE").unwrap();
+        assert_eq!(&be[..4], &[0xfe, 0xff, 0, b'<']);
+        assert_eq!(ElementTree::parse(&be[..]).unwrap().root, doc.root);
+
+        assert!(doc.write_with_encoding(&mut Vec::new(), "EBCDIC-42").is_err());
+        match ElementTree::parse_str("<?xml version=\"1.0\" encoding=\"EBCDIC-42\"?><a/>") {
+            Err(crate::errors::XmlErrors::ParseError { source }) => {
+                assert_eq!(source.kind, crate::errors::ParseErrorKind::Encoding)
+            }
+            _ => panic!("expected an encoding error"),
+        }
+    }
+
+    #[test]
+    fn test_doctype_and_entities() {
+        use crate::dtd::AttributeDefault;
+
+        let doc_raw = r#"<?xml version="1.0"?>
+<!DOCTYPE catalog SYSTEM "catalog.dtd" [
+  <!ENTITY % kinds "(paper|ebook)">
+  <!ENTITY publisher "O'Reilly &amp; Co">
+  <!ENTITY imprint "&publisher; &#x2014; Books">
+  <!ELEMENT catalog (book*)>
+  <!-- a book > a pamphlet -->
+  <!ATTLIST book id ID #REQUIRED
+                 kind %kinds; "paper"
+                 lang CDATA #FIXED "en">
+]>
+<catalog>
+  <book id="first" kind="ebook"><publisher>&imprint;</publisher></book>
+  <book id="second" title="&publisher;"/>
+</catalog>"#;
+
+        let doc = ElementTree::parse_str(doc_raw).unwrap();
+        let doctype = doc.doctype.as_ref().unwrap();
+        assert_eq!(doctype.name, "catalog");
+        assert_eq!(doctype.system_id.as_deref(), Some("catalog.dtd"));
+        assert_eq!(doctype.attributes[1].kind, "(paper|ebook)");
+        assert_eq!(doctype.attributes[2].default, AttributeDefault::Fixed("en".to_string()));
+
+        let root = doc.get_root().unwrap();
+        let first = &root.children[0];
+        assert_eq!(first.children[0].text.as_deref(), Some("O'Reilly & Co \u{2014} Books"));
+        assert_eq!(first.attributes.get("kind"), Some("ebook"));
+        assert_eq!(first.attributes.get("lang"), Some("en"));
+        let second = &root.children[1];
+        assert_eq!(second.attributes.get("title"), Some("O'Reilly & Co"));
+        assert_eq!(second.attributes.get("kind"), Some("paper"));
+        // positions after the DOCTYPE are unaffected
+        assert_eq!(first.span.unwrap().start.line, 13);
+
+        let written = doc.to_string();
+        assert!(written.contains("<!DOCTYPE catalog SYSTEM \"catalog.dtd\" [\n  <!ENTITY % kinds"));
+        let reparsed = ElementTree::parse_str(&written).unwrap();
+        assert_eq!(reparsed.doctype, doc.doctype);
+        assert_eq!(reparsed.root, doc.root);
+
+        assert!(ElementTree::parse_str("<!DOCTYPE a [<!ENTITY x \"&x;\">]><a>&x;</a>").is_err());
+        assert!(ElementTree::parse_str("<!DOCTYPE a [<!ENTITY x \"1\">]><a>&y;</a>").is_err());
+
+        // entities holding markup are not parsed, so only declaring them is allowed
+        let markup = "<!DOCTYPE a [<!ENTITY bold \"<b>x</b>\"><!ENTITY wrap \"[&bold;]\">]>";
+        assert!(ElementTree::parse_str(&format!("{}<a/>", markup)).is_ok());
+        for entity in ["bold", "wrap"] {
+            let err = ElementTree::parse_str(&format!("{}<a>&{};</a>", markup, entity)).err().unwrap();
+            assert!(format!("{:#}", err).contains("contains markup"), "{:#}", err);
+        }
+        let escaped = ElementTree::parse_str("<!DOCTYPE a [<!ENTITY lt2 \"&#38;lt;b>\">]><a>&lt2;</a>").unwrap();
+        assert_eq!(escaped.root.unwrap().text.as_deref(), Some("<b>"));
+
+        // defaults that cannot be expanded are errors, not copied as written
+        match ElementTree::parse_str("<!DOCTYPE a [<!ATTLIST b c CDATA \"&nope;\">]><a><b/></a>") {
+            Err(crate::errors::XmlErrors::ParseError { source }) => {
+                assert_eq!(source.kind, crate::errors::ParseErrorKind::Syntax("Unexpected entity: nope".to_string()));
+                assert_eq!(source.open_elements, ["a", "b"]);
+            }
+            _ => panic!("expected a parse error"),
+        }
+        let err = ElementTree::parse_str(&format!("{}<a/>", markup.replace("]>", "<!ATTLIST a c CDATA \"&bold;\">]>"))).err().unwrap();
+        assert!(format!("{:#}", err).contains("contains markup"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        use crate::errors::{ParseErrorKind, XmlErrors};
+        use crate::options::{Limit, ParseOptions};
+
+        fn kind(doc: &str, options: &ParseOptions) -> ParseErrorKind {
+            match ElementTree::parse_with_options(doc.as_bytes(), options) {
+                Err(XmlErrors::ParseError { source }) => source.kind,
+                Err(other) => panic!("unexpected error {}", other),
+                Ok(_) => panic!("expected an error"),
+            }
+        }
+        let limit = |limit| ParseErrorKind::LimitExceeded(limit);
+        let defaults = ParseOptions::default();
+
+        let deep = format!("{}{}", "<a>".repeat(300), "</a>".repeat(300));
+        assert_eq!(kind(&deep, &defaults), limit(Limit::Depth));
+        let deep = format!("{}{}", "<a>".repeat(256), "</a>".repeat(256));
+        assert!(ElementTree::parse_with_options(deep.as_bytes(), &defaults).is_ok());
+
+        let doc = "<a x='1' y='2' z='3'><b>text</b><c/><d/></a>";
+        assert!(ElementTree::parse_with_options(doc.as_bytes(), &defaults).is_ok());
+        assert_eq!(kind(doc, &ParseOptions { max_nodes: 4, ..ParseOptions::new() }), limit(Limit::Nodes));
+        assert_eq!(kind(doc, &ParseOptions { max_attributes: 2, ..ParseOptions::new() }), limit(Limit::Attributes));
+        assert_eq!(kind(doc, &ParseOptions { max_text_length: 3, ..ParseOptions::new() }), limit(Limit::TextLength));
+        let long_name = format!("<{0}></{0}>", "n".repeat(200));
+        assert_eq!(kind(&long_name, &ParseOptions { max_name_length: 100, ..ParseOptions::new() }), limit(Limit::NameLength));
+
+        let mut laughs = String::from("<!DOCTYPE lolz [<!ENTITY lol0 \"lol\">");
+        for i in 1..10 {
+            laughs.push_str(&format!("<!ENTITY lol{} \"{}\">", i, format!("&lol{};", i - 1).repeat(10)));
+        }
+        laughs.push_str("]><lolz>&lol9;</lolz>");
+        assert_eq!(kind(&laughs, &defaults), limit(Limit::EntityAmplification));
+        let unamplified = ParseOptions { max_entity_amplification: usize::MAX, ..ParseOptions::new() };
+        assert_eq!(kind(&laughs, &unamplified), limit(Limit::EntityExpansions));
+
+        // entity values are limited before the parse builds text from them
+        let mut nested = format!("<!DOCTYPE a [<!ENTITY e0 \"{}\">", "x".repeat(1000));
+        for i in 1..4 {
+            nested.push_str(&format!("<!ENTITY e{} \"{}\">", i, format!("&e{};", i - 1).repeat(10)));
+        }
+        nested.push_str(&format!("]><a>{}</a>", "&e3;".repeat(300)));
+        assert_eq!(kind(&nested, &defaults), limit(Limit::EntityAmplification));
+        let small = "<!DOCTYPE a [<!ENTITY e0 \"xy\"><!ENTITY e1 \"&e0;&e0;&e0;\">]><a>&e1;</a>";
+        assert_eq!(ElementTree::parse_str(small).unwrap().root.unwrap().text.as_deref(), Some("xyxyxy"));
+
+        let amplified = format!(
+            "<!DOCTYPE a [<!ENTITY big \"{}\">]><a>{}</a>",
+            "x".repeat(5000),
+            "<b>&big;</b>".repeat(500)
+        );
+        assert_eq!(kind(&amplified, &defaults), limit(Limit::EntityAmplification));
+
+        let path = std::env::temp_dir().join(format!("xmlparsernav-entity-{}.txt", std::process::id()));
+        std::fs::write(&path, "<?xml version='1.0'?>external text").unwrap();
+        let external = format!("<!DOCTYPE a [<!ENTITY ext SYSTEM \"{}\">]><a>&ext;</a>", path.display());
+        assert_eq!(kind(&external, &defaults), ParseErrorKind::ExternalEntity("ext".to_string()));
+        let options = ParseOptions { resolve_external_entities: true, ..ParseOptions::new() };
+        let doc = ElementTree::parse_with_options(external.as_bytes(), &options).unwrap();
+        assert_eq!(doc.root.unwrap().text.as_deref(), Some("external text"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_lenient_recovery() {
+        use crate::errors::{ParseErrorKind, XmlErrors};
+        use crate::options::{Limit, ParseOptions};
+
+        let html = "<html><body><p>Fish & chips<p class=menu id='x' hidden>Tea<br>time</b></div></p><ul><li>one<li>two</ul>";
+        let (doc, diagnostics) = ElementTree::parse_lenient(html.as_bytes()).unwrap();
+        assert!(ElementTree::parse_str(html).is_err());
+        let body = &doc.root.as_ref().unwrap().children[0];
+        assert_eq!(body.children.len(), 3);
+        assert_eq!(body.children[0].text.as_deref(), Some("Fish & chips"));
+        let second = &body.children[1];
+        assert_eq!(second.attributes.get("class"), Some("menu"));
+        assert_eq!(second.attributes.get("hidden"), Some("hidden"));
+        assert_eq!(second.children[0].tag, "br");
+        assert_eq!(second.text.as_deref(), Some("Teatime"));
+        assert_eq!(body.children[2].children.len(), 2);
+        assert_eq!(doc.query("//li").unwrap().len(), 2);
+
+        let messages: Vec<String> = diagnostics.iter().map(|err| err.to_string()).collect();
+        assert!(messages.iter().any(|msg| msg.contains("Unescaped '&'")), "{:?}", messages);
+        assert!(messages.iter().any(|msg| msg.contains("Unquoted value of attribute 'class'")));
+        assert!(messages.iter().any(|msg| msg.contains("Unexpected closing tag: b")));
+        assert!(messages.iter().any(|msg| msg.contains("Implied end tag for 'li'")));
+        assert!(diagnostics.iter().any(|err| err.kind == ParseErrorKind::UnexpectedEof));
+        let stray = diagnostics.iter().find(|err| err.to_string().contains("closing tag: b")).unwrap();
+        assert_eq!(stray.open_elements, vec!["html", "body", "p"]);
+        assert_eq!(stray.location.map(|loc| loc.column), Some(67));
+
+        let (doc, diagnostics) = ElementTree::parse_lenient("<a><b>x</a><c/>".as_bytes()).unwrap();
+        let root = doc.root.unwrap();
+        assert_eq!(root.children.iter().map(|e| e.tag.as_str()).collect::<Vec<_>>(), vec!["b", "c"]);
+        assert_eq!(diagnostics.len(), 2);
+
+        let (doc, diagnostics) = ElementTree::parse_lenient("<a>fine</a>".as_bytes()).unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(doc.root.unwrap().text.as_deref(), Some("fine"));
+        let (doc, diagnostics) = ElementTree::parse_lenient("just text".as_bytes()).unwrap();
+        assert!(doc.root.is_none());
+        assert_eq!(diagnostics.len(), 2);
+
+        let deep = "<a>".repeat(20000);
+        match ElementTree::parse_lenient(deep.as_bytes()) {
+            Err(XmlErrors::ParseError { source }) => {
+                assert_eq!(source.kind, ParseErrorKind::LimitExceeded(Limit::Depth));
+                assert_eq!(source.open_elements.len(), 256);
+            }
+            _ => panic!("expected a depth error"),
+        }
+        let deep = "<a>".repeat(3000);
+        let deep_options = ParseOptions { max_depth: 3000, ..ParseOptions::new() };
+        let (doc, diagnostics) = ElementTree::parse_lenient_with_options(deep.as_bytes(), &deep_options).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        let mut depth = 0;
+        let mut element = doc.root.as_ref();
+        while let Some(e) = element {
+            depth += 1;
+            element = e.children.first();
+        }
+        assert_eq!(depth, 3000);
+        let few = ParseOptions { max_nodes: 2, ..ParseOptions::new() };
+        assert!(ElementTree::parse_lenient_with_options("<a><b/></a>".as_bytes(), &few).is_ok());
+        // content after the root is moved into it and counts as well
+        assert!(ElementTree::parse_lenient_with_options("<a><b/></a><c/>".as_bytes(), &few).is_err());
+    }
+
+    #[test]
+    fn test_fragments_and_documents() {
+        use crate::borrowed::BorrowedDocument;
+        use crate::element::Node;
+        use crate::fragment::{Documents, Fragment};
+        use crate::options::ParseOptions;
+
+        assert!(ElementTree::parse_str("<a/><b/>").is_err());
+
+        let fragment = Fragment::parse("<a x='1'/>between<b><c/></b>").unwrap();
+        assert_eq!(fragment.children.len(), 2);
+        assert_eq!(fragment.nodes.len(), 3);
+        assert!(matches!(fragment.nodes[1], Node::Text(ref text) if text.value == "between"));
+        assert_eq!(fragment.iter().map(|e| e.tag.as_str()).collect::<Vec<_>>(), vec!["a", "b", "c"]);
+        assert!(Fragment::parse("<a></b>").is_err());
+        assert!(Fragment::parse("</a>").is_err());
+        assert!(Fragment::parse("").unwrap().children.is_empty());
+
+        let fragment = Fragment::parse_with_namespaces("<x:item x:id='7'/>", &[("x", "urn:x")]).unwrap();
+        let attr = fragment.children[0].attributes.get_attribute("x:id").unwrap();
+        assert_eq!(attr.namespace.as_deref(), Some("urn:x"));
+        // prefixes must be declared, in the fragment or around it, as in a document
+        assert!(Fragment::parse("<x:item/>").is_err());
+        assert!(Fragment::parse("<item x:id='7'/>").is_err());
+        assert!(Fragment::parse("<a xmlns:x='urn:x'/><x:item/>").is_err());
+        assert!(Fragment::parse("<a xmlns:x='urn:x'><x:item/></a><b xml:lang='en'/>").is_ok());
+        assert!(BorrowedDocument::parse("<x:item/>").is_err());
+        // an XML declaration only at the start
+        assert!(Fragment::parse("<?xml version='1.0'?><a/>").is_ok());
+        assert!(Fragment::parse("<a/><?xml version='1.0'?>").is_err());
+        assert!(Fragment::parse("text<?xml version='1.0'?>").is_err());
+
+        // the limits of the other parsers, and no recursion for deep fragments
+        let deep = format!("{}{}", "<a>".repeat(300), "</a>".repeat(300));
+        assert!(Fragment::parse(&deep).is_err());
+        let deep_options = ParseOptions { max_depth: 300, ..ParseOptions::new() };
+        assert_eq!(Fragment::parse_with_options(&deep, &[], &deep_options).unwrap().iter().count(), 300);
+        let few = ParseOptions { max_nodes: 2, ..ParseOptions::new() };
+        assert!(Fragment::parse_with_options("<a/><b/>", &[], &few).is_ok());
+        assert!(Fragment::parse_with_options("<a/><b/><c/>", &[], &few).is_err());
+
+        let log = "<?xml version=\"1.0\"?>\n<entry n=\"1\"/>\n<!-- done -->\n<?xml version=\"1.0\"?>\n<entry n=\"2\"><m>hi</m></entry>\n<entry n=\"3\"></oops>\n<entry n=\"4\"/>\n";
+        let docs: Vec<_> = Documents::new(log.as_bytes()).collect();
+        assert_eq!(docs.len(), 4);
+        let second = docs[1].as_ref().unwrap().root.as_ref().unwrap();
+        assert_eq!(second.attributes.get("n"), Some("2"));
+        assert_eq!(second.children[0].span.unwrap().start.line, 5);
+        let err = format!("{:#}", docs[2].as_ref().err().unwrap());
+        assert!(err.contains("6:"), "{}", err);
+        assert_eq!(docs[3].as_ref().unwrap().root.as_ref().unwrap().attributes.get("n"), Some("4"));
+
+        // markup cut across reads of the input
+        let many = "<r><!-- a long comment --><![CDATA[x]]></r>".repeat(1000);
+        assert_eq!(Documents::new(many.as_bytes()).filter(|doc| doc.is_ok()).count(), 1000);
+    }
+
+    #[test]
+    fn test_namespace_writing() {
+        use crate::builder::ElementBuilder;
+
+        let doc_raw = r#"<first:catalog xmlns="urn:default" xmlns:first="urn:first"><first:author first:id="7">Ann <b>Lee</b> jr</first:author><plain xmlns=""><first:note xmlns:first="urn:first"/></plain></first:catalog>"#;
+        let doc = ElementTree::parse_str(doc_raw).unwrap();
+        let root = doc.root.as_ref().unwrap();
+        assert_eq!(root.namespace.as_deref(), Some("first"));
+        assert_eq!(root.attributes.get("xmlns:first"), Some("urn:first"));
+
+        let mut out = Vec::new();
+        doc.write_with(&mut out, false, " ", false).unwrap();
+        let written = String::from_utf8(out).unwrap();
+        assert_eq!(
+            written,
+            r#"<first:catalog xmlns="urn:default" xmlns:first="urn:first"><first:author first:id="7">Ann <b>Lee</b> jr</first:author><plain xmlns=""><first:note /></plain></first:catalog>"#
+        );
+        let reparsed = ElementTree::parse_str(&written).unwrap();
+        // the only difference is the redundant declaration that was left out
+        let mut expected = root.clone();
+        expected.children[1].children[0].attributes.remove("xmlns:first");
+        assert_eq!(reparsed.root, Some(expected));
+
+        // a prefix only known from an attribute's namespace gets declared where it is used
+        let built: crate::element::Element = ElementBuilder::new("item").attr_ns("urn:x", "x:id", "1").into();
+        assert!(built.to_string().contains(r#"<item xmlns:x="urn:x" x:id="1" />"#), "{}", built);
+
+        // a prefix nothing binds is an error rather than a document that is not namespace well-formed
+        use crate::errors::XmlErrorKind;
+        let options = crate::WriteOptions::compact();
+        let unbound: crate::element::Element = ElementBuilder::new("h:x").build();
+        assert_eq!(unbound.to_string_with(&options).unwrap_err().kind(), XmlErrorKind::Serialization);
+        let mut out = Vec::new();
+        assert!(ElementTree::from(unbound).write(&mut out).is_err());
+        let attr: crate::element::Element = ElementBuilder::new("x").attr("h:id", "1").build();
+        assert!(attr.to_string_with(&options).is_err());
+        let bound: crate::element::Element = ElementBuilder::new("h:x").xmlns("h", "urn:h").child(ElementBuilder::new("h:y")).build();
+        assert_eq!(bound.to_string_with(&options).unwrap(), r#"<h:x xmlns:h="urn:h"><h:y/></h:x>"#);
+    }
+
+    #[test]
+    fn test_canonical_xml() {
+        use crate::c14n::Canonicalization;
+        use crate::options::ParseOptions;
+
+        let canonical = |doc: &str, options: &Canonicalization| {
+            let parse_options = ParseOptions { preserve_whitespace: true, ..ParseOptions::new() };
+            let tree = ElementTree::parse_with_options(doc.as_bytes(), &parse_options).unwrap();
+            let mut out = Vec::new();
+            tree.canonicalize(&mut out, options).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let plain = Canonicalization::new();
+        let with_comments = Canonicalization { with_comments: true, ..Canonicalization::new() };
+        let exclusive = Canonicalization { exclusive: true, ..Canonicalization::new() };
+
+        // the examples of section 3 of the Canonical XML 1.0 recommendation
+        let pis_and_comments = "<?xml version=\"1.0\"?>\n\n<?xml-stylesheet   href=\"doc.xsl\"\n   type=\"text/xsl\"   ?>\n\n<!DOCTYPE doc SYSTEM \"doc.dtd\">\n\n<doc>Hello, world!<!-- Comment 1 --></doc>\n\n<?pi-without-data     ?>\n\n<!-- Comment 2 -->\n\n<!-- Comment 3 -->";
+        assert_eq!(
+            canonical(pis_and_comments, &plain),
+            "<?xml-stylesheet href=\"doc.xsl\"\n   type=\"text/xsl\"   ?>\n<doc>Hello, world!</doc>\n<?pi-without-data?>"
+        );
+        assert_eq!(
+            canonical(pis_and_comments, &with_comments),
+            "<?xml-stylesheet href=\"doc.xsl\"\n   type=\"text/xsl\"   ?>\n<doc>Hello, world!<!-- Comment 1 --></doc>\n<?pi-without-data?>\n<!-- Comment 2 -->\n<!-- Comment 3 -->"
+        );
+
+        let whitespace = "<doc>\n   <clean>   </clean>\n   <dirty>   A   B   </dirty>\n   <mixed>\n      A\n      <clean>   </clean>\n      B\n      <dirty>   A   B   </dirty>\n      C\n   </mixed>\n</doc>";
+        assert_eq!(canonical(whitespace, &plain), whitespace);
+
+        let tags = r#"<!DOCTYPE doc [<!ATTLIST e9 attr CDATA "default">]>
+<doc>
+   <e1   />
+   <e2   ></e2>
+   <e3   name = "elem3"   id="elem3"   />
+   <e4   name="elem4"   id="elem4"   ></e4>
+   <e5 a:attr="out" b:attr="sorted" attr2="all" attr="I'm"
+      xmlns:b="http://www.ietf.org"
+      xmlns:a="http://www.w3.org"
+      xmlns="http://example.org"/>
+   <e6 xmlns="" xmlns:a="http://www.w3.org">
+      <e7 xmlns="http://www.ietf.org">
+         <e8 xmlns="" xmlns:a="http://www.w3.org">
+            <e9 xmlns="" xmlns:a="http://www.ietf.org"/>
+         </e8>
+      </e7>
+   </e6>
+</doc>"#;
+        assert_eq!(
+            canonical(tags, &plain),
+            r#"<doc>
+   <e1></e1>
+   <e2></e2>
+   <e3 id="elem3" name="elem3"></e3>
+   <e4 id="elem4" name="elem4"></e4>
+   <e5 xmlns="http://example.org" xmlns:a="http://www.w3.org" xmlns:b="http://www.ietf.org" attr="I'm" attr2="all" b:attr="sorted" a:attr="out"></e5>
+   <e6 xmlns:a="http://www.w3.org">
+      <e7 xmlns="http://www.ietf.org">
+         <e8 xmlns="">
+            <e9 xmlns:a="http://www.ietf.org" attr="default"></e9>
+         </e8>
+      </e7>
+   </e6>
+</doc>"#
+        );
+
+        let characters = "<doc>\n   <text>First line&#x0d;&#10;Second line</text>\n   <value>&#x32;</value>\n   <compute><![CDATA[value>\"0\" && value<\"10\" ?\"valid\":\"error\"]]></compute>\n   <compute expr='value>\"0\" &amp;&amp; value&lt;\"10\" ?\"valid\":\"error\"'>valid</compute>\n   <norm attr=' &apos;   &#x20;&#13;&#xa;&#9;   &apos; '/>\n</doc>";
+        assert_eq!(
+            canonical(characters, &plain),
+            "<doc>\n   <text>First line&#xD;\nSecond line</text>\n   <value>2</value>\n   <compute>value&gt;\"0\" &amp;&amp; value&lt;\"10\" ?\"valid\":\"error\"</compute>\n   <compute expr=\"value>&quot;0&quot; &amp;&amp; value&lt;&quot;10&quot; ?&quot;valid&quot;:&quot;error&quot;\">valid</compute>\n   <norm attr=\" '    &#xD;&#xA;&#x9;   ' \"></norm>\n</doc>"
+        );
+
+        // the example of the Exclusive XML Canonicalization recommendation, as a whole document
+        let nested = "<n0:local xmlns:n0=\"foo:bar\" xmlns:n3=\"ftp://example.org\"><n1:elem2 xmlns:n1=\"http://example.net\" xml:lang=\"en\"><n3:stuff xmlns:n3=\"ftp://example.org\"/></n1:elem2></n0:local>";
+        assert_eq!(
+            canonical(nested, &plain),
+            "<n0:local xmlns:n0=\"foo:bar\" xmlns:n3=\"ftp://example.org\"><n1:elem2 xmlns:n1=\"http://example.net\" xml:lang=\"en\"><n3:stuff></n3:stuff></n1:elem2></n0:local>"
+        );
+        assert_eq!(
+            canonical(nested, &exclusive),
+            "<n0:local xmlns:n0=\"foo:bar\"><n1:elem2 xmlns:n1=\"http://example.net\" xml:lang=\"en\"><n3:stuff xmlns:n3=\"ftp://example.org\"></n3:stuff></n1:elem2></n0:local>"
+        );
+        let prefix_list = Canonicalization { inclusive_prefixes: vec!["n3".to_string()], ..exclusive };
+        assert_eq!(
+            canonical(nested, &prefix_list),
+            "<n0:local xmlns:n0=\"foo:bar\" xmlns:n3=\"ftp://example.org\"><n1:elem2 xmlns:n1=\"http://example.net\" xml:lang=\"en\"><n3:stuff></n3:stuff></n1:elem2></n0:local>"
+        );
+    }
+
+    #[test]
+    fn test_write_options() {
+        use crate::options::ParseOptions;
+        use crate::element::Element;
+        use crate::serialize::{LineEnding, Quote, WriteOptions};
+
+        let written = |doc: &ElementTree, options: &WriteOptions| {
+            let mut out = Vec::new();
+            doc.write_with_options(&mut out, options).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let doc_raw = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!-- books -->\n<catalog>\n  <book id=\"1\" title=\"Rain &quot;&amp;&quot; Sun\">\n    <title>Ann <b>Lee</b> \u{e9}</title>\n    <empty></empty>\n    <pre xml:space=\"preserve\"><a></a> <b></b></pre>\n  </book>\n</catalog>";
+        let doc = ElementTree::parse_str(doc_raw).unwrap();
+
+        let pretty = WriteOptions { declaration: false, ..WriteOptions::pretty() };
+        assert_eq!(
+            written(&doc, &pretty),
+            "<!-- books -->\n<catalog>\n  <book id=\"1\" title=\"Rain &quot;&amp;&quot; Sun\">\n    <title>Ann <b>Lee</b> \u{e9}</title>\n    <empty />\n    <pre xml:space=\"preserve\"><a /><b /></pre>\n  </book>\n</catalog>"
+        );
+        assert_eq!(
+            written(&doc, &WriteOptions::compact()),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><!-- books --><catalog><book id=\"1\" title=\"Rain &quot;&amp;&quot; Sun\"><title>Ann <b>Lee</b> \u{e9}</title><empty/><pre xml:space=\"preserve\"><a/><b/></pre></book></catalog>"
+        );
+        let custom = WriteOptions {
+            declaration: false,
+            indent: "\t".to_string(),
+            self_closing: false,
+            quote: Quote::Single,
+            escape_non_ascii: true,
+            line_ending: LineEnding::CrLf,
+            line_width: Some(20),
+            ..WriteOptions::new()
+        };
+        assert_eq!(
+            written(&doc, &custom),
+            "<!-- books -->\r\n<catalog>\r\n\t<book\r\n\t\tid='1'\r\n\t\ttitle='Rain \"&amp;\" Sun'>\r\n\t\t<title>Ann <b>Lee</b> &#233;</title>\r\n\t\t<empty></empty>\r\n\t\t<pre xml:space='preserve'><a></a><b></b></pre>\r\n\t</book>\r\n</catalog>"
+        );
+
+        // with the whitespace kept by the parser, the document comes back as it was written
+        let parse_options = ParseOptions { preserve_whitespace: true, ..ParseOptions::new() };
+        let kept = ElementTree::parse_with_options(doc_raw.as_bytes(), &parse_options).unwrap();
+        let preserve = WriteOptions { self_closing: false, ..WriteOptions::preserve() };
+        assert_eq!(written(&kept, &preserve), doc_raw);
+
+        let awkward: Element = Element::builder("a").attr("gt", "1 > 0\n").text("]]> & \r").into();
+        assert_eq!(awkward.to_string(), "<a gt=\"1 > 0&#xA;\">]]&gt; &amp; &#xD;</a>");
+        let escaped = WriteOptions { escape_gt: true, ..WriteOptions::new() };
+        assert_eq!(awkward.to_string_with(&escaped).unwrap(), "<a gt=\"1 &gt; 0&#xA;\">]]&gt; &amp; &#xD;</a>");
+        assert_eq!(ElementTree::parse_str(&awkward.to_string()).unwrap().root.unwrap().text, awkward.text);
+    }
+
+    #[test]
+    fn test_lossless_round_trip() {
+        use crate::element::{Element, Node, Text};
+
+        let written = |doc: &ElementTree| {
+            let mut out = Vec::new();
+            doc.write(&mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let config = "<?xml version='1.0'?>\r\n<!-- service settings -->\r\n<config  version = '2' >\r\n\t<server host=\"example.org\" port='8080'/>\r\n\t<motd><![CDATA[<b>hi</b>]]> &amp; &#x2014; bye</motd>\r\n\t<users>\r\n\t\t<user name='ann' />\r\n\t</users>\r\n\t<?reload now?>\r\n</config >\r\n<!-- end -->\r\n";
+        let mut doc = ElementTree::parse_lossless(config.as_bytes()).unwrap();
+        assert_eq!(written(&doc), config);
+
+        // only the edited start tag is written afresh
+        let root = doc.root.as_mut().unwrap();
+        root.children[0].attributes.insert("port", "9090");
+        assert_eq!(
+            written(&doc),
+            config.replace("<server host=\"example.org\" port='8080'/>", "<server host=\"example.org\" port=\"9090\" />")
+        );
+
+        // new content goes between the original tags
+        let root = doc.root.as_mut().unwrap();
+        let users = &mut root.children[2];
+        let index = users.nodes.len() - 1;
+        users.nodes.insert(index, Node::Element(users.children.len()));
+        users.nodes.insert(index, Node::Text(Text { value: "\n\t\t".to_string(), span: None }));
+        users.children.push(Element::builder("user").attr("name", "bob & co").into());
+        users.text = Some(users.nodes.iter().filter_map(|node| match node {
+            Node::Text(text) => Some(text.value.as_str()),
+            _ => None,
+        }).collect());
+        let expected = config
+            .replace("<server host=\"example.org\" port='8080'/>", "<server host=\"example.org\" port=\"9090\" />")
+            .replace("<user name='ann' />", "<user name='ann' />\r\n\t\t<user name=\"bob &amp; co\" />");
+        assert_eq!(written(&doc), expected);
+
+        // a reparsed copy of the output keeps its layout as well
+        let reparsed = ElementTree::parse_lossless(expected.as_bytes()).unwrap();
+        assert_eq!(written(&reparsed), expected);
+
+        // edits around the root rewrite only the prolog or epilog
+        doc.epilog.clear();
+        assert!(written(&doc).ends_with("</config >"));
+        doc.prolog.clear();
+        assert!(written(&doc).starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\r\n<config  version = '2' >"));
+
+        // without the source, the tree is written as usual
+        doc.lossless = None;
+        assert!(written(&doc).contains("<config version=\"2\">"));
+
+        let latin1 = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><menu>caf\xe9 <item  id='1'/></menu>";
+        let mut doc = ElementTree::parse_lossless(&latin1[..]).unwrap();
+        let mut out = Vec::new();
+        doc.write(&mut out).unwrap();
+        assert_eq!(out, &latin1[..]);
+        doc.root.as_mut().unwrap().children[0].attributes.insert("id", "2");
+        out.clear();
+        doc.write(&mut out).unwrap();
+        assert_eq!(out, b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><menu>caf\xe9 <item id=\"2\" /></menu>");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_conversion() {
+        use crate::json::{Convention, JsonOptions};
+        use serde_json::json;
+
+        let doc_raw = r#"<catalog xmlns:h="urn:h" id="c1"><book id="1"><title>Rain</title><price>5.95</price></book><book id="2"><title>Snow</title><h:stock>true</h:stock></book><note>Ann <b>Lee</b> jr</note><empty/></catalog>"#;
+        let doc = ElementTree::parse_str(doc_raw).unwrap();
+
+        let simple = JsonOptions::new(Convention::Simple);
+        let value = doc.to_json(&simple);
+        assert_eq!(
+            value,
+            json!({"catalog": {
+                "@xmlns:h": "urn:h",
+                "@id": "c1",
+                "book": [
+                    {"@id": "1", "title": "Rain", "price": "5.95"},
+                    {"@id": "2", "title": "Snow", "h:stock": "true"}
+                ],
+                "note": {"#text": ["Ann ", " jr"], "b": "Lee"},
+                "empty": null
+            }})
+        );
+        // children come back grouped by name, which this document already is
+        let back = ElementTree::from_json(&value, &simple).unwrap();
+        let compact = crate::serialize::WriteOptions::compact();
+        let expected = doc_raw.replace("Ann <b>Lee</b> jr", "Ann  jr<b>Lee</b>");
+        assert_eq!(back.root.unwrap().to_string_with(&compact).unwrap(), expected);
+
+        let badgerfish = JsonOptions::new(Convention::BadgerFish);
+        let value = doc.to_json(&badgerfish);
+        assert_eq!(value["catalog"]["@xmlns"], json!({"h": "urn:h"}));
+        assert_eq!(value["catalog"]["book"][1], json!({"@id": "2", "title": {"$": "Snow"}, "h:stock": {"$": "true"}}));
+        assert_eq!(value["catalog"]["empty"], json!({}));
+        let back = ElementTree::from_json(&value, &badgerfish).unwrap();
+        assert_eq!(back.to_json(&badgerfish), value);
+
+        let parker = JsonOptions::new(Convention::Parker);
+        let value = doc.to_json(&parker);
+        assert_eq!(
+            value,
+            json!({
+                "book": [{"title": "Rain", "price": 5.95}, {"title": "Snow", "h:stock": true}],
+                "note": {"b": "Lee"},
+                "empty": null
+            })
+        );
+        let back = ElementTree::from_json(&json!({"item": [1, "two", null]}), &JsonOptions { root: "list".to_string(), ..parker });
+        assert_eq!(back.unwrap().root.unwrap().to_string(), "<list>\n  <item>1</item>\n  <item>two</item>\n  <item />\n</list>");
+
+        let err = ElementTree::from_json(&json!({"a": 1, "b": 2}), &simple).err().unwrap();
+        assert_eq!(err.kind(), crate::errors::XmlErrorKind::Conversion);
+        assert!(ElementTree::from_json(&json!({"not a name": 1}), &simple).is_err());
+        assert!(ElementTree::from_json(&json!({"a": {"@id": {"x": 1}}}), &simple).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, PartialEq, Deserialize, Serialize)]
+        #[serde(rename_all = "lowercase")]
+        enum Format {
+            Paperback,
+            Ebook,
+        }
+
+        #[derive(Debug, PartialEq, Deserialize, Serialize)]
+        struct Price {
+            #[serde(rename = "@currency")]
+            currency: String,
+            #[serde(rename = "$text")]
+            amount: f64,
+        }
+
+        #[derive(Debug, PartialEq, Deserialize, Serialize)]
+        #[serde(rename = "book")]
+        struct Book {
+            #[serde(rename = "@id")]
+            id: String,
+            title: String,
+            author: Vec<String>,
+            price: Price,
+            year: Option<u32>,
+            format: Format,
+        }
+
+        let doc = ElementTree::parse_str(
+            r#"<catalog>
+                <book id="b1">
+                    <title>Rain</title>
+                    <author>Ann</author>
+                    <author>Bob</author>
+                    <price currency="EUR">12.50</price>
+                    <format>ebook</format>
+                </book>
+                <book id="b2"><title>Snow</title><author>Cy</author><price currency="USD">8</price><year>2001</year><format>paperback</format></book>
+            </catalog>"#,
+        )
+        .unwrap();
+
+        #[derive(Debug, Deserialize)]
+        struct Catalog {
+            book: Vec<Book>,
+        }
+        let catalog: Catalog = crate::de::from_tree(&doc).unwrap();
+        assert_eq!(catalog.book.len(), 2);
+        let first = &catalog.book[0];
+        assert_eq!(first.id, "b1");
+        assert_eq!(first.author, vec!["Ann", "Bob"]);
+        assert_eq!(first.price, Price { currency: "EUR".to_string(), amount: 12.5 });
+        assert_eq!(first.year, None);
+        assert_eq!(first.format, Format::Ebook);
+
+        // any element, such as one a query found, can be read
+        let found = match doc.query("//book[@id='b2']").unwrap().remove(0) {
+            ReturnEnum::ElementNode(element) => element,
+            ReturnEnum::ElementName(name) => panic!("expected an element, found {}", name),
+        };
+        let second: Book = crate::de::from_element(found).unwrap();
+        assert_eq!(second.year, Some(2001));
+        assert_eq!(second.format, Format::Paperback);
+
+        let element = crate::ser::to_element(&second).unwrap();
+        assert_eq!(
+            element.to_string_with(&crate::WriteOptions::compact()).unwrap(),
+            r#"<book id="b2"><title>Snow</title><author>Cy</author><price currency="USD">8</price><year>2001</year><format>paperback</format></book>"#
+        );
+        assert_eq!(crate::de::from_element::<Book>(&element).unwrap(), second);
+
+        let err = crate::de::from_element::<Price>(&ElementTree::parse_str("<price currency='EUR'>lots</price>").unwrap().root.unwrap())
+            .unwrap_err();
+        assert!(matches!(err, crate::XmlErrors::ValueFromStr { .. }));
+        assert!(crate::de::from_element::<Book>(&found.children[0]).is_err());
+        assert!(crate::ser::to_element(&5).is_err());
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_from_xml_derive() {
+        use crate::errors::XmlErrors;
+        use crate::FromXml;
+
+        #[derive(Debug, PartialEq, FromXml)]
+        struct Price {
+            #[xpath("@currency")]
+            currency: String,
+            #[xpath(".")]
+            amount: f64,
+        }
+
+        #[derive(Debug, PartialEq, FromXml)]
+        struct Book {
+            #[xpath("@id")]
+            id: String,
+            #[xpath("title")]
+            title: String,
+            #[xpath("author")]
+            authors: Vec<String>,
+            #[xpath("price")]
+            price: Price,
+            #[xpath("price/@currency")]
+            currency: String,
+            year: Option<u32>,
+        }
+
+        #[derive(Debug, FromXml)]
+        struct Catalog {
+            #[xpath("book")]
+            books: Vec<Book>,
+            #[xpath("//title")]
+            titles: Vec<String>,
+        }
+
+        let doc = ElementTree::parse_str(
+            r#"<catalog>
+                <book id="b1"><title>Rain</title><author>Ann</author><author>Bob</author><price currency="EUR">12.50</price></book>
+                <book id="b2"><title>Snow</title><author>Cy</author><price currency="USD">8</price><year>2001</year></book>
+            </catalog>"#,
+        )
+        .unwrap();
+        let catalog = Catalog::from_tree(&doc).unwrap();
+        assert_eq!(catalog.titles, vec!["Rain", "Snow"]);
+        assert_eq!(
+            catalog.books[0],
+            Book {
+                id: "b1".to_string(),
+                title: "Rain".to_string(),
+                authors: vec!["Ann".to_string(), "Bob".to_string()],
+                price: Price { currency: "EUR".to_string(), amount: 12.5 },
+                currency: "EUR".to_string(),
+                year: None,
+            }
+        );
+        assert_eq!(catalog.books[1].year, Some(2001));
+
+        // relative paths start from the element, prefix and all
+        #[derive(Debug, FromXml)]
+        struct Shelf {
+            #[xpath("h:label")]
+            label: String,
+            #[xpath(".//title")]
+            titles: Vec<String>,
+            #[xpath("./box/title")]
+            boxed: String,
+        }
+        let doc = ElementTree::parse_str(
+            r#"<h:shelf xmlns:h="urn:shelf"><h:label>New</h:label><box><title>Rain</title></box><book><title>Snow</title></book></h:shelf>"#,
+        )
+        .unwrap();
+        let shelf = Shelf::from_tree(&doc).unwrap();
+        assert_eq!(shelf.label, "New");
+        assert_eq!(shelf.titles, vec!["Rain", "Snow"]);
+        assert_eq!(shelf.boxed, "Rain");
+
+        // the error names the path and the type that failed
+        let doc = ElementTree::parse_str(r#"<book id="b3"><title>Fog</title><price currency="EUR">cheap</price></book>"#).unwrap();
+        let err = Book::from_tree(&doc).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "Cannot read 'price' as Price: Cannot read '.' as f64: Value 'cheap' cannot be parsed"
+        );
+        assert_eq!(err.kind(), crate::errors::XmlErrorKind::Conversion);
+        let doc = ElementTree::parse_str(r#"<book><title>Fog</title></book>"#).unwrap();
+        match Book::from_tree(&doc).unwrap_err() {
+            XmlErrors::FieldError { path, ty, .. } => assert_eq!((path.as_str(), ty.as_str()), ("@id", "String")),
+            err => panic!("unexpected error {}", err),
+        }
+
+        // values and errors are those of `select_one` and `select_bool`
+        #[derive(Debug, FromXml)]
+        struct Stock {
+            #[xpath("@instock")]
+            instock: bool,
+            #[xpath("count")]
+            count: u32,
+        }
+        let stock = ElementTree::parse_str(r#"<item instock="1"><count> 3 </count></item>"#).unwrap();
+        let read = Stock::from_tree(&stock).unwrap();
+        assert!(read.instock);
+        assert_eq!(read.count, 3);
+        assert_eq!(stock.select_bool("@instock").unwrap(), read.instock);
+        assert!(!Stock::from_tree(&ElementTree::parse_str(r#"<item instock="0"><count>3</count></item>"#).unwrap()).unwrap().instock);
+        let field_source = |xml: &str| match Stock::from_tree(&ElementTree::parse_str(xml).unwrap()) {
+            Err(XmlErrors::FieldError { source, .. }) => *source,
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        };
+        assert!(matches!(
+            field_source(r#"<item instock="1"/>"#),
+            XmlErrors::ElementNotFound { t } if t == "count"
+        ));
+        assert!(matches!(
+            field_source(r#"<item instock="1"><count>1</count><count>2</count></item>"#),
+            XmlErrors::QueryError { source: crate::errors::QueryError::MultipleMatches(2) }
+        ));
+        assert!(matches!(
+            field_source(r#"<item instock="yes"><count>1</count></item>"#),
+            XmlErrors::ValueFromStr { t } if t == "yes"
+        ));
+    }
+
+    #[test]
+    fn test_select_values() {
+        use crate::errors::{QueryError, XmlErrors};
+
+        let doc = ElementTree::parse_str(
+            r#"<catalog>
+                <book id="b1" available="true"><title>Rain</title><price> 12.50 </price><year>1999</year></book>
+                <book id="b2" available="0"><title>Snow</title><price>8</price><year>2001</year></book>
+            </catalog>"#,
+        )
+        .unwrap();
+        assert_eq!(doc.select_str("//book[@id='b2']/title").unwrap(), "Snow");
+        assert_eq!(doc.select_str("//book[@id='b2']/@available").unwrap(), "0");
+        assert_eq!(doc.select_f64("//book[@id='b1']/price").unwrap(), 12.5);
+        assert!(!doc.select_bool("//book[@id='b2']/@available").unwrap());
+        assert_eq!(doc.select_one::<u32>("//book[@id='b1']/year").unwrap(), 1999);
+        assert_eq!(doc.select_all::<u32>("//year").unwrap(), vec![1999, 2001]);
+        assert_eq!(doc.select_all::<String>("/catalog/book/@id").unwrap(), vec!["b1", "b2"]);
+        assert_eq!(doc.select_all::<f64>("//isbn").unwrap(), Vec::<f64>::new());
+
+        // on an element, a lone attribute step reads the element's own attribute
+        let book = doc.root.as_ref().unwrap().children.first().unwrap();
+        assert_eq!(book.select_str("@id").unwrap(), "b1");
+        assert!(book.select_bool("@available").unwrap());
+        assert_eq!(book.select_str("//title").unwrap(), "Rain");
+        // and other paths are relative to it, as in `#[xpath]`
+        assert_eq!(book.select_all::<String>("title").unwrap(), vec!["Rain"]);
+        assert_eq!(book.select_f64("./price").unwrap(), 12.5);
+        assert_eq!(book.select_str(".//title").unwrap(), "Rain");
+        // steps from an element named like its children select those children, not the element
+        let items = ElementTree::parse_str("<item><item>inner<item>deep</item></item></item>").unwrap();
+        let outer = items.root.as_ref().unwrap();
+        assert_eq!(outer.select_all::<String>("item").unwrap(), vec!["inner"]);
+        assert_eq!(outer.select_all::<String>("item/item").unwrap(), vec!["deep"]);
+        assert_eq!(outer.select_all::<String>(".//item").unwrap(), vec!["inner", "deep"]);
+        assert_eq!(outer.select_all::<String>("/item/item").unwrap(), vec!["inner"]);
+        assert_eq!(outer.select_all::<String>("/item//item").unwrap(), vec!["inner", "deep"]);
+        assert_eq!(outer.select_all::<String>("/item/descendant::item").unwrap(), vec!["inner", "deep"]);
+        assert_eq!(outer.select_str(".").unwrap(), "");
+        // a path from `/` takes the element as the root, so it does not reach its ancestors
+        let nested = ElementTree::parse_str("<r><s id='1'/><s id='2'><s id='3'/></s></r>").unwrap();
+        let second = &nested.root.as_ref().unwrap().children[1];
+        assert_eq!(second.select_all::<String>("/r/s").unwrap(), Vec::<String>::new());
+        assert_eq!(second.select_all::<String>("/s/s/@id").unwrap(), vec!["3"]);
+        assert_eq!(second.select_all::<String>("s/@id").unwrap(), vec!["3"]);
+
+        assert!(matches!(doc.select_str("//isbn"), Err(XmlErrors::ElementNotFound { t }) if t == "//isbn"));
+        // a predicate or position that matches nothing selects nothing
+        assert!(matches!(doc.select_str("//book[@id='b9']"), Err(XmlErrors::ElementNotFound { .. })));
+        assert!(matches!(doc.select_one::<u32>("//book[@id='b9']/year"), Err(XmlErrors::ElementNotFound { .. })));
+        assert!(matches!(doc.select_str("//book[3]"), Err(XmlErrors::ElementNotFound { .. })));
+        assert_eq!(doc.select_all::<String>("//x[@id='1']").unwrap(), Vec::<String>::new());
+        assert_eq!(doc.query("//book[@id='b9']/title").unwrap().len(), 0);
+        assert!(matches!(
+            doc.select_f64("//price"),
+            Err(XmlErrors::QueryError { source: QueryError::MultipleMatches(2) })
+        ));
+        assert!(matches!(doc.select_f64("//book[1]/title"), Err(XmlErrors::ValueFromStr { t }) if t == "Rain"));
+        assert!(matches!(doc.select_bool("//book[@id='b1']/title"), Err(XmlErrors::ValueFromStr { .. })));
+        assert!(matches!(doc.select_all::<u32>("//title"), Err(XmlErrors::ValueFromStr { .. })));
+    }
+
+    #[test]
+    fn test_lazy_query() {
+        use crate::ElementBuilder;
+
+        let doc = ElementTree::parse_str(
+            r#"<c><b id="x"><t>A</t><t>B</t></b><b id="y"><t>C</t><t>D</t><b id="z"><t>E</t></b></b></c>"#,
+        )
+        .unwrap();
+        // the lazy matches are the ones `query` returns, in the same order, or the same error
+        let queries = [
+            "//t[2]",
+            "//t",
+            "/c/b/t",
+            "/c/b[2]/t",
+            "//b[@id='y']/t",
+            "//b//t",
+            "//t/parent::b",
+            "//b/following-sibling::b",
+            "//ancestor::b",
+            "//t/..",
+        ];
+        for xpath in queries {
+            let eager = doc.query(xpath).map(|found| found.iter().map(|node| node.to_string()).collect::<Vec<_>>());
+            let lazy = doc.query_iter(xpath).map(|found| found.map(|node| node.to_string()).collect::<Vec<_>>());
+            match (eager, lazy) {
+                (Ok(eager), Ok(lazy)) => assert_eq!(lazy, eager, "{}", xpath),
+                (Err(eager), Err(lazy)) => assert_eq!(format!("{:#}", lazy), format!("{:#}", eager), "{}", xpath),
+                (eager, lazy) => panic!("{}: query gave {:?}, query_iter gave {:?}", xpath, eager, lazy.map(|_| ())),
+            }
+        }
+        let texts = |xpath: &str| -> Vec<String> {
+            doc.query_iter(xpath).unwrap().map(|node| match node {
+                ReturnEnum::ElementNode(element) => element.text.clone().unwrap_or_default(),
+                ReturnEnum::ElementName(name) => name,
+            }).collect()
+        };
+        assert_eq!(texts("//t[2]"), ["B"]);
+        assert_eq!(texts("/c/b/t"), ["A", "B", "C", "D"]);
+        assert!(doc.query_iter("//b[@id='y']/t").unwrap().exists());
+        assert!(!doc.query_iter("//b/note").unwrap().exists());
+
+        // descendants in document order, a nested match before a shallower one after it
+        let nested = ElementTree::parse_str("<r><a><b>1</b></a><b>2</b></r>").unwrap();
+        let found = |xpath: &str| -> Vec<String> {
+            nested.query(xpath).unwrap().iter().map(|node| node.to_string()).collect()
+        };
+        assert_eq!(found("//b"), ["<b>1</b>", "<b>2</b>"]);
+        assert_eq!(found("/r//b"), ["<b>1</b>", "<b>2</b>"]);
+        match nested.query_iter("//b").unwrap().first() {
+            Some(ReturnEnum::ElementNode(element)) => assert_eq!(element.text.as_deref(), Some("1")),
+            other => panic!("unexpected {:?}", other),
+        }
+        let order: Vec<_> = nested.query_iter("//b").unwrap().map(|node| node.to_string()).collect();
+        let walked: Vec<_> = nested.iter().filter(|e| e.tag == "b").map(|e| e.to_string()).collect();
+        assert_eq!(order, walked);
+
+        // sibling and namespace steps from the elements before them, nothing when there are none
+        let siblings = ElementTree::parse_str("<r><s id='1'/><t/><s id='2'/><s id='3'/></r>").unwrap();
+        let ids = |xpath: &str| -> Vec<String> {
+            siblings.query(xpath).unwrap().iter().map(|node| match node {
+                ReturnEnum::ElementNode(element) => element.attributes.get("id").unwrap_or(&element.tag).to_string(),
+                ReturnEnum::ElementName(name) => name.clone(),
+            }).collect()
+        };
+        assert_eq!(ids("//s[@id='2']/following-sibling::s"), ["3"]);
+        assert_eq!(ids("//s[@id='3']/following-sibling::s"), Vec::<String>::new());
+        assert_eq!(ids("//s[@id='9']/following-sibling::s"), Vec::<String>::new());
+        assert_eq!(ids("//s/following-sibling::s"), ["2", "3"]);
+        assert_eq!(ids("//s[@id='3']/preceding-sibling::s"), ["1", "2"]);
+        assert_eq!(ids("/r/s/preceding-sibling::t"), ["t"]);
+        assert_eq!(ids("//s[@id='9']/preceding-sibling::s"), Vec::<String>::new());
+        assert_eq!(ids("//s[@id='9']/namespace::x"), Vec::<String>::new());
+        assert_eq!(ids("//ancestor-or-self::x"), Vec::<String>::new());
+
+        let mut bridge = Bridge::new(doc, "//t".to_string());
+        bridge.token_step_filler();
+        assert_eq!(bridge.produce_iter().unwrap().count(), bridge.produce().unwrap().len());
+
+        // the last step stops at the first match, however large the rest of the tree
+        let big = ElementTree::new_with_root(
+            ElementBuilder::new("root")
+                .children((0..100_000).map(|i| ElementBuilder::new("item").attr("n", i)))
+                .build(),
+        );
+        match big.query_iter("/root/item").unwrap().first() {
+            Some(ReturnEnum::ElementNode(element)) => assert_eq!(element.attributes.get("n"), Some("0")),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(big.query_iter("//item").unwrap().exists());
+        let on_element = big.root.as_ref().unwrap();
+        assert_eq!(on_element.query_iter("//item[@n='99999']").unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_explain() {
+        use crate::explain::{AstAxis, NodeTest, Predicate};
+        use crate::tokens::AxesName;
+
+        let explain = crate::explain("//book[@id='b2']/title").unwrap();
+        assert_eq!(explain.ast.len(), 2);
+        assert_eq!(explain.ast[0].separator, "//");
+        assert_eq!(explain.ast[0].axis, AstAxis::Implicit);
+        assert_eq!(
+            explain.ast[0].predicates,
+            vec![Predicate::AttributeEquals("id".to_string(), "b2".to_string())]
+        );
+        let steps: Vec<String> = explain.steps.iter().map(|step| step.to_string()).collect();
+        assert_eq!(
+            steps,
+            vec!["descendant-or-self::node()", "child::book[attribute::id = 'b2']", "child::title"]
+        );
+        assert!(explain.evaluation.is_none());
+
+        let explain = crate::explain("/catalog/book[2]/@id").unwrap();
+        assert_eq!(explain.steps[2].axis, AxesName::Attribute);
+        assert_eq!(explain.steps[2].test, NodeTest::Name("id".to_string()));
+        assert_eq!(explain.ast[1].to_string(), "/book[2]");
+        assert!(crate::explain("//book[@id").is_err());
+        assert!(crate::explain("//book/text()").is_err());
+
+        let doc = ElementTree::parse_str(
+            r#"<catalog>
+                <book id="b1"><title>Rain</title></book>
+                <book id="b2"><title>Snow</title><title>Sleet</title></book>
+            </catalog>"#,
+        )
+        .unwrap();
+        let explain = doc.explain("//book[@id='b2']/title").unwrap();
+        let evaluation = |explain: &crate::Explain| -> Vec<(String, usize)> {
+            let evaluation = explain.evaluation.as_ref().unwrap();
+            evaluation.iter().map(|evaluated| (evaluated.step.clone(), evaluated.selected)).collect()
+        };
+        // what the engine itself had after each of its steps, the last one being what it returned
+        assert_eq!(
+            evaluation(&explain),
+            vec![
+                ("descendant-or-self::book".to_string(), 2),
+                ("[attribute::id=b2]".to_string(), 1),
+                ("child::title".to_string(), 2),
+            ]
+        );
+        assert_eq!(explain.returned.as_ref().unwrap().as_ref().unwrap(), &2);
+        let printed = explain.to_string();
+        assert!(printed.contains("tokens: // book [ @ id = b2 ] / title"));
+        assert!(printed.contains("  //book[@id='b2']\n  /title\n"));
+        assert!(printed.contains("  3. child::title\nevaluation:\n"));
+        assert!(printed.contains("  child::title              2 selected\n"));
+        assert!(printed.ends_with("engine returned: 2\n"));
+
+        // the trace stops where the engine gave up
+        let explain = doc.explain("//title/..").unwrap();
+        assert_eq!(evaluation(&explain), vec![("descendant-or-self::title".to_string(), 3)]);
+        assert!(explain.returned.unwrap().is_err());
+        let explain = doc.explain("//book/following-sibling::book").unwrap();
+        assert_eq!(evaluation(&explain).last().unwrap().1, 1);
+        assert!(doc.explain("./book").unwrap().returned.unwrap().is_err());
+
+        let mut bridge = Bridge::new(doc, "//title[2]".to_string());
+        bridge.token_step_filler();
+        let explain = bridge.explain().unwrap();
+        assert_eq!(
+            evaluation(&explain),
+            vec![("descendant-or-self::title".to_string(), 3), ("[2]".to_string(), 1)]
+        );
+        assert_eq!(explain.returned.unwrap().unwrap(), bridge.produce().unwrap().len());
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use crate::element::{Element, Node};
+use crate::position::Span;
+use crate::tree::ElementTree;
+
+/// The source of a tree parsed with `ElementTree::parse_lossless`, and the tree as it was parsed.
+///
+/// Writing the tree copies the source of every node that is still as it was parsed, so only
+/// the edited nodes are serialised again. Set `ElementTree::lossless` to `None` to write the
+/// whole tree afresh.
+pub struct Lossless {
+    /// The input decoded to UTF-8, which the spans of the tree index
+    source: String,
+    original: Box<ElementTree>,
+}
+
+impl Lossless {
+    pub(crate) fn new(source: String, tree: &ElementTree) -> Lossless {
+        let original = ElementTree {
+            root: tree.root.clone(),
+            version: tree.version,
+            encoding: tree.encoding.clone(),
+            doctype: tree.doctype.clone(),
+            prolog: tree.prolog.clone(),
+            epilog: tree.epilog.clone(),
+            lossless: None,
+        };
+        Lossless {
+            source,
+            original: Box::new(original),
+        }
+    }
+
+    ///The source as it was parsed
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    ///Indexes the parsed nodes by where they start
+    pub(crate) fn originals(&self) -> Originals<'_> {
+        let mut originals = Originals {
+            lossless: self,
+            elements: HashMap::new(),
+            nodes: HashMap::new(),
+        };
+        for element in self.original.iter() {
+            if let Some(span) = element.span {
+                originals.elements.insert(span.start.offset, element);
+            }
+            for node in &element.nodes {
+                if let Some(span) = span_of(node) {
+                    originals.nodes.insert(span.start.offset, node);
+                }
+            }
+        }
+        originals
+    }
+}
+
+/// The parsed nodes of a lossless tree, to compare the nodes being written with
+pub(crate) struct Originals<'e> {
+    lossless: &'e Lossless,
+    elements: HashMap<usize, &'e Element>,
+    nodes: HashMap<usize, &'e Node>,
+}
+
+impl<'e> Originals<'e> {
+    fn slice(&self, span: Span) -> &'e str {
+        &self.lossless.source[span.start.offset..span.end.offset]
+    }
+
+    ///The parsed element `element` comes from, with the same span
+    fn original(&self, element: &Element) -> Option<&'e Element> {
+        let span = element.span?;
+        self.elements.get(&span.start.offset).copied().filter(|original| original.span == Some(span))
+    }
+
+    ///The source of `element` if neither it nor its content was edited
+    pub(crate) fn element(&self, element: &Element) -> Option<&'e str> {
+        let original = self.original(element).filter(|&original| original == element)?;
+        Some(self.slice(original.span?))
+    }
+
+    ///The source of the start and end tags of `element` if only its content was edited
+    pub(crate) fn tags(&self, element: &Element) -> Option<(&'e str, &'e str)> {
+        let original = self.original(element)?;
+        if original.namespace != element.namespace || original.tag != element.tag || original.attributes != element.attributes {
+            return None;
+        }
+        let source = self.slice(original.span?);
+        let start_tag = &source[..start_tag_len(source)];
+        // an empty-element tag has no end tag to put content before
+        if start_tag.ends_with("/>") {
+            return None;
+        }
+        Some((start_tag, &source[source.rfind("</")?..]))
+    }
+
+    ///The source of a text, comment or processing instruction if it was not edited
+    pub(crate) fn node(&self, node: &Node) -> Option<&'e str> {
+        let span = span_of(node)?;
+        let original = self.nodes.get(&span.start.offset).filter(|&&original| original == node)?;
+        (span_of(original) == Some(span)).then(|| self.slice(span))
+    }
+
+    ///The source before the root element if the declaration, DOCTYPE and prolog were not edited
+    pub(crate) fn prolog(&self, tree: &ElementTree) -> Option<&'e str> {
+        let original = &self.lossless.original;
+        if tree.version != original.version
+            || tree.encoding != original.encoding
+            || tree.doctype != original.doctype
+            || tree.prolog != original.prolog
+        {
+            return None;
+        }
+        let end = original.root.as_ref().and_then(|root| root.span)?.start.offset;
+        Some(&self.lossless.source[..end])
+    }
+
+    ///The source after the root element if the epilog was not edited
+    pub(crate) fn epilog(&self, tree: &ElementTree) -> Option<&'e str> {
+        let original = &self.lossless.original;
+        if tree.epilog != original.epilog {
+            return None;
+        }
+        let start = original.root.as_ref().and_then(|root| root.span)?.end.offset;
+        Some(&self.lossless.source[start..])
+    }
+}
+
+fn span_of(node: &Node) -> Option<Span> {
+    match *node {
+        Node::Element(_) => None,
+        Node::Text(ref text) => text.span,
+        Node::Comment(ref comment) => comment.span,
+        Node::ProcessingInstruction(ref pi) => pi.span,
+    }
+}
+
+///The length of the start tag `source` begins with
+fn start_tag_len(source: &str) -> usize {
+    let mut quote = None;
+    for (index, byte) in source.bytes().enumerate() {
+        match (quote, byte) {
+            (Some(q), _) if q == byte => quote = None,
+            (Some(_), _) => {}
+            (None, b'"' | b'\'') => quote = Some(byte),
+            (None, b'>') => return index + 1,
+            _ => {}
+        }
+    }
+    source.len()
+}
@@ -1,16 +1,88 @@
-use crate::errors::Error;
-use crate::translator::{Bridge, ReturnEnum};
-use crate::tree::ElementTree;
+use XMLParserNav::Error;
+use XMLParserNav::{Bridge, ReturnEnum};
+use XMLParserNav::ElementTree;
 
-
-mod tree;
-mod element;
-mod errors;
-mod parser;
-mod translator;
-mod tokens;
+const USAGE: &str = "usage: XMLParserNav to-json|from-json [--convention simple|badgerfish|parker] [--root NAME] [FILE]
+       XMLParserNav explain XPATH [FILE]";
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        #[cfg(feature = "json")]
+        Some(command @ ("to-json" | "from-json")) => {
+            if let Err(err) = convert(command, &args[1..]) {
+                eprintln!("{:#}", err);
+                std::process::exit(1);
+            }
+        }
+        Some("explain") if (2..=3).contains(&args.len()) => {
+            if let Err(err) = explain(&args[1], args.get(2)) {
+                eprintln!("{:#}", err);
+                std::process::exit(1);
+            }
+        }
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+        None => demo(),
+    }
+}
+
+///Converts FILE, or standard input, between XML and JSON and prints the result
+#[cfg(feature = "json")]
+fn convert(command: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::{self, Read, Write};
+    use XMLParserNav::{Convention, JsonOptions};
+
+    let mut options = JsonOptions::default();
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--convention" => {
+                options.convention = match args.next().map(String::as_str) {
+                    Some("simple") => Convention::Simple,
+                    Some("badgerfish") => Convention::BadgerFish,
+                    Some("parker") => Convention::Parker,
+                    _ => return Err(USAGE.into()),
+                }
+            }
+            "--root" => options.root = args.next().ok_or(USAGE)?.clone(),
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    let input: Box<dyn Read> = match path {
+        Some(path) => Box::new(XMLParserNav::input::open(path)?),
+        None => Box::new(io::stdin()),
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if command == "to-json" {
+        let doc = ElementTree::parse(input)?;
+        serde_json::to_writer_pretty(&mut out, &doc.to_json(&options))?;
+    } else {
+        let value = serde_json::from_reader(input)?;
+        ElementTree::from_json(&value, &options)?.write(&mut out)?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+///Prints how XPATH is read and, if FILE is given, what each of its steps selects there
+fn explain(xpath: &str, path: Option<&String>) -> Result<(), Box<dyn std::error::Error>> {
+    let explain = match path {
+        Some(path) => ElementTree::parse(XMLParserNav::input::open(path)?)?.explain(xpath)?,
+        None => XMLParserNav::explain(xpath)?,
+    };
+    print!("{}", explain);
+    Ok(())
+}
+
+///Runs a sample query against a small catalog
+fn demo() {
     let doc_raw = r#"
     <?xml version="1.1" encoding="UTF-8"?>
     <catalog> catalog test
@@ -0,0 +1,159 @@
+use std::fmt;
+use crate::errors::{ParseError, ParseErrorKind};
+use crate::position::Location;
+
+/// Output size below which entity amplification is not checked
+const AMPLIFICATION_THRESHOLD: usize = 1 << 20;
+
+/// Limits applied while parsing, for documents that cannot be trusted.
+///
+/// The defaults are generous for ordinary documents and stop the usual attacks;
+/// set a field to `usize::MAX` to lift a limit. They also apply to `ElementTree::parse`
+/// and `ElementTree::parse_str`, which therefore reject documents nested more than 256 deep.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// How deeply elements may nest, 256 by default
+    pub max_depth: usize,
+    /// How many elements and text nodes the tree may hold
+    pub max_nodes: usize,
+    /// How many attributes one element may have
+    pub max_attributes: usize,
+    /// Longest element or attribute name, in bytes
+    pub max_name_length: usize,
+    /// Longest text node, attribute value or entity replacement text, in bytes
+    pub max_text_length: usize,
+    /// How many entity references may be expanded in the DOCTYPE's entity values
+    pub max_entity_expansions: usize,
+    /// How many times larger than the input the text may grow through entities,
+    /// checked once the text passes 1 MiB, and how many times longer than the DOCTYPE
+    /// the replacement text of one of its entities may be
+    pub max_entity_amplification: usize,
+    /// Whether external entities are read from the files their system identifiers name
+    pub resolve_external_entities: bool,
+    /// Whether whitespace-only text between tags is kept as text nodes
+    pub preserve_whitespace: bool,
+}
+
+/// The options of parses that were not given any
+pub(crate) static DEFAULT_OPTIONS: ParseOptions = ParseOptions {
+    max_depth: 256,
+    max_nodes: 10_000_000,
+    max_attributes: 1024,
+    max_name_length: 10_000,
+    max_text_length: 10_000_000,
+    max_entity_expansions: 10_000,
+    max_entity_amplification: 10,
+    resolve_external_entities: false,
+    preserve_whitespace: false,
+};
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        DEFAULT_OPTIONS.clone()
+    }
+}
+
+impl ParseOptions {
+    ///Creates the default options
+    pub fn new() -> ParseOptions {
+        ParseOptions::default()
+    }
+}
+
+/// The limit a document ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// `max_depth`
+    Depth,
+    /// `max_nodes`
+    Nodes,
+    /// `max_attributes`
+    Attributes,
+    /// `max_name_length`
+    NameLength,
+    /// `max_text_length`
+    TextLength,
+    /// `max_entity_expansions`
+    EntityExpansions,
+    /// `max_entity_amplification`
+    EntityAmplification,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Limit::Depth => "nesting depth",
+            Limit::Nodes => "number of nodes",
+            Limit::Attributes => "number of attributes",
+            Limit::NameLength => "name length",
+            Limit::TextLength => "text length",
+            Limit::EntityExpansions => "number of entity expansions",
+            Limit::EntityAmplification => "entity amplification",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What a parse has used up of its `ParseOptions`
+pub(crate) struct Budget<'o> {
+    pub(crate) options: &'o ParseOptions,
+    depth: usize,
+    nodes: usize,
+    /// Bytes of text and attribute values produced
+    output: usize,
+}
+
+impl<'o> Budget<'o> {
+    pub(crate) fn new(options: &'o ParseOptions) -> Budget<'o> {
+        Budget {
+            options,
+            depth: 0,
+            nodes: 0,
+            output: 0,
+        }
+    }
+
+    fn check(exceeded: bool, limit: Limit, location: Location) -> Result<(), ParseError> {
+        if exceeded {
+            Err(ParseError::new(ParseErrorKind::LimitExceeded(limit), Some(location)))
+        } else {
+            Ok(())
+        }
+    }
+
+    ///Accounts for an element starting at `location`, nested one level deeper
+    pub(crate) fn enter(&mut self, qname: &str, attributes: usize, location: Location) -> Result<(), ParseError> {
+        self.depth += 1;
+        self.nodes += 1;
+        Budget::check(self.depth > self.options.max_depth, Limit::Depth, location)?;
+        Budget::check(self.nodes > self.options.max_nodes, Limit::Nodes, location)?;
+        Budget::check(attributes > self.options.max_attributes, Limit::Attributes, location)?;
+        Budget::check(qname.len() > self.options.max_name_length, Limit::NameLength, location)
+    }
+
+    ///Accounts for the end of the element entered last
+    pub(crate) fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    ///Accounts for an attribute
+    pub(crate) fn attribute(&mut self, qname: &str, value: &str, location: Location, read: usize) -> Result<(), ParseError> {
+        Budget::check(qname.len() > self.options.max_name_length, Limit::NameLength, location)?;
+        self.value(value, location, read)
+    }
+
+    ///Accounts for a text node, `read` being how many bytes of input were read so far
+    pub(crate) fn text(&mut self, text: &str, location: Location, read: usize) -> Result<(), ParseError> {
+        self.nodes += 1;
+        Budget::check(self.nodes > self.options.max_nodes, Limit::Nodes, location)?;
+        self.value(text, location, read)
+    }
+
+    fn value(&mut self, value: &str, location: Location, read: usize) -> Result<(), ParseError> {
+        Budget::check(value.len() > self.options.max_text_length, Limit::TextLength, location)?;
+        self.output += value.len();
+        let amplified = self.output > AMPLIFICATION_THRESHOLD
+            && self.output / self.options.max_entity_amplification.max(1) > read;
+        Budget::check(amplified, Limit::EntityAmplification, location)
+    }
+}
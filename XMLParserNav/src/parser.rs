@@ -1,8 +1,8 @@
-use crate::errors::Error;
+use crate::errors::QueryError;
 use crate::tokens::{AxesName, ValidToken};
 
 
-pub static TOKEN_LIST: [(&'static str, ValidToken); 11] = [
+pub static TOKEN_LIST: [(&'static str, ValidToken); 12] = [
     ("..", ValidToken::Parent),
     ("//", ValidToken::DoubleForwardSlash),
     (".", ValidToken::Period),
@@ -13,7 +13,8 @@ pub static TOKEN_LIST: [(&'static str, ValidToken); 11] = [
     (")", ValidToken::RightParen),
     ("@", ValidToken::AtSign),
     ("::", ValidToken::LocationStep),
-    ("=", ValidToken::Equal)
+    ("=", ValidToken::Equal),
+    ("*", ValidToken::Asterisk)
 ];
 
 
@@ -49,12 +50,17 @@ impl Token {
         }
     }
 
+    ///The XPath being tokenized
+    pub fn xpath(&self) -> &str {
+        &self.xpath
+    }
+
     pub fn is_end(&self) -> bool {
         self.xpath.len() <= self.pos
     }
 
 
-    pub fn next_func(&mut self) -> Result<ValidToken, Error> {
+    pub fn next_func(&mut self) -> Result<ValidToken, QueryError> {
         /*let _remaining_path = {
             // let k = self.xpath.chars().nth(self.pos).unwrap();
             while self.xpath.chars().nth(self.pos).unwrap() == ' ' {
@@ -74,7 +80,7 @@ impl Token {
             Ok(token)
         } else {
             self.pos = self.xpath.len();
-            Err(Error::Token)
+            Err(QueryError::Token)
         }
     }
 
@@ -108,17 +114,35 @@ impl Token {
     }
 
     fn parse_literal(&mut self) -> Option<(usize, ValidToken)> {
-        let a_ascii = 'a' as u32;
+        let remaining = &self.xpath[self.pos..];
+        let first = remaining.chars().next()?;
+        if first == '\'' || first == '"' {
+            let end = remaining[1..].find(first)?;
+            return Some((end + 2, ValidToken::Literal(remaining[1..end + 1].to_string())));
+        }
+
         let mut end_pos: usize = 0;
-        for i in self.xpath[self.pos..].chars() {
-            if (a_ascii <= i.to_ascii_lowercase() as u32) && (i.to_ascii_lowercase() as u32 <= a_ascii + 25) {
-                end_pos += 1;
+        let mut seen_colon = false;
+        let mut chars = remaining.chars().peekable();
+        while let Some(i) = chars.next() {
+            let name_start = i.is_ascii_alphabetic() || i == '_';
+            let allowed = if end_pos == 0 {
+                name_start
+            } else if i == ':' {
+                // a prefix separator, not the '::' of an axis
+                let prefixed = !seen_colon && chars.peek().map_or(false, |c| c.is_ascii_alphabetic() || *c == '_');
+                seen_colon = true;
+                prefixed
             } else {
+                name_start || i.is_ascii_digit() || i == '-'
+            };
+            if !allowed {
                 break;
             }
+            end_pos += 1;
         }
         if end_pos > 0 {
-            return Some((end_pos, ValidToken::Literal(self.xpath[self.pos..(self.pos + end_pos)].parse().unwrap())));
+            return Some((end_pos, ValidToken::Literal(remaining[..end_pos].to_string())));
         }
         None
     }
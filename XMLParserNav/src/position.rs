@@ -0,0 +1,214 @@
+use std::fmt;
+use std::io::{self, Read};
+use xml::common::{Position, TextPosition};
+use xml::reader::{EventReader, XmlEvent};
+use crate::errors::ParseError;
+
+/// A location in the source document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Location {
+    /// Line number, counting from 1
+    pub line: u64,
+    /// Column in characters, counting from 1
+    pub column: u64,
+    /// Byte offset from the start of the input
+    pub offset: usize,
+}
+
+impl Location {
+    pub(crate) fn start() -> Location {
+        Location {
+            line: 1,
+            column: 1,
+            offset: 0,
+        }
+    }
+
+    pub(crate) fn advance(&mut self, byte: u8) {
+        self.offset += 1;
+        if byte == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else if byte & 0xC0 != 0x80 {
+            // only the first byte of a character moves the column
+            self.column += 1;
+        }
+    }
+
+    ///Turns a location counted from `base` into one counted from the start of the input
+    pub(crate) fn from_base(self, base: Location) -> Location {
+        Location {
+            line: base.line + self.line - 1,
+            column: if self.line == 1 { base.column + self.column - 1 } else { self.column },
+            offset: base.offset + self.offset,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// The part of the source a node was parsed from, `end` being exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    /// Where the node starts
+    pub start: Location,
+    /// Just past the end of the node
+    pub end: Location,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// Location just past `bytes`, counted from the start of the input
+pub(crate) fn location_after(bytes: &[u8]) -> Location {
+    let mut loc = Location::start();
+    for &byte in bytes {
+        loc.advance(byte);
+    }
+    loc
+}
+
+/// Reader handed to xml-rs which keeps the bytes read since the last event,
+/// so that the line/column positions of xml-rs can be turned into `Location`s
+pub(crate) struct SourceReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    base: Location,
+}
+
+impl<R: Read> SourceReader<R> {
+    pub(crate) fn new(inner: R) -> SourceReader<R> {
+        SourceReader {
+            inner,
+            buf: Vec::new(),
+            base: Location::start(),
+        }
+    }
+
+    ///The wrapped reader
+    pub(crate) fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    ///Location of the xml-rs position `pos`, which must not lie before the kept bytes
+    pub(crate) fn locate(&self, pos: TextPosition) -> Location {
+        let (line, column) = (pos.row + 1, pos.column + 1);
+        let mut loc = self.base;
+        for &byte in &self.buf {
+            if loc.line > line || (loc.line == line && loc.column >= column) {
+                break;
+            }
+            loc.advance(byte);
+        }
+        // step over the continuation bytes of the last character
+        let mut index = loc.offset - self.base.offset;
+        while index < self.buf.len() && self.buf[index] & 0xC0 == 0x80 {
+            loc.offset += 1;
+            index += 1;
+        }
+        loc
+    }
+
+    ///Location of the byte `offset`, which must lie within the kept bytes
+    pub(crate) fn locate_offset(&self, offset: usize) -> Location {
+        let mut loc = self.base;
+        for &byte in &self.buf[..offset - self.base.offset] {
+            loc.advance(byte);
+        }
+        loc
+    }
+
+    ///Location just past the last byte handed to the parser
+    pub(crate) fn current(&self) -> Location {
+        self.locate_offset(self.base.offset + self.buf.len())
+    }
+
+    ///The kept bytes from `offset` up to the current location
+    pub(crate) fn bytes_from(&self, offset: usize) -> &[u8] {
+        &self.buf[offset - self.base.offset..]
+    }
+
+    ///Location of the `<` of the markup at `loc`, which xml-rs may report past the start of a tag
+    pub(crate) fn tag_start(&self, loc: Location) -> Location {
+        let index = loc.offset - self.base.offset;
+        if self.buf.get(index) == Some(&b'<') {
+            return loc;
+        }
+        match self.buf[..index].iter().rposition(|&byte| byte == b'<') {
+            Some(found) => self.locate_offset(self.base.offset + found),
+            None => loc,
+        }
+    }
+
+    ///Forgets the bytes before `loc`, keeping the tag it may lie in for `tag_start`
+    pub(crate) fn discard_before(&mut self, loc: Location) {
+        let keep = self.tag_start(loc);
+        self.buf.drain(..keep.offset - self.base.offset);
+        self.base = keep;
+    }
+}
+
+impl<R: Read> Read for SourceReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(out)?;
+        self.buf.extend_from_slice(&out[..read]);
+        Ok(read)
+    }
+}
+
+/// Reads the next event, turning xml-rs errors into located `ParseError`s
+pub(crate) fn next_event<R: Read>(
+    xml_reader: &mut EventReader<SourceReader<R>>,
+) -> Result<XmlEvent, ParseError> {
+    xml_reader.next().map_err(|err| {
+        let location = xml_reader.source().locate(err.position());
+        ParseError::from_xml(err, location)
+    })
+}
+
+/// Finds the spans of the attributes in the raw start tag `tag`, which begins at `start`.
+/// Namespace declarations are included as they appear in the source.
+pub(crate) fn attribute_offsets(tag: &[u8], start: usize) -> Vec<(String, usize, usize)> {
+    let mut found = Vec::new();
+    let mut i = 1;
+    while i < tag.len() && !is_space(tag[i]) && tag[i] != b'>' && tag[i] != b'/' {
+        i += 1;
+    }
+    loop {
+        while i < tag.len() && is_space(tag[i]) {
+            i += 1;
+        }
+        if i >= tag.len() || tag[i] == b'>' || tag[i] == b'/' {
+            return found;
+        }
+        let name_start = i;
+        while i < tag.len() && !is_space(tag[i]) && tag[i] != b'=' {
+            i += 1;
+        }
+        let name = String::from_utf8_lossy(&tag[name_start..i]).into_owned();
+        while i < tag.len() && tag[i] != b'"' && tag[i] != b'\'' {
+            i += 1;
+        }
+        if i >= tag.len() {
+            return found;
+        }
+        let quote = tag[i];
+        i += 1;
+        while i < tag.len() && tag[i] != quote {
+            i += 1;
+        }
+        i += 1;
+        found.push((name, start + name_start, start + i.min(tag.len())));
+    }
+}
+
+fn is_space(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\r' | b'\n')
+}
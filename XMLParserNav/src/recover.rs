@@ -0,0 +1,472 @@
+use std::borrow::Cow;
+use crate::borrowed::{close, within, BorrowedDocument, BorrowedElement};
+use crate::errors::{qualified, ParseError, ParseErrorKind};
+use crate::lexer::{is_name_char, is_name_start, is_space, resolve_entity, BorrowedAttribute, Lexeme, Lexer};
+use crate::options::{Budget, ParseOptions};
+use crate::position::{Location, Span};
+
+/// HTML elements that never have content, closed right after their start tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source", "track", "wbr",
+];
+
+/// HTML elements closed by the start of a sibling with the same name
+const IMPLIED_END: &[&str] = &["p", "li", "dt", "dd", "option", "tr", "td", "th"];
+
+/// A best-effort parse of broken markup, carrying on after every error.
+///
+/// Open elements are closed when a parent or the input ends, stray end tags are dropped,
+/// and markup that cannot be read is kept as text. Each repair adds a diagnostic.
+/// Running into a limit of the `ParseOptions` is not repaired but ends the parse.
+pub(crate) struct Recovery<'a, 'o> {
+    input: &'a str,
+    pos: usize,
+    /// Only used to turn offsets into locations
+    locator: Lexer<'a>,
+    version: Option<&'a str>,
+    encoding: Option<&'a str>,
+    open: Vec<BorrowedElement<'a>>,
+    root: Option<BorrowedElement<'a>>,
+    /// Whether content after the root element put it back on `open`
+    reopened: bool,
+    diagnostics: Vec<ParseError>,
+    /// Counts the open elements apart from the root, which stays counted once it is closed
+    budget: Budget<'o>,
+}
+
+impl<'a, 'o> Recovery<'a, 'o> {
+    ///Parses `input`, returning the document if it had any element and what had to be repaired
+    pub(crate) fn parse(
+        input: &'a str,
+        options: &'o ParseOptions,
+    ) -> Result<(Option<BorrowedDocument<'a>>, Vec<ParseError>), ParseError> {
+        let pos = if input.starts_with('\u{feff}') { 3 } else { 0 };
+        let mut recovery = Recovery {
+            input,
+            pos,
+            locator: Lexer::new(input),
+            version: None,
+            encoding: None,
+            open: Vec::new(),
+            root: None,
+            reopened: false,
+            diagnostics: Vec::new(),
+            budget: Budget::new(options),
+        };
+        while recovery.pos < input.len() {
+            recovery.step()?;
+        }
+        Ok(recovery.finish())
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn locate(&mut self, offset: usize) -> Location {
+        self.locator.locate(offset)
+    }
+
+    fn report(&mut self, kind: ParseErrorKind, offset: usize) {
+        let location = self.locate(offset);
+        self.report_at(kind, location);
+    }
+
+    fn report_at(&mut self, kind: ParseErrorKind, location: Location) {
+        let err = within(ParseError::new(kind, Some(location)), &self.open);
+        self.diagnostics.push(err);
+    }
+
+    fn syntax(&mut self, msg: String, offset: usize) {
+        self.report(ParseErrorKind::Syntax(msg), offset);
+    }
+
+    ///Moves past `end`, or to the end of the input with a diagnostic, returning what came before
+    fn until(&mut self, end: &str) -> &'a str {
+        let rest = self.rest();
+        match rest.find(end) {
+            Some(index) => {
+                self.pos += index + end.len();
+                &rest[..index]
+            }
+            None => {
+                self.report(ParseErrorKind::UnexpectedEof, self.input.len());
+                self.pos = self.input.len();
+                rest
+            }
+        }
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start_matches(is_space).len();
+    }
+
+    ///Reads the longest name at the current position, which may be empty
+    fn name(&mut self) -> (Option<&'a str>, &'a str) {
+        let rest = self.rest();
+        let len = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+        self.pos += len;
+        match rest[..len].split_once(':') {
+            Some((prefix, local)) if !prefix.is_empty() && !local.is_empty() => (Some(prefix), local),
+            _ => (None, &rest[..len]),
+        }
+    }
+
+    fn step(&mut self) -> Result<(), ParseError> {
+        let start = self.pos;
+        let rest = self.rest();
+        if rest.starts_with("<!--") {
+            self.pos += 4;
+            self.until("-->");
+        } else if rest.starts_with("<![CDATA[") {
+            self.pos += 9;
+            let start_loc = self.locate(start);
+            let value = self.until("]]>");
+            self.text(Cow::Borrowed(value), start_loc, true)?;
+        } else if rest.starts_with("<!") {
+            self.doctype();
+        } else if rest.starts_with("<?") {
+            self.pos += 2;
+            self.until("?>");
+            if rest.starts_with("<?xml") && (start == 0 || start == 3 && self.input.starts_with('\u{feff}')) {
+                if let Some(Ok(Lexeme::Declaration { version, encoding, .. })) = Lexer::new(&self.input[..self.pos]).next() {
+                    self.version = Some(version);
+                    self.encoding = encoding;
+                }
+            }
+        } else if rest.starts_with("</") {
+            self.end_tag();
+        } else if rest.starts_with('<') && rest[1..].starts_with(is_name_start) {
+            self.start_tag()?;
+        } else if rest.starts_with('<') {
+            let start_loc = self.locate(start);
+            self.syntax("Unescaped '<' in text".to_string(), start);
+            self.pos += 1;
+            self.text(Cow::Borrowed("<"), start_loc, true)?;
+        } else {
+            let raw = &rest[..rest.find('<').unwrap_or(rest.len())];
+            // located first, as diagnostics in the text move the locator past it
+            let start_loc = self.locate(start);
+            self.pos += raw.len();
+            let value = self.unescape(raw, start);
+            self.text(value, start_loc, false)?;
+        }
+        Ok(())
+    }
+
+    ///Skips a document type declaration or other `<!` markup, with any internal subset
+    fn doctype(&mut self) {
+        let mut depth = 0;
+        for (index, c) in self.rest().char_indices() {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                '>' if depth <= 0 => {
+                    self.pos += index + 1;
+                    return;
+                }
+                _ => {}
+            }
+        }
+        self.report(ParseErrorKind::UnexpectedEof, self.input.len());
+        self.pos = self.input.len();
+    }
+
+    ///Adds text found at `start`, keeping it even when it is whitespace if `keep` is set
+    fn text(&mut self, value: Cow<'a, str>, start: Location, keep: bool) -> Result<(), ParseError> {
+        if !keep && value.chars().all(is_space) {
+            return Ok(());
+        }
+        if self.open.is_empty() {
+            match self.root.take() {
+                // content after the root element goes back into it
+                Some(root) => {
+                    let msg = "Unexpected characters outside the root element".to_string();
+                    self.report_at(ParseErrorKind::Syntax(msg), start);
+                    self.open.push(root);
+                    self.reopened = true;
+                }
+                None => {
+                    let msg = "Unexpected characters outside the root element".to_string();
+                    self.report_at(ParseErrorKind::Syntax(msg), start);
+                    return Ok(());
+                }
+            }
+        }
+        let checked = self.budget.text(&value, start, self.pos);
+        checked.map_err(|err| within(err, &self.open))?;
+        let span = Span {
+            start,
+            end: self.locate(self.pos),
+        };
+        self.open.last_mut().unwrap().push_text(value, span);
+        Ok(())
+    }
+
+    ///Closes the innermost open element, ending it at `end`
+    fn pop(&mut self, end: Location) {
+        let mut element = self.open.pop().unwrap();
+        element.span.end = end;
+        if !self.open.is_empty() {
+            self.budget.leave();
+        }
+        close(element, &mut self.open, &mut self.root);
+    }
+
+    fn start_tag(&mut self) -> Result<(), ParseError> {
+        let start = self.pos;
+        let start_loc = self.locate(start);
+        self.pos += 1;
+        let (prefix, local) = self.name();
+        let mut attributes = Vec::new();
+        let mut empty = self.attributes(&mut attributes);
+        let span = Span {
+            start: start_loc,
+            end: self.locate(self.pos),
+        };
+
+        if let Some(top) = self.open.last() {
+            if top.prefix == prefix && top.tag.eq_ignore_ascii_case(local) && is_one_of(local, IMPLIED_END) {
+                let msg = format!("Implied end tag for '{}'", top.tag);
+                self.report_at(ParseErrorKind::Syntax(msg), start_loc);
+                self.pop(start_loc);
+            }
+        }
+        if self.open.is_empty() {
+            if let Some(root) = self.root.take() {
+                let msg = "Unexpected element after the root element".to_string();
+                self.report_at(ParseErrorKind::Syntax(msg), start_loc);
+                self.open.push(root);
+                self.reopened = true;
+            }
+        }
+        empty |= prefix.is_none() && is_one_of(local, VOID_ELEMENTS);
+        let read = self.pos;
+        let checked = self.budget.enter(&qualified(prefix, local), attributes.len(), start_loc).and_then(|_| {
+            attributes
+                .iter()
+                .try_for_each(|attr| self.budget.attribute(&attr.qname(), &attr.value, attr.span.start, read))
+        });
+        checked.map_err(|err| within(err, &self.open))?;
+
+        let element = BorrowedElement {
+            prefix,
+            tag: local,
+            attributes,
+            children: Vec::new(),
+            text: None,
+            nodes: Vec::new(),
+            span,
+        };
+        if empty {
+            if !self.open.is_empty() {
+                self.budget.leave();
+            }
+            close(element, &mut self.open, &mut self.root);
+        } else {
+            self.open.push(element);
+        }
+        Ok(())
+    }
+
+    ///Reads attributes up to the end of a start tag, returning whether it was `/>`
+    fn attributes(&mut self, attributes: &mut Vec<BorrowedAttribute<'a>>) -> bool {
+        loop {
+            self.skip_space();
+            let rest = self.rest();
+            if rest.starts_with("/>") {
+                self.pos += 2;
+                return true;
+            } else if rest.starts_with('>') {
+                self.pos += 1;
+                return false;
+            } else if rest.is_empty() || rest.starts_with('<') {
+                self.syntax("Unclosed start tag".to_string(), self.pos);
+                return false;
+            }
+
+            let start = self.pos;
+            let start_loc = self.locate(start);
+            let (prefix, name) = self.name();
+            let name_end = self.pos;
+            if name.is_empty() {
+                let c = self.rest().chars().next().unwrap();
+                self.syntax(format!("Unexpected '{}' in start tag", c), start);
+                self.pos += c.len_utf8();
+                continue;
+            }
+            self.skip_space();
+            let value = if self.rest().starts_with('=') {
+                self.pos += 1;
+                self.skip_space();
+                self.attribute_value(name)
+            } else {
+                self.pos = name_end;
+                self.report_at(ParseErrorKind::Syntax(format!("Attribute '{}' has no value", name)), start_loc);
+                Cow::Borrowed(name)
+            };
+            let attr = BorrowedAttribute {
+                prefix,
+                name,
+                value,
+                span: Span {
+                    start: start_loc,
+                    end: self.locate(self.pos),
+                },
+            };
+            if attributes.iter().any(|old| (old.prefix, old.name) == (prefix, name)) {
+                self.report_at(ParseErrorKind::Syntax(format!("Duplicate attribute '{}'", attr.qname())), start_loc);
+            } else {
+                attributes.push(attr);
+            }
+        }
+    }
+
+    fn attribute_value(&mut self, name: &str) -> Cow<'a, str> {
+        let rest = self.rest();
+        match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let value_start = self.pos + 1;
+                let raw = match rest[1..].find(quote) {
+                    Some(index) => {
+                        self.pos += index + 2;
+                        &rest[1..index + 1]
+                    }
+                    None => {
+                        // the value runs to the end of the tag
+                        self.syntax(format!("Unterminated value of attribute '{}'", name), self.pos);
+                        let len = rest.find('>').unwrap_or(rest.len());
+                        self.pos += len;
+                        &rest[1..len.max(1)]
+                    }
+                };
+                self.unescape(raw, value_start)
+            }
+            _ => {
+                let len = rest.find(|c: char| is_space(c) || c == '>').unwrap_or(rest.len());
+                let raw = &rest[..len];
+                self.syntax(format!("Unquoted value of attribute '{}'", name), self.pos);
+                let value_start = self.pos;
+                self.pos += len;
+                self.unescape(raw, value_start)
+            }
+        }
+    }
+
+    fn end_tag(&mut self) {
+        let start = self.pos;
+        let start_loc = self.locate(start);
+        self.pos += 2;
+        let (prefix, local) = self.name();
+        self.skip_space();
+        match self.rest().find('>') {
+            Some(0) => self.pos += 1,
+            Some(index) if !self.rest()[..index].contains('<') => {
+                self.syntax(format!("Unexpected characters in closing tag: {}", local), self.pos);
+                self.pos += index + 1;
+            }
+            _ => self.syntax(format!("Unclosed closing tag: {}", local), self.pos),
+        }
+        let end = self.locate(self.pos);
+
+        let found = self
+            .open
+            .iter()
+            .rposition(|element| element.prefix == prefix && element.tag == local)
+            .or_else(|| {
+                self.open
+                    .iter()
+                    .rposition(|element| element.prefix == prefix && element.tag.eq_ignore_ascii_case(local))
+            });
+        let index = match found {
+            Some(index) => index,
+            None => {
+                self.report_at(ParseErrorKind::Syntax(format!("Unexpected closing tag: {}", qualified(prefix, local))), start_loc);
+                return;
+            }
+        };
+        // elements left open inside the one being closed end here
+        while self.open.len() > index + 1 {
+            let top = self.open.last().unwrap();
+            let kind = ParseErrorKind::MismatchedTag {
+                expected: qualified(top.prefix, top.tag),
+                found: qualified(prefix, local),
+            };
+            self.report_at(kind, start_loc);
+            self.pop(start_loc);
+        }
+        self.pop(end);
+    }
+
+    ///Resolves the references in `raw`, found at `offset`, keeping any `&` that does not start one
+    fn unescape(&mut self, raw: &'a str, offset: usize) -> Cow<'a, str> {
+        if !raw.contains(['&', '\r']) {
+            return Cow::Borrowed(raw);
+        }
+        let mut out = String::with_capacity(raw.len());
+        let mut rest = raw;
+        while let Some(index) = rest.find(['&', '\r']) {
+            out.push_str(&rest[..index]);
+            rest = &rest[index..];
+            if let Some(after) = rest.strip_prefix('\r') {
+                out.push('\n');
+                rest = after.strip_prefix('\n').unwrap_or(after);
+                continue;
+            }
+            let at = offset + (raw.len() - rest.len());
+            let entity = rest[1..]
+                .find(|c: char| !(is_name_char(c) || c == '#'))
+                .filter(|&end| rest[1 + end..].starts_with(';'))
+                .map(|end| &rest[1..end + 1]);
+            match entity.map(|entity| (entity, resolve_entity(entity))) {
+                Some((entity, Some(c))) => {
+                    out.push(c);
+                    rest = &rest[entity.len() + 2..];
+                }
+                Some((entity, None)) => {
+                    self.syntax(format!("Unexpected entity: {}", entity), at);
+                    out.push_str(&rest[..entity.len() + 2]);
+                    rest = &rest[entity.len() + 2..];
+                }
+                None => {
+                    self.syntax("Unescaped '&' in text".to_string(), at);
+                    out.push('&');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        out.push_str(rest);
+        Cow::Owned(out)
+    }
+
+    fn finish(mut self) -> (Option<BorrowedDocument<'a>>, Vec<ParseError>) {
+        // a root element only open again for content after it was closed already
+        if self.open.len() > usize::from(self.reopened) {
+            self.report(ParseErrorKind::UnexpectedEof, self.input.len());
+        }
+        if !self.open.is_empty() {
+            let end = self.locate(self.input.len());
+            while let Some(mut element) = self.open.pop() {
+                element.span.end = end;
+                close(element, &mut self.open, &mut self.root);
+            }
+        }
+        if self.root.is_none() {
+            self.report(ParseErrorKind::UnexpectedEof, self.input.len());
+        }
+        let document = self.root.map(|root| BorrowedDocument {
+            version: self.version,
+            encoding: self.encoding,
+            root,
+            prolog: Vec::new(),
+            epilog: Vec::new(),
+        });
+        (document, self.diagnostics)
+    }
+}
+
+///Whether the HTML element name `name` is in `names`, in any case
+fn is_one_of(name: &str, names: &[&str]) -> bool {
+    names.iter().any(|known| known.eq_ignore_ascii_case(name))
+}
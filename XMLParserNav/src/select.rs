@@ -0,0 +1,127 @@
+use std::str::FromStr;
+use crate::element::Element;
+use crate::errors::{QueryError, XmlErrors};
+use crate::translator::ReturnEnum;
+use crate::tree::ElementTree;
+
+impl Element {
+    ///The text of the one node `xpath` selects, which may end with an attribute step
+    ///(`/book/@id`) or be only one (`@id`), and is relative to the element unless it starts with `/`,
+    ///when the element is taken as the root of its document
+    pub fn select_str(&self, xpath: &str) -> Result<String, XmlErrors> {
+        one(xpath, self.select_values(xpath)?)
+    }
+
+    ///The one node `xpath` selects, read as a number
+    pub fn select_f64(&self, xpath: &str) -> Result<f64, XmlErrors> {
+        self.select_one(xpath)
+    }
+
+    ///The one node `xpath` selects, read as `true`/`1` or `false`/`0`
+    pub fn select_bool(&self, xpath: &str) -> Result<bool, XmlErrors> {
+        parse_bool(self.select_str(xpath)?)
+    }
+
+    ///The one node `xpath` selects, parsed as a `T`
+    pub fn select_one<T: FromStr>(&self, xpath: &str) -> Result<T, XmlErrors> {
+        parse(self.select_str(xpath)?)
+    }
+
+    ///Every node `xpath` selects, each parsed as a `T`
+    pub fn select_all<T: FromStr>(&self, xpath: &str) -> Result<Vec<T>, XmlErrors> {
+        self.select_values(xpath)?.into_iter().map(parse).collect()
+    }
+
+    ///The text of the elements, or the values of the attribute, that `xpath` selects, where a
+    ///path without a leading `/` is relative to the element
+    fn select_values(&self, xpath: &str) -> Result<Vec<String>, XmlErrors> {
+        let (steps, attribute) = split_attribute(xpath);
+        Ok(resolve(self, steps)?
+            .into_iter()
+            .filter_map(|found| match (found, attribute) {
+                (ReturnEnum::ElementNode(element), Some(attribute)) => element.attributes.get(attribute).map(str::to_string),
+                (ReturnEnum::ElementNode(element), None) => Some(element.text.clone().unwrap_or_default()),
+                (ReturnEnum::ElementName(_), Some(_)) => None,
+                (ReturnEnum::ElementName(name), None) => Some(name),
+            })
+            .collect())
+    }
+}
+
+impl ElementTree {
+    ///The text of the one node `xpath` selects, which may end with an attribute step (`//book/@id`)
+    pub fn select_str(&self, xpath: &str) -> Result<String, XmlErrors> {
+        self.root_element()?.select_str(xpath)
+    }
+
+    ///The one node `xpath` selects, read as a number
+    pub fn select_f64(&self, xpath: &str) -> Result<f64, XmlErrors> {
+        self.root_element()?.select_f64(xpath)
+    }
+
+    ///The one node `xpath` selects, read as `true`/`1` or `false`/`0`
+    pub fn select_bool(&self, xpath: &str) -> Result<bool, XmlErrors> {
+        self.root_element()?.select_bool(xpath)
+    }
+
+    ///The one node `xpath` selects, parsed as a `T`
+    pub fn select_one<T: FromStr>(&self, xpath: &str) -> Result<T, XmlErrors> {
+        self.root_element()?.select_one(xpath)
+    }
+
+    ///Every node `xpath` selects, each parsed as a `T`
+    pub fn select_all<T: FromStr>(&self, xpath: &str) -> Result<Vec<T>, XmlErrors> {
+        self.root_element()?.select_all(xpath)
+    }
+
+    fn root_element(&self) -> Result<&Element, XmlErrors> {
+        self.root.as_ref().ok_or_else(|| XmlErrors::ElementNotFound { t: "root".to_string() })
+    }
+}
+
+///Splits a final attribute step off `xpath`, which the query engine cannot select
+pub(crate) fn split_attribute(xpath: &str) -> (&str, Option<&str>) {
+    let xpath = xpath.trim();
+    match xpath.rsplit_once('/') {
+        Some((steps, last)) if last.starts_with('@') => (steps, Some(&last[1..])),
+        None if xpath.starts_with('@') => ("", Some(&xpath[1..])),
+        _ => (xpath, None),
+    }
+}
+
+///Runs `steps` under `element`, those without a leading `/` relative to it
+pub(crate) fn resolve<'a>(element: &'a Element, steps: &str) -> Result<Vec<ReturnEnum<'a>>, XmlErrors> {
+    // the first step of an absolute path, and only that one, selects the element itself,
+    // so a relative path is run as a step to the element followed by child steps from it
+    if let Some(steps) = steps.strip_prefix(".//") {
+        return element.query(&format!("/{}//{}", element.qname(), steps));
+    }
+    match steps.strip_prefix("./").unwrap_or(steps) {
+        "" | "." => Ok(vec![ReturnEnum::ElementNode(element)]),
+        steps if steps.starts_with('/') => element.query(steps),
+        steps => element.query(&format!("/{}/{}", element.qname(), steps)),
+    }
+}
+
+///The one value `xpath` selected
+pub(crate) fn one<T>(xpath: &str, mut values: Vec<T>) -> Result<T, XmlErrors> {
+    match values.len() {
+        1 => Ok(values.remove(0)),
+        0 => Err(XmlErrors::ElementNotFound { t: xpath.to_string() }),
+        n => Err(QueryError::MultipleMatches(n).into()),
+    }
+}
+
+///Parses the trimmed `value` as a `T`
+pub(crate) fn parse<T: FromStr>(value: String) -> Result<T, XmlErrors> {
+    value.trim().parse().map_err(|_| XmlErrors::ValueFromStr { t: value })
+}
+
+///Reads the trimmed `value` as `true`/`1` or `false`/`0`, as XML Schema writes booleans
+pub(crate) fn parse_bool(value: String) -> Result<bool, XmlErrors> {
+    match value.trim() {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(XmlErrors::ValueFromStr { t: value }),
+    }
+}
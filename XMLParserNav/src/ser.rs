@@ -0,0 +1,483 @@
+use serde::ser::{self, Impossible, Serialize, SerializeMap, SerializeSeq, SerializeStruct, SerializeTuple, SerializeTupleStruct};
+use crate::builder::ElementBuilder;
+use crate::de::TEXT;
+use crate::element::Element;
+use crate::errors::XmlErrors;
+use crate::tree::ElementTree;
+
+///Writes `value` as an element named after its type, laid out as `de::from_element` reads it
+pub fn to_element<T: Serialize + ?Sized>(value: &T) -> Result<Element, XmlErrors> {
+    let mut elements = value.serialize(ElementSerializer { name: None })?;
+    match elements.len() {
+        1 => Ok(elements.remove(0)),
+        n => Err(ser::Error::custom(format!("expected one root element, found {}", n))),
+    }
+}
+
+///Writes `value` as the root element of a new document
+pub fn to_tree<T: Serialize + ?Sized>(value: &T) -> Result<ElementTree, XmlErrors> {
+    to_element(value).map(ElementTree::new_with_root)
+}
+
+/// Serializes a value as the elements named `name`: none for `None`, one per item of a
+/// sequence, and otherwise one. The root element, which has no name, takes the name of
+/// its struct.
+struct ElementSerializer {
+    name: Option<String>,
+}
+
+impl ElementSerializer {
+    fn builder(&self) -> Result<ElementBuilder, XmlErrors> {
+        match self.name {
+            Some(ref name) => Ok(ElementBuilder::new(name)),
+            None => Err(ser::Error::custom("only a struct or an enum variant can be the root element")),
+        }
+    }
+
+    fn text<S: ToString>(self, text: S) -> Result<Vec<Element>, XmlErrors> {
+        Ok(vec![self.builder()?.text(text).build()])
+    }
+}
+
+/// Methods that write the value as the text of an element
+macro_rules! serialize_text {
+    ($($method:ident: $t:ty,)*) => {
+        $(
+            fn $method(self, v: $t) -> Result<Self::Ok, XmlErrors> {
+                self.text(v)
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for ElementSerializer {
+    type Ok = Vec<Element>;
+    type Error = XmlErrors;
+    type SerializeSeq = Elements;
+    type SerializeTuple = Elements;
+    type SerializeTupleStruct = Elements;
+    type SerializeTupleVariant = Impossible<Vec<Element>, XmlErrors>;
+    type SerializeMap = Fields;
+    type SerializeStruct = Fields;
+    type SerializeStructVariant = Fields;
+
+    serialize_text! {
+        serialize_bool: bool,
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_i128: i128,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_u128: u128,
+        serialize_f32: f32,
+        serialize_f64: f64,
+        serialize_char: char,
+        serialize_str: &str,
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Vec<Element>, XmlErrors> {
+        Err(ser::Error::custom("bytes cannot be written as XML"))
+    }
+
+    fn serialize_none(self) -> Result<Vec<Element>, XmlErrors> {
+        Ok(Vec::new())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<Element>, XmlErrors> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Vec<Element>, XmlErrors> {
+        Ok(vec![self.builder()?.build()])
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Vec<Element>, XmlErrors> {
+        Ok(vec![Element::new(self.name.as_deref().unwrap_or(name))])
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<Vec<Element>, XmlErrors> {
+        match self.name {
+            Some(_) => self.text(variant),
+            None => Ok(vec![Element::new(variant)]),
+        }
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Vec<Element>, XmlErrors> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Vec<Element>, XmlErrors> {
+        let content = value.serialize(ElementSerializer { name: Some(variant.to_string()) })?;
+        match self.name {
+            Some(ref name) => Ok(vec![ElementBuilder::new(name).children(content).build()]),
+            None => Ok(content),
+        }
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Elements, XmlErrors> {
+        Ok(Elements {
+            name: self.name,
+            elements: Vec::new(),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Elements, XmlErrors> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Elements, XmlErrors> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, XmlErrors> {
+        Err(ser::Error::custom(format!("tuple variant '{}' cannot be written as XML", variant)))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Fields, XmlErrors> {
+        Ok(Fields::new(self.builder()?, None))
+    }
+
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Fields, XmlErrors> {
+        Ok(Fields::new(ElementBuilder::new(self.name.as_deref().unwrap_or(name)), None))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Fields, XmlErrors> {
+        // the variant is a child of the element named for the field, or the root itself
+        let wrapper = self.name.as_deref().map(ElementBuilder::new);
+        Ok(Fields::new(ElementBuilder::new(variant), wrapper))
+    }
+}
+
+/// The elements of a sequence, each named for the field that holds it
+struct Elements {
+    name: Option<String>,
+    elements: Vec<Element>,
+}
+
+impl SerializeSeq for Elements {
+    type Ok = Vec<Element>;
+    type Error = XmlErrors;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XmlErrors> {
+        let elements = value.serialize(ElementSerializer { name: self.name.clone() })?;
+        self.elements.extend(elements);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Vec<Element>, XmlErrors> {
+        Ok(self.elements)
+    }
+}
+
+impl SerializeTuple for Elements {
+    type Ok = Vec<Element>;
+    type Error = XmlErrors;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XmlErrors> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Vec<Element>, XmlErrors> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl SerializeTupleStruct for Elements {
+    type Ok = Vec<Element>;
+    type Error = XmlErrors;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XmlErrors> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Vec<Element>, XmlErrors> {
+        SerializeSeq::end(self)
+    }
+}
+
+/// The fields of a struct or entries of a map, written as attributes, text or children
+struct Fields {
+    builder: Option<ElementBuilder>,
+    wrapper: Option<ElementBuilder>,
+    key: Option<String>,
+}
+
+impl Fields {
+    fn new(builder: ElementBuilder, wrapper: Option<ElementBuilder>) -> Fields {
+        Fields {
+            builder: Some(builder),
+            wrapper,
+            key: None,
+        }
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<(), XmlErrors> {
+        let builder = self.builder.take().expect("builder taken by an earlier failed field");
+        let builder = if let Some(name) = key.strip_prefix('@') {
+            match value.serialize(TextSerializer)? {
+                Some(text) => builder.attr(name, text),
+                None => builder,
+            }
+        } else if key == TEXT {
+            match value.serialize(TextSerializer)? {
+                Some(text) => builder.text(text),
+                None => builder,
+            }
+        } else {
+            builder.children(value.serialize(ElementSerializer { name: Some(key.to_string()) })?)
+        };
+        self.builder = Some(builder);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<Element>, XmlErrors> {
+        let element = self.builder.expect("builder taken by an earlier failed field").build();
+        Ok(vec![match self.wrapper {
+            Some(wrapper) => wrapper.child(element).build(),
+            None => element,
+        }])
+    }
+}
+
+impl SerializeStruct for Fields {
+    type Ok = Vec<Element>;
+    type Error = XmlErrors;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), XmlErrors> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<Vec<Element>, XmlErrors> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for Fields {
+    type Ok = Vec<Element>;
+    type Error = XmlErrors;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), XmlErrors> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<Vec<Element>, XmlErrors> {
+        self.finish()
+    }
+}
+
+impl SerializeMap for Fields {
+    type Ok = Vec<Element>;
+    type Error = XmlErrors;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), XmlErrors> {
+        match key.serialize(TextSerializer)? {
+            Some(key) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            None => Err(ser::Error::custom("map keys cannot be empty")),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XmlErrors> {
+        match self.key.take() {
+            Some(key) => self.field(&key, value),
+            None => Err(ser::Error::custom("map value written before its key")),
+        }
+    }
+
+    fn end(self) -> Result<Vec<Element>, XmlErrors> {
+        self.finish()
+    }
+}
+
+/// Serializes a value as an attribute value or text, `None` leaving it out
+struct TextSerializer;
+
+/// Methods that write the value with `ToString`
+macro_rules! to_text {
+    ($($method:ident: $t:ty,)*) => {
+        $(
+            fn $method(self, v: $t) -> Result<Option<String>, XmlErrors> {
+                Ok(Some(v.to_string()))
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for TextSerializer {
+    type Ok = Option<String>;
+    type Error = XmlErrors;
+    type SerializeSeq = Words;
+    type SerializeTuple = Words;
+    type SerializeTupleStruct = Words;
+    type SerializeTupleVariant = Impossible<Option<String>, XmlErrors>;
+    type SerializeMap = Impossible<Option<String>, XmlErrors>;
+    type SerializeStruct = Impossible<Option<String>, XmlErrors>;
+    type SerializeStructVariant = Impossible<Option<String>, XmlErrors>;
+
+    to_text! {
+        serialize_bool: bool,
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_i128: i128,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_u128: u128,
+        serialize_f32: f32,
+        serialize_f64: f64,
+        serialize_char: char,
+        serialize_str: &str,
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Option<String>, XmlErrors> {
+        Err(ser::Error::custom("bytes cannot be written as XML"))
+    }
+
+    fn serialize_none(self) -> Result<Option<String>, XmlErrors> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Option<String>, XmlErrors> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Option<String>, XmlErrors> {
+        Ok(None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Option<String>, XmlErrors> {
+        Ok(None)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<Option<String>, XmlErrors> {
+        Ok(Some(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Option<String>, XmlErrors> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _value: &T,
+    ) -> Result<Option<String>, XmlErrors> {
+        Err(not_text(variant))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Words, XmlErrors> {
+        Ok(Words(Vec::new()))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Words, XmlErrors> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Words, XmlErrors> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, XmlErrors> {
+        Err(not_text(variant))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, XmlErrors> {
+        Err(not_text("map"))
+    }
+
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Self::SerializeStruct, XmlErrors> {
+        Err(not_text(name))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, XmlErrors> {
+        Err(not_text(variant))
+    }
+}
+
+fn not_text(name: &str) -> XmlErrors {
+    ser::Error::custom(format!("'{}' cannot be written as an attribute or text", name))
+}
+
+/// The items of a sequence written as text, separated by spaces as in xs:list
+struct Words(Vec<String>);
+
+impl SerializeSeq for Words {
+    type Ok = Option<String>;
+    type Error = XmlErrors;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XmlErrors> {
+        self.0.extend(value.serialize(TextSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Option<String>, XmlErrors> {
+        Ok(Some(self.0.join(" ")))
+    }
+}
+
+impl SerializeTuple for Words {
+    type Ok = Option<String>;
+    type Error = XmlErrors;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XmlErrors> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Option<String>, XmlErrors> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl SerializeTupleStruct for Words {
+    type Ok = Option<String>;
+    type Error = XmlErrors;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), XmlErrors> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Option<String>, XmlErrors> {
+        SerializeSeq::end(self)
+    }
+}
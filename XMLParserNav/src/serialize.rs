@@ -0,0 +1,519 @@
+use std::io;
+use encoding_rs::Encoding;
+use xml::namespace::{NS_EMPTY_URI, NS_XMLNS_PREFIX, NS_XML_PREFIX, NS_XML_URI};
+use xml::writer::Error as EmitterError;
+use crate::element::{Element, Node};
+use crate::encoding::can_encode;
+use crate::errors::XmlErrors;
+use crate::lossless::Originals;
+use crate::tree::ElementTree;
+
+/// How `ElementTree::write_with_options` and `Element::to_string_with` lay out a document.
+///
+/// `WriteOptions::pretty`, `WriteOptions::compact` and `WriteOptions::preserve` cover the usual
+/// cases; the fields can be changed on top of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Whether the document starts with an XML declaration
+    pub declaration: bool,
+    /// What each level of nesting is indented with; empty keeps everything on one line
+    pub indent: String,
+    /// Width past which the attributes of a start tag are put on lines of their own
+    pub line_width: Option<usize>,
+    /// Whether empty elements are written as `<a/>` rather than `<a></a>`
+    pub self_closing: bool,
+    /// Whether self-closing tags get a space before the slash, as in `<a />`
+    pub pad_self_closing: bool,
+    /// The quotes around attribute values
+    pub quote: Quote,
+    /// Whether `>` is escaped in text and attribute values, not only where it would end a CDATA section
+    pub escape_gt: bool,
+    /// Whether characters outside ASCII are written as character references
+    pub escape_non_ascii: bool,
+    /// The line ending of the lines the writer breaks and of the newlines in text
+    pub line_ending: LineEnding,
+    /// Whether the whitespace of the tree is written exactly as it is, without indentation.
+    /// Parse with `ParseOptions::preserve_whitespace` to reproduce a document's own layout,
+    /// or with `ElementTree::parse_lossless` to reproduce the document itself.
+    pub preserve_whitespace: bool,
+}
+
+/// The quotes around attribute values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quote {
+    /// `"value"`
+    Double,
+    /// `'value'`
+    Single,
+}
+
+impl Quote {
+    fn as_char(self) -> char {
+        match self {
+            Quote::Double => '"',
+            Quote::Single => '\'',
+        }
+    }
+}
+
+/// Line ending style
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`
+    Lf,
+    /// `\r\n`
+    CrLf,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            declaration: true,
+            indent: " ".to_string(),
+            line_width: None,
+            self_closing: true,
+            pad_self_closing: true,
+            quote: Quote::Double,
+            escape_gt: false,
+            escape_non_ascii: false,
+            line_ending: LineEnding::Lf,
+            preserve_whitespace: false,
+        }
+    }
+}
+
+impl WriteOptions {
+    ///Creates the default options, which `ElementTree::write` uses
+    pub fn new() -> WriteOptions {
+        WriteOptions::default()
+    }
+
+    ///Indents with two spaces
+    pub fn pretty() -> WriteOptions {
+        WriteOptions::new().with_indent_width(2)
+    }
+
+    ///Writes everything on one line, with `<a/>` for empty elements
+    pub fn compact() -> WriteOptions {
+        WriteOptions {
+            indent: String::new(),
+            pad_self_closing: false,
+            ..WriteOptions::new()
+        }
+    }
+
+    ///Writes the whitespace of the tree as it is
+    pub fn preserve() -> WriteOptions {
+        WriteOptions {
+            indent: String::new(),
+            preserve_whitespace: true,
+            ..WriteOptions::new()
+        }
+    }
+
+    ///Indents with `width` spaces
+    pub fn with_indent_width(mut self, width: usize) -> WriteOptions {
+        self.indent = " ".repeat(width);
+        self
+    }
+}
+
+/// Namespace bindings in effect, innermost last; `None` is the default namespace
+type Scope<'e> = Vec<(Option<&'e str>, &'e str)>;
+
+fn bound<'e>(scope: &Scope<'e>, prefix: Option<&str>) -> Option<&'e str> {
+    scope.iter().rev().find(|&&(p, _)| p == prefix).map(|&(_, uri)| uri)
+}
+
+///The error for a name whose prefix has no namespace to be declared with
+fn unbound(qname: &str) -> XmlErrors {
+    let msg = format!("the prefix of '{}' is not bound to a namespace", qname);
+    EmitterError::Io(io::Error::new(io::ErrorKind::InvalidData, msg)).into()
+}
+
+struct Serializer<'e> {
+    options: &'e WriteOptions,
+    /// The parsed nodes of a lossless tree, whose source is copied where nothing was edited
+    originals: Option<Originals<'e>>,
+    /// The encoding the output is meant for, whose missing characters are written as references
+    target: Option<&'static Encoding>,
+    scope: Scope<'e>,
+    out: String,
+}
+
+///Writes `tree` as UTF-8, declaring `label` as its encoding, with the characters of text and
+///attribute values that `target` lacks written as references.
+///A prefix that is not bound where it is used is an error.
+pub(crate) fn document(tree: &ElementTree, label: &str, target: &'static Encoding, options: &WriteOptions) -> Result<String, XmlErrors> {
+    let originals = tree.lossless.as_ref().map(|lossless| lossless.originals());
+    // edited nodes of a lossless tree must not disturb the layout around them
+    let edited;
+    let options = match tree.lossless {
+        Some(ref lossless) => {
+            let crlf = lossless.source().contains("\r\n");
+            edited = WriteOptions {
+                preserve_whitespace: true,
+                line_ending: if crlf { LineEnding::CrLf } else { LineEnding::Lf },
+                ..options.clone()
+            };
+            &edited
+        }
+        None => options,
+    };
+    let prolog = originals.as_ref().and_then(|originals| originals.prolog(tree));
+    let epilog = originals.as_ref().and_then(|originals| originals.epilog(tree));
+    let mut writer = Serializer::new(options, originals, Some(target));
+    // whitespace outside the root is not kept, so top-level nodes go on lines of their own
+    let separate = !options.indent.is_empty() || options.preserve_whitespace;
+    let mut first = true;
+    let mut separator = |writer: &mut Serializer| {
+        if separate && !first {
+            writer.newline();
+        }
+        first = false;
+    };
+
+    match prolog {
+        Some(prolog) => {
+            separator(&mut writer);
+            writer.out.push_str(prolog);
+        }
+        None => {
+            if options.declaration {
+                separator(&mut writer);
+                writer.out.push_str("<?xml version=");
+                writer.quoted(&tree.version.to_string());
+                writer.out.push_str(" encoding=");
+                writer.quoted(label);
+                writer.out.push_str("?>");
+            }
+            if let Some(ref doctype) = tree.doctype {
+                separator(&mut writer);
+                writer.raw(&doctype.to_string());
+            }
+            for node in &tree.prolog {
+                separator(&mut writer);
+                writer.misc(node);
+            }
+        }
+    }
+    if let Some(ref root) = tree.root {
+        // a copied prolog ends with what separated it from the root
+        if prolog.is_none() {
+            separator(&mut writer);
+        }
+        writer.element(root, 0, false)?;
+    }
+    match epilog {
+        Some(epilog) => writer.out.push_str(epilog),
+        None => {
+            for node in &tree.epilog {
+                separator(&mut writer);
+                writer.misc(node);
+            }
+        }
+    }
+    Ok(writer.out)
+}
+
+///Writes `element` and its contents, with only the namespaces it declares itself in scope
+pub(crate) fn element(element: &Element, options: &WriteOptions) -> Result<String, XmlErrors> {
+    let mut writer = Serializer::new(options, None, None);
+    writer.element(element, 0, false)?;
+    Ok(writer.out)
+}
+
+/// A piece of an element's content
+enum Content<'e> {
+    Element(&'e Element),
+    /// Text, with its node if it has one
+    Text(&'e str, Option<&'e Node>),
+    Other(&'e Node),
+}
+
+impl<'e> Serializer<'e> {
+    fn new(options: &'e WriteOptions, originals: Option<Originals<'e>>, target: Option<&'static Encoding>) -> Serializer<'e> {
+        Serializer {
+            options,
+            originals,
+            target,
+            scope: vec![(Some(NS_XML_PREFIX), NS_XML_URI)],
+            out: String::new(),
+        }
+    }
+
+    fn newline(&mut self) {
+        self.out.push_str(self.options.line_ending.as_str());
+    }
+
+    fn indent(&mut self, depth: usize) {
+        self.newline();
+        for _ in 0..depth {
+            self.out.push_str(&self.options.indent);
+        }
+    }
+
+    ///Writes markup as it is, apart from its line endings
+    fn raw(&mut self, markup: &str) {
+        for c in markup.chars() {
+            match c {
+                '\n' => self.newline(),
+                c => self.out.push(c),
+            }
+        }
+    }
+
+    ///Writes a comment or processing instruction
+    fn misc(&mut self, node: &Node) {
+        match *node {
+            Node::Comment(ref comment) => {
+                self.out.push_str("<!--");
+                self.raw(&comment.value);
+                self.out.push_str("-->");
+            }
+            Node::ProcessingInstruction(ref pi) => {
+                self.out.push_str("<?");
+                self.out.push_str(&pi.target);
+                if let Some(ref data) = pi.data {
+                    self.out.push(' ');
+                    self.raw(data);
+                }
+                self.out.push_str("?>");
+            }
+            Node::Element(_) | Node::Text(_) => {}
+        }
+    }
+
+    /// Write the element at nesting level `depth`, `space_preserve` telling whether an
+    /// ancestor asked for its whitespace to be kept with `xml:space`.
+    /// Declarations already in effect are left out, and prefixed attributes whose namespace
+    /// is not declared get a declaration. Other prefixes must be bound in scope.
+    fn element(&mut self, element: &'e Element, depth: usize, mut space_preserve: bool) -> Result<(), XmlErrors> {
+        if let Some(source) = self.originals.as_ref().and_then(|originals| originals.element(element)) {
+            self.out.push_str(source);
+            return Ok(());
+        }
+        let declared = self.scope.len();
+        let mut attributes = Vec::with_capacity(element.attributes.len());
+        for attr in &element.attributes {
+            let prefix = match (attr.prefix.as_deref(), attr.name.as_str()) {
+                (None, NS_XMLNS_PREFIX) => None,
+                (Some(NS_XMLNS_PREFIX), prefix) => Some(prefix),
+                _ => continue,
+            };
+            // no default namespace is the same as an empty one
+            let in_effect = match prefix {
+                None => bound(&self.scope, None).unwrap_or(NS_EMPTY_URI) == attr.value,
+                Some(_) => bound(&self.scope, prefix) == Some(attr.value.as_str()),
+            };
+            if in_effect {
+                continue;
+            }
+            self.scope.push((prefix, &attr.value));
+            let name = match prefix {
+                Some(prefix) => format!("{}:{}", NS_XMLNS_PREFIX, prefix),
+                None => NS_XMLNS_PREFIX.to_string(),
+            };
+            attributes.push((name, attr.value.as_str()));
+        }
+        for attr in &element.attributes {
+            if let (Some(prefix), Some(uri)) = (attr.prefix.as_deref(), attr.namespace.as_deref()) {
+                if prefix != NS_XMLNS_PREFIX && bound(&self.scope, Some(prefix)).is_none() {
+                    self.scope.push((Some(prefix), uri));
+                    attributes.push((format!("{}:{}", NS_XMLNS_PREFIX, prefix), uri));
+                }
+            }
+        }
+        for attr in &element.attributes {
+            match attr.prefix.as_deref() {
+                Some(NS_XMLNS_PREFIX) => continue,
+                None if attr.name == NS_XMLNS_PREFIX => continue,
+                Some(NS_XML_PREFIX) if attr.name == "space" => space_preserve = attr.value == "preserve",
+                Some(prefix) if bound(&self.scope, Some(prefix)).is_none() => return Err(unbound(&attr.qname())),
+                _ => {}
+            }
+            attributes.push((attr.qname(), attr.value.as_str()));
+        }
+
+        let qname = match element.namespace {
+            Some(ref prefix) => format!("{}:{}", prefix, element.tag),
+            None => element.tag.clone(),
+        };
+        if element.namespace.is_some() && bound(&self.scope, element.namespace.as_deref()).is_none() {
+            return Err(unbound(&qname));
+        }
+        let tags = self.originals.as_ref().and_then(|originals| originals.tags(element));
+        let content = content(element);
+        if let Some((start_tag, end_tag)) = tags {
+            self.out.push_str(start_tag);
+            for item in &content {
+                self.content(item, depth, space_preserve)?;
+            }
+            self.out.push_str(end_tag);
+            self.scope.truncate(declared);
+            return Ok(());
+        }
+
+        self.out.push('<');
+        self.out.push_str(&qname);
+        self.attributes(&attributes, depth);
+        if content.iter().all(|item| matches!(item, Content::Text(text, _) if text.is_empty())) {
+            if self.options.self_closing {
+                self.out.push_str(if self.options.pad_self_closing { " />" } else { "/>" });
+            } else {
+                self.out.push_str("></");
+                self.out.push_str(&qname);
+                self.out.push('>');
+            }
+            self.scope.truncate(declared);
+            return Ok(());
+        }
+        self.out.push('>');
+
+        // only element content is indented, where whitespace carries no meaning
+        let indented = !self.options.indent.is_empty()
+            && !self.options.preserve_whitespace
+            && !space_preserve
+            && content.iter().any(|item| !matches!(item, Content::Text(..)))
+            && content.iter().all(|item| !matches!(item, Content::Text(text, _) if !is_whitespace(text)));
+        for item in &content {
+            match *item {
+                Content::Text(..) if indented => continue,
+                Content::Text(..) => {}
+                _ if indented => self.indent(depth + 1),
+                _ => {}
+            }
+            self.content(item, depth, space_preserve)?;
+        }
+        if indented {
+            self.indent(depth);
+        }
+        self.out.push_str("</");
+        self.out.push_str(&qname);
+        self.out.push('>');
+        self.scope.truncate(declared);
+        Ok(())
+    }
+
+    ///Writes a piece of the content of an element at nesting level `depth`
+    fn content(&mut self, item: &Content<'e>, depth: usize, space_preserve: bool) -> Result<(), XmlErrors> {
+        let node = match *item {
+            Content::Element(child) => return self.element(child, depth + 1, space_preserve),
+            Content::Text(_, node) => node,
+            Content::Other(node) => Some(node),
+        };
+        if let Some(source) = node.and_then(|node| self.originals.as_ref()?.node(node)) {
+            self.out.push_str(source);
+            return Ok(());
+        }
+        match *item {
+            Content::Text(text, _) => self.text(text),
+            Content::Other(node) => self.misc(node),
+            Content::Element(_) => {}
+        }
+        Ok(())
+    }
+
+    ///Writes the attributes of a start tag, one per line if they do not fit in `line_width`
+    fn attributes(&mut self, attributes: &[(String, &str)], depth: usize) {
+        let start = self.out.len();
+        for &(ref name, value) in attributes {
+            self.out.push(' ');
+            self.out.push_str(name);
+            self.out.push('=');
+            self.quoted(value);
+        }
+        let width = match self.options.line_width {
+            Some(width) if attributes.len() > 1 => width,
+            _ => return,
+        };
+        let line_start = self.out.rfind('\n').map_or(0, |index| index + 1);
+        // the tag's `>` included
+        if self.out[line_start..].chars().count() < width {
+            return;
+        }
+        self.out.truncate(start);
+        for &(ref name, value) in attributes {
+            self.indent(depth + 1);
+            self.out.push_str(name);
+            self.out.push('=');
+            self.quoted(value);
+        }
+    }
+
+    ///Writes an attribute value within quotes
+    fn quoted(&mut self, value: &str) {
+        let quote = self.options.quote.as_char();
+        self.out.push(quote);
+        for c in value.chars() {
+            match c {
+                '&' => self.out.push_str("&amp;"),
+                '<' => self.out.push_str("&lt;"),
+                '>' if self.options.escape_gt => self.out.push_str("&gt;"),
+                '"' if quote == '"' => self.out.push_str("&quot;"),
+                '\'' if quote == '\'' => self.out.push_str("&apos;"),
+                // kept from being normalized to spaces when read back
+                '\t' => self.out.push_str("&#x9;"),
+                '\n' => self.out.push_str("&#xA;"),
+                '\r' => self.out.push_str("&#xD;"),
+                c => self.char(c),
+            }
+        }
+        self.out.push(quote);
+    }
+
+    fn text(&mut self, text: &str) {
+        for c in text.chars() {
+            match c {
+                '&' => self.out.push_str("&amp;"),
+                '<' => self.out.push_str("&lt;"),
+                '>' if self.options.escape_gt || self.out.ends_with("]]") => self.out.push_str("&gt;"),
+                '\n' => self.newline(),
+                // a carriage return would be read back as a line ending
+                '\r' => self.out.push_str("&#xD;"),
+                c => self.char(c),
+            }
+        }
+    }
+
+    ///Writes a character of text or of an attribute value
+    fn char(&mut self, c: char) {
+        let unmappable = self.target.map_or(false, |target| !can_encode(target, c));
+        if (self.options.escape_non_ascii && !c.is_ascii()) || unmappable {
+            self.out.push_str(&format!("&#{};", c as u32));
+        } else {
+            self.out.push(c);
+        }
+    }
+}
+
+///The content of `element` in document order
+fn content(element: &Element) -> Vec<Content<'_>> {
+    if element.nodes_match_content() {
+        element
+            .nodes
+            .iter()
+            .map(|node| match *node {
+                Node::Element(index) => Content::Element(&element.children[index]),
+                Node::Text(ref text) => Content::Text(&text.value, Some(node)),
+                _ => Content::Other(node),
+            })
+            .collect()
+    } else {
+        let text = element.text.as_deref().map(|text| Content::Text(text, None));
+        text.into_iter().chain(element.children.iter().map(Content::Element)).collect()
+    }
+}
+
+fn is_whitespace(text: &str) -> bool {
+    text.chars().all(|c| matches!(c, ' ' | '\t' | '\n' | '\r'))
+}
@@ -0,0 +1,300 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::Read;
+use xml::common::Position;
+use xml::reader::{EventReader, XmlEvent};
+use crate::attribute::{Attribute, Attributes};
+use crate::element::Element;
+use crate::encoding::Transcoder;
+use crate::errors::{QueryError, XmlErrors};
+use crate::options::{Budget, ParseOptions, DEFAULT_OPTIONS};
+use crate::position::{next_event, SourceReader};
+use crate::tokens::{AxesName, ValidToken};
+use crate::translator::tokenize;
+
+/// A forward-only XPath, made of child and descendant steps with simple predicates,
+/// optionally ending in an attribute step.
+///
+/// Unlike `ElementTree::query`, a position counts the matching children of each parent, as in
+/// XPath, so `//b[2]` selects the second `b` of every element that has two, and matches nested
+/// in an earlier match are not returned on their own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamQuery {
+    steps: Vec<StreamStep>,
+    attribute: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct StreamStep {
+    descendant: bool,
+    name: Option<String>,
+    predicates: Vec<StreamPredicate>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum StreamPredicate {
+    /// `[n]`, counting the matching siblings from 1
+    Position(usize),
+    /// `[@name]`
+    HasAttribute(String),
+    /// `[@name=value]`
+    AttributeEquals(String, String),
+}
+
+impl StreamQuery {
+    ///Parses `xpath`, which must be absolute and only use the child, descendant and attribute axes
+    pub fn new(xpath: &str) -> Result<StreamQuery, XmlErrors> {
+        let tokens = tokenize(xpath)?;
+        let mut query = StreamQuery {
+            steps: Vec::new(),
+            attribute: None,
+        };
+        let mut i = 0;
+        while i < tokens.len() {
+            let descendant = match tokens[i] {
+                ValidToken::Axes(AxesName::Child) => false,
+                ValidToken::Axes(AxesName::DescendantOrSelf) => true,
+                _ => return Err(QueryError::XPath.into()),
+            };
+            i += 1;
+
+            // an explicit axis may follow the slash
+            let mut axis = None;
+            if let (Some(ValidToken::Axes(name)), Some(ValidToken::LocationStep)) = (tokens.get(i), tokens.get(i + 1)) {
+                axis = Some(*name);
+                i += 2;
+            } else if let Some(ValidToken::Axes(name)) = tokens.get(i) {
+                axis = Some(*name);
+                i += 1;
+            }
+            let descendant = match axis {
+                None | Some(AxesName::Child) => descendant,
+                Some(AxesName::Descendant) => true,
+                Some(AxesName::Attribute) => {
+                    match tokens.get(i) {
+                        Some(ValidToken::Literal(name)) if !descendant && i + 1 == tokens.len() && !query.steps.is_empty() => {
+                            query.attribute = Some(name.clone());
+                            return Ok(query);
+                        }
+                        _ => return Err(QueryError::XPath.into()),
+                    }
+                }
+                Some(_) => return Err(QueryError::XPath.into()),
+            };
+
+            let name = match tokens.get(i) {
+                Some(ValidToken::Literal(name)) => Some(name.clone()),
+                Some(ValidToken::Asterisk) => None,
+                _ => return Err(QueryError::XPath.into()),
+            };
+            i += 1;
+
+            let mut predicates = Vec::new();
+            while tokens.get(i) == Some(&ValidToken::LeftBracket) {
+                let (predicate, used) = StreamQuery::predicate(&tokens[i + 1..])?;
+                predicates.push(predicate);
+                i += used + 1;
+            }
+            query.steps.push(StreamStep {
+                descendant,
+                name,
+                predicates,
+            });
+        }
+        if query.steps.is_empty() {
+            return Err(QueryError::XPath.into());
+        }
+        Ok(query)
+    }
+
+    ///Parses the predicate at the start of `tokens`, returning it and the tokens it used up to `]`
+    fn predicate(tokens: &[ValidToken]) -> Result<(StreamPredicate, usize), XmlErrors> {
+        use ValidToken::*;
+        match tokens {
+            [Number(n), RightBracket, ..] if *n >= 1.0 && n.fract() == 0.0 => {
+                Ok((StreamPredicate::Position(*n as usize), 2))
+            }
+            [Axes(AxesName::Attribute), Literal(name), Equal, Literal(value), RightBracket, ..] => {
+                Ok((StreamPredicate::AttributeEquals(name.clone(), value.clone()), 5))
+            }
+            [Axes(AxesName::Attribute), Literal(name), Equal, Number(value), RightBracket, ..] => {
+                Ok((StreamPredicate::AttributeEquals(name.clone(), value.to_string()), 5))
+            }
+            [Axes(AxesName::Attribute), Literal(name), RightBracket, ..] => {
+                Ok((StreamPredicate::HasAttribute(name.clone()), 3))
+            }
+            _ if !tokens.contains(&RightBracket) => Err(QueryError::XPathOpenBracket.into()),
+            _ => Err(QueryError::XPath.into()),
+        }
+    }
+
+    ///Evaluates the query over the document read from `read`, within the default `ParseOptions`
+    pub fn stream<R: Read>(&self, read: R) -> Matches<'static, R> {
+        self.stream_with_options(read, &DEFAULT_OPTIONS)
+    }
+
+    ///Evaluates the query over the document read from `read`, within the limits of `options`,
+    ///which apply to the whole document and not to each match on its own
+    pub fn stream_with_options<'o, R: Read>(&self, read: R, options: &'o ParseOptions) -> Matches<'o, R> {
+        Matches {
+            query: self.clone(),
+            reader: EventReader::new(SourceReader::new(Transcoder::new(read))),
+            stack: vec![Frame {
+                active: [0].into_iter().collect(),
+                ..Frame::default()
+            }],
+            budget: Budget::new(options),
+            done: false,
+        }
+    }
+}
+
+/// A node matched by a `StreamQuery`
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamItem {
+    /// A matching element, with its whole subtree
+    Element(Element),
+    /// A matching attribute, for queries ending in an attribute step
+    Attribute(Attribute),
+}
+
+/// What is known about an open element while streaming
+#[derive(Debug, Default)]
+struct Frame {
+    /// Steps that may be matched by children of this element
+    active: BTreeSet<usize>,
+    /// Descendant steps that may be matched anywhere below this element
+    pending: BTreeSet<usize>,
+    /// Per step and predicate, how many children got that far
+    counts: HashMap<(usize, usize), usize>,
+}
+
+/// Iterator over the nodes matched by a `StreamQuery`, in document order.
+///
+/// Only the open elements are remembered, apart from the subtree of the match being
+/// returned. Matches nested inside an earlier match are part of that match's subtree
+/// and are not returned on their own.
+pub struct Matches<'o, R: Read> {
+    query: StreamQuery,
+    reader: EventReader<SourceReader<Transcoder<R>>>,
+    stack: Vec<Frame>,
+    budget: Budget<'o>,
+    done: bool,
+}
+
+impl<'o, R: Read> Matches<'o, R> {
+    fn advance(&mut self) -> Result<Option<StreamItem>, XmlErrors> {
+        let last = self.query.steps.len();
+        loop {
+            let event = next_event(&mut self.reader)?;
+            let start = self.reader.source().locate(self.reader.position());
+            self.reader.source_mut().discard_before(start);
+            match event {
+                XmlEvent::StartElement { name, attributes, namespace: scope } => {
+                    let attributes = Attributes::from(attributes);
+                    let frame = self.step(name.prefix.as_deref(), &name.local_name, &attributes);
+                    let qname = match name.prefix {
+                        Some(ref prefix) => format!("{}:{}", prefix, name.local_name),
+                        None => name.local_name.clone(),
+                    };
+                    if frame.active.contains(&last) {
+                        match self.query.attribute {
+                            Some(ref wanted) => {
+                                let found = attributes.get_attribute(wanted).cloned();
+                                self.budget.enter(&qname, attributes.len(), start)?;
+                                self.stack.push(frame);
+                                if let Some(attr) = found {
+                                    return Ok(Some(StreamItem::Attribute(attr)));
+                                }
+                            }
+                            None => {
+                                let namespace = name.prefix.clone();
+                                let element = Element::parse_started(namespace, name, attributes, scope, start, &mut self.reader, &mut self.budget)?;
+                                return Ok(Some(StreamItem::Element(element)));
+                            }
+                        }
+                    } else {
+                        self.budget.enter(&qname, attributes.len(), start)?;
+                        self.stack.push(frame);
+                    }
+                }
+                XmlEvent::EndElement { .. } => {
+                    self.budget.leave();
+                    self.stack.pop();
+                }
+                XmlEvent::EndDocument => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+
+    ///Works out which steps an element opened under the top of the stack matches
+    fn step(&mut self, prefix: Option<&str>, local: &str, attributes: &Attributes) -> Frame {
+        let steps = &self.query.steps;
+        let parent = self.stack.last_mut().unwrap();
+        let mut frame = Frame::default();
+        let candidates: BTreeSet<usize> = parent.active.union(&parent.pending).cloned().collect();
+        for k in candidates {
+            if k == steps.len() {
+                continue;
+            }
+            let step = &steps[k];
+            if step.descendant {
+                frame.pending.insert(k);
+            } else if !parent.active.contains(&k) {
+                continue;
+            }
+            if !name_matches(step.name.as_deref(), prefix, local) {
+                continue;
+            }
+            let mut passed = true;
+            for (p, predicate) in step.predicates.iter().enumerate() {
+                let count = parent.counts.entry((k, p)).or_insert(0);
+                *count += 1;
+                passed = match predicate {
+                    StreamPredicate::Position(n) => *count == *n,
+                    StreamPredicate::HasAttribute(name) => attributes.contains(name),
+                    StreamPredicate::AttributeEquals(name, value) => attributes.get(name) == Some(value.as_str()),
+                };
+                if !passed {
+                    break;
+                }
+            }
+            if passed {
+                frame.active.insert(k + 1);
+            }
+        }
+        frame
+    }
+}
+
+impl<'o, R: Read> Iterator for Matches<'o, R> {
+    type Item = Result<StreamItem, XmlErrors>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.advance() {
+            Ok(Some(item)) => Some(Ok(item)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+///Unprefixed names match on the local name alone, like the rest of the query engine
+fn name_matches(test: Option<&str>, prefix: Option<&str>, local: &str) -> bool {
+    match test {
+        None => true,
+        Some(test) => match test.split_once(':') {
+            Some((test_prefix, test_local)) => prefix == Some(test_prefix) && local == test_local,
+            None => local == test,
+        },
+    }
+}
@@ -38,7 +38,7 @@ impl Display for AxesName {
 }
 
 
-#[derive(PartialEq)]
+#[derive(Debug, PartialEq)]
 pub enum ValidToken {
     /// ..
     Parent,
@@ -63,6 +63,8 @@ pub enum ValidToken {
     //This is attribute
     /// '::'
     LocationStep,
+    /// '*'
+    Asterisk,
     Literal(String),
     Number(f64),
     Axes(AxesName),
@@ -85,6 +87,7 @@ impl Display for ValidToken {
             ValidToken::AtSign => { write!(f, "@") }
             ValidToken::LocationStep => { write!(f, "::") }
             ValidToken::Equal => { write!(f, "=") }
+            ValidToken::Asterisk => { write!(f, "*") }
         }
     }
 }
@@ -106,6 +109,7 @@ impl Clone for ValidToken {
             ValidToken::AtSign => { ValidToken::AtSign }
             ValidToken::LocationStep => { ValidToken::LocationStep }
             ValidToken::Equal => { ValidToken::Equal }
+            ValidToken::Asterisk => { ValidToken::Asterisk }
         }
     }
 }
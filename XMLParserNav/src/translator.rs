@@ -1,19 +1,21 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{Debug, Display, Formatter};
-use crate::errors::Error;
+use std::ops::Range;
+use crate::errors::{QueryError, XmlErrors};
 // use crate::{tree, tokens};
 use crate::element::Element;
 use crate::tokens::{AxesName, ValidToken};
 use crate::parser::Token;
+use crate::position::Span;
 use crate::tree::ElementTree;
 
 
 ///This data structure takes in XML Tree and query
 pub struct Bridge {
     pub tree: ElementTree,
-    pub token: Token,
+    pub(crate) token: Token,
     pub token_steps: Vec<ValidToken>,
-    pub error: Option<Error>,
+    pub error: Option<QueryError>,
 }
 
 
@@ -31,10 +33,10 @@ impl Bridge {
     ///fills up the token step vector from token string
     pub fn token_step_filler(&mut self) {
         while !self.token.is_end() {
-            match self.token.next() {
-                Some(token) => { self.expand_abbreviation(token) }
-                None => {
-                    self.error = Some(Error::Token);
+            match self.token.next_func() {
+                Ok(token) => { expand_abbreviation(&mut self.token_steps, token) }
+                Err(err) => {
+                    self.error = Some(err);
                     return;
                 }
             }
@@ -42,395 +44,587 @@ impl Bridge {
     }
 
 
-    ///changes tokens to final Axes version of themselves
-    fn expand_abbreviation(&mut self, token: ValidToken) {
-        match token {
-            ValidToken::Period => {
-                self.token_steps.extend([
-                    ValidToken::Axes(AxesName::SelfAxis)
-                ].iter().cloned());
-            }
-            ValidToken::AtSign => {
-                self.token_steps.push(ValidToken::Axes(AxesName::Attribute));
-            }
-            ValidToken::Parent => {
-                self.token_steps.extend([
-                    ValidToken::Axes(AxesName::Parent)
-                ].iter().cloned());
-            }
-            ValidToken::DoubleForwardSlash => {
-                self.token_steps.extend([
-                    ValidToken::Axes(AxesName::DescendantOrSelf)
-                ].iter().cloned());
-            }
-            ValidToken::ForwardSlash => {
-                self.token_steps.extend([
-                    ValidToken::Axes(AxesName::Child)
-                ].iter().cloned());
-            }
-            _ => {
-                self.token_steps.push(token);
-            }
+    ///Method which makes sense of the tokenized XPath and the ElementTree passed to the Bridge
+    pub fn produce(&mut self) -> Result<Vec<ReturnEnum>, XmlErrors> {
+        if let Some(ref err) = self.error {
+            return Err(err.clone().into());
+        }
+        match self.tree.root {
+            Some(ref root) => evaluate(root, &self.token_steps),
+            None => Err(XmlErrors::ElementNotFound { t: "root".to_string() }),
         }
     }
 
-
-    ///Method which makes sense of the tokenized XPath and the ElementTree passed to the Bridge
-    pub fn produce(&mut self) -> Result<Vec<ReturnEnum>, Error>
-    {
-        let mut preceding_sibling_flag: bool = false;
-        let mut follow_sibling_flag: bool = false;
-        let mut equal_flag: bool = false;
-        let mut attribute_name = String::new();
-        let mut attribute_flag: bool = false;
-        let mut namespace_flag: bool = false;
-        let mut parent_flag: bool = false;
-        let mut aos_flag: bool = false;
-        let mut ancestor_flag: bool = false;
-        let mut self_flag: bool = false;
-        let mut is_valid_xpath: bool = false;
-        let mut ret_vec: Vec<&Element> = Vec::new();
-        let mut fin_ret_vec: Vec<ReturnEnum> = Vec::new();
-        let mut child_flag: bool = false;
-        let mut dos_flag: bool = false;
-        let mut descendant_flag: bool = false;
-        let root: &Element = self.tree.root.as_ref().unwrap();
-        let mut bracket_open: bool = false;
-
-
-        if self.token_steps.len() == 1 {
-            return Err(Error::XPath);
+    ///Like `produce`, but the matches of the last step are only looked for as they are asked
+    ///for, so that taking only the first one stops the evaluation there
+    pub fn produce_iter(&self) -> Result<TreeMatches<'_>, XmlErrors> {
+        if let Some(ref err) = self.error {
+            return Err(err.clone().into());
+        }
+        match self.tree.root {
+            Some(ref root) => evaluate_iter(root, &self.token_steps),
+            None => Err(XmlErrors::ElementNotFound { t: "root".to_string() }),
         }
+    }
+}
+
+
+///Splits an XPath into tokens, with abbreviations expanded to their axes
+pub fn tokenize(xpath: &str) -> Result<Vec<ValidToken>, QueryError> {
+    let mut token = Token::new(xpath.to_string());
+    let mut token_steps = Vec::new();
+    while !token.is_end() {
+        expand_abbreviation(&mut token_steps, token.next_func()?);
+    }
+    Ok(token_steps)
+}
+
 
-        if self.token_steps[0] != ValidToken::Axes(AxesName::DescendantOrSelf) &&
-            self.token_steps[0] != ValidToken::Axes(AxesName::Child) {
-            return Err(Error::XPath);
+///changes tokens to final Axes version of themselves
+fn expand_abbreviation(token_steps: &mut Vec<ValidToken>, token: ValidToken) {
+    match token {
+        ValidToken::Period => {
+            token_steps.push(ValidToken::Axes(AxesName::SelfAxis));
         }
+        ValidToken::AtSign => {
+            token_steps.push(ValidToken::Axes(AxesName::Attribute));
+        }
+        ValidToken::Parent => {
+            token_steps.push(ValidToken::Axes(AxesName::Parent));
+        }
+        ValidToken::DoubleForwardSlash => {
+            token_steps.push(ValidToken::Axes(AxesName::DescendantOrSelf));
+        }
+        ValidToken::ForwardSlash => {
+            token_steps.push(ValidToken::Axes(AxesName::Child));
+        }
+        _ => {
+            token_steps.push(token);
+        }
+    }
+}
 
-        for token in self.token_steps.clone() {
-            match token {
-                ValidToken::Equal => {
-                    if !attribute_flag || attribute_name.is_empty() {
-                        return Err(Error::XPath);
-                    } else {
-                        attribute_flag = false;
-                        equal_flag = true;
-                    }
+
+///Makes sense of the tokenized XPath, evaluating it against the tree under `root`
+pub fn evaluate<'a>(root: &'a Element, token_steps: &[ValidToken]) -> Result<Vec<ReturnEnum<'a>>, XmlErrors> {
+    Ok(evaluate_iter(root, token_steps)?.collect())
+}
+
+///Like `evaluate`, but when the query ends with a child or descendant step, the matches of
+///that step are only looked for as they are asked for
+pub fn evaluate_iter<'a>(root: &'a Element, token_steps: &[ValidToken]) -> Result<TreeMatches<'a>, XmlErrors> {
+    run(root, token_steps, None)
+}
+
+/// How many elements the engine had after a step of an evaluation
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Traced {
+    /// The tokens of the step
+    pub tokens: Range<usize>,
+    pub selected: usize,
+}
+
+///Like `evaluate`, also recording in `trace` how many elements each step left
+pub(crate) fn evaluate_traced<'a>(
+    root: &'a Element,
+    token_steps: &[ValidToken],
+    trace: &mut Vec<Traced>,
+) -> Result<Vec<ReturnEnum<'a>>, XmlErrors> {
+    Ok(run(root, token_steps, Some(trace))?.collect())
+}
+
+///Adds the tokens from `traced_to` up to `end`, and any `]` right after them, to `trace`
+fn record(trace: &mut Option<&mut Vec<Traced>>, token_steps: &[ValidToken], traced_to: &mut usize, mut end: usize, selected: usize) {
+    while token_steps.get(end) == Some(&ValidToken::RightBracket) {
+        end += 1;
+    }
+    if let Some(trace) = trace {
+        trace.push(Traced {
+            tokens: *traced_to..end,
+            selected,
+        });
+    }
+    *traced_to = end;
+}
+
+fn run<'a>(root: &'a Element, token_steps: &[ValidToken], mut trace: Option<&mut Vec<Traced>>) -> Result<TreeMatches<'a>, XmlErrors> {
+    let mut preceding_sibling_flag: bool = false;
+    let mut follow_sibling_flag: bool = false;
+    let mut equal_flag: bool = false;
+    let mut attribute_name = String::new();
+    let mut attribute_flag: bool = false;
+    let mut namespace_flag: bool = false;
+    let mut parent_flag: bool = false;
+    let mut aos_flag: bool = false;
+    let mut ancestor_flag: bool = false;
+    let mut self_flag: bool = false;
+    let mut is_valid_xpath: bool = false;
+    let mut ret_vec: Vec<&Element> = Vec::new();
+    let mut fin_ret_vec: Vec<ReturnEnum> = Vec::new();
+    let mut child_flag: bool = false;
+    let mut dos_flag: bool = false;
+    let mut descendant_flag: bool = false;
+    let mut bracket_open: bool = false;
+    // the tokens before this are in the trace
+    let mut traced_to = 0;
+
+
+    if token_steps.len() <= 1 {
+        return Err(QueryError::XPath.into());
+    }
+
+    if token_steps[0] != ValidToken::Axes(AxesName::DescendantOrSelf) &&
+        token_steps[0] != ValidToken::Axes(AxesName::Child) {
+        return Err(QueryError::XPath.into());
+    }
+
+    for (i, token) in token_steps.iter().cloned().enumerate() {
+        match token {
+            ValidToken::Equal => {
+                if !attribute_flag || attribute_name.is_empty() {
+                    return Err(QueryError::XPath.into());
+                } else {
+                    attribute_flag = false;
+                    equal_flag = true;
+                }
+            }
+            ValidToken::AtSign => {
+                if !bracket_open {
+                    return Err(QueryError::XPath.into());
                 }
-                ValidToken::AtSign => {
-                    if !bracket_open {
-                        return Err(Error::XPath);
+            }
+            ValidToken::LeftBracket => {
+                bracket_open = true;
+            }
+            ValidToken::RightBracket => {
+                if bracket_open {
+                    bracket_open = false;
+                    // `[@name]` keeps the elements that have the attribute
+                    if attribute_flag && !attribute_name.is_empty() {
+                        ret_vec.retain(|element| element.attributes.contains(&attribute_name));
+                        attribute_flag = false;
+                        is_valid_xpath = true;
+                        record(&mut trace, token_steps, &mut traced_to, i + 1, ret_vec.len());
                     }
+                } else {
+                    return Err(QueryError::XPathOpenBracket.into());
                 }
-                ValidToken::LeftBracket => {
-                    bracket_open = true;
+            }
+            // ValidToken::LeftParen => {}
+            // ValidToken::RightParen => {}
+            ValidToken::Literal(literal) => {
+                // an attribute name is part of the predicate that follows
+                let naming_attribute = attribute_flag;
+                // when nothing but a child or descendant step is left, its matches are found lazily
+                let last_step = i + 1 == token_steps.len() && trace.is_none() && !bracket_open && !namespace_flag
+                    && !(ancestor_flag || self_flag || aos_flag || parent_flag || attribute_flag || equal_flag)
+                    && !(follow_sibling_flag || preceding_sibling_flag);
+                // only the first step of an absolute path selects the root itself
+                let root_step = i == 1 && child_flag && is_named(root, &literal);
+                if last_step && child_flag && !dos_flag && !root_step && !ret_vec.is_empty() {
+                    let parents = ret_vec.drain(..).collect();
+                    return Ok(TreeMatches::new(Vec::new(), StepMatches::children(parents, literal)));
                 }
-                ValidToken::RightBracket => {
-                    if bracket_open {
-                        bracket_open = false;
-                    } else {
-                        return Err(Error::XPathOpenBracket);
-                    }
+                // and a leading `//` the root, where after a step it selects below that step's element
+                let root_step = root_step || (dos_flag && ret_vec.is_empty() && is_named(root, &literal));
+                if last_step && dos_flag && !child_flag && !root_step {
+                    let node = ret_vec.pop().unwrap_or(root);
+                    return Ok(TreeMatches::new(ret_vec, StepMatches::descendants(node, literal)));
                 }
-                // ValidToken::LeftParen => {}
-                // ValidToken::RightParen => {}
-                ValidToken::Literal(literal) => {
-                    /*This is logic for literal after ancestor token*/if ancestor_flag {
-                        let mut parent_map = HashMap::new();
-                        let mut ancestor_vec = VecDeque::new();
-                        ret_vec.clear();
-                        // ret_vec.push(root);
-                        ancestor_vec.push_back(root);
-
-                        while let Some(element) = ancestor_vec.pop_front() {
-                            for child in &element.children {
-                                // if child.tag != literal{
-                                if !parent_map.contains_key(child) {
-                                    parent_map.insert(child, element);
-                                    ancestor_vec.push_back(child);
-                                }
-                                // parent_map.entry(child).or_insert(element);
-                                if child.tag == literal {
-                                    ancestor_vec.clear(); //Comment this if you want all the paths instead of first
-                                    let mut changing_child = child;
-                                    while let Some(elem) = parent_map.get(changing_child) {
-                                        ret_vec.push(*elem);
-                                        changing_child = *elem;
-                                    }
-                                }
-                            }
-                        }
-                        ancestor_flag = false;
-                        if !aos_flag {
-                            is_valid_xpath = true;
-                        }
-                    }
-                    /*This is logic for literal after a child Token*/ if child_flag {
-                        if root.tag == literal {
-                            ret_vec.push(root)
-                        } else if !ret_vec.is_empty() {
-                            let mut old_element_vector = Vec::new();
-                            while let Some(element) = ret_vec.pop() {
-                                old_element_vector.push(element)
-                            }
+                /*This is logic for literal after ancestor token*/if ancestor_flag {
+                    let mut parent_map = HashMap::new();
+                    let mut ancestor_vec = VecDeque::new();
+                    ret_vec.clear();
+                    // ret_vec.push(root);
+                    ancestor_vec.push_back(root);
 
-                            for old_element in old_element_vector {
-                                for element in old_element.filter_children(|el| el.tag == literal) {
-                                    ret_vec.push(element)
-                                }
+                    while let Some(element) = ancestor_vec.pop_front() {
+                        for child in &element.children {
+                            // if child.tag != literal{
+                            if !parent_map.contains_key(child) {
+                                parent_map.insert(child, element);
+                                ancestor_vec.push_back(child);
                             }
-                        }
-                        child_flag = false;
-                        is_valid_xpath = true;
-                    }
-                    /*This is logic for literal after a descendant or self Token*/if dos_flag {
-                        let mut nodes: VecDeque<&Element> = VecDeque::new();
-                        if ret_vec.is_empty() {
-                            nodes.push_back(root);
-                        } else {
-                            nodes.push_back(ret_vec.pop().unwrap());
-                        }
-
-                        if nodes[0].tag == literal {
-                            ret_vec.push(nodes.pop_front().unwrap());
-                            is_valid_xpath = true;
-                            if !descendant_flag {
-                                break;
-                            }
-                        }
-
-                        while let Some(node) = nodes.pop_front() {
-                            for child in &node.children {
-                                if child.tag == literal {
-                                    // ret_vec.clear();
-                                    ret_vec.push(child);
-                                    for child in &child.children {
-                                        for grandchild in &child.children {
-                                            ret_vec.push(grandchild)
-                                        }
-                                    }
-                                } else {
-                                    nodes.push_back(child)
+                            // parent_map.entry(child).or_insert(element);
+                            if is_named(child, &literal) {
+                                ancestor_vec.clear(); //Comment this if you want all the paths instead of first
+                                let mut changing_child = child;
+                                while let Some(elem) = parent_map.get(changing_child) {
+                                    ret_vec.push(*elem);
+                                    changing_child = *elem;
                                 }
                             }
                         }
-
-                        dos_flag = false;
-                        is_valid_xpath = true;
                     }
-                    /* This removes self for descendant*/ if descendant_flag {
-                        // ret_vec.pop();
-                        descendant_flag = true;
+                    ancestor_flag = false;
+                    if !aos_flag {
                         is_valid_xpath = true;
                     }
-                    /*This is logic for literal after self token*/if self_flag {
-                        if ret_vec.is_empty() {
-                            ret_vec.push(root);
-                        } else {
-                            let parent = ret_vec.pop().unwrap();
-                            ret_vec.clear();
-                            ret_vec.push(parent.find_child(|el| el.tag == literal).unwrap());
-                            self_flag = false;
-                            is_valid_xpath = true;
-                        }
+                }
+                /*This is logic for literal after a child Token*/ if child_flag {
+                    if root_step {
+                        ret_vec.push(root)
+                    } else if !ret_vec.is_empty() {
+                        let parents = ret_vec.drain(..).collect();
+                        ret_vec.extend(StepMatches::children(parents, literal.clone()));
                     }
-                    /*This is logic for literal after ancestor-or-self token*/if aos_flag {
-                        let temp_vec = ret_vec.clone();
-                        ret_vec.clear();
-                        ret_vec.push(temp_vec[0].find_child(|el| el.tag == literal).unwrap());
-                        for i in temp_vec {
-                            ret_vec.push(i);
-                        }
+                    child_flag = false;
+                    is_valid_xpath = true;
+                }
+                /*This is logic for literal after a descendant or self Token*/if dos_flag {
+                    let node = ret_vec.pop().unwrap_or(root);
+                    if root_step {
+                        ret_vec.push(node);
                         is_valid_xpath = true;
-                    }
-                    /*This is logic for literal after parent token*/if parent_flag {
-                        let mut parent_map = HashMap::new();
-                        let mut ancestor_vec = VecDeque::new();
-                        ret_vec.clear();
-                        ancestor_vec.push_back(root);
-
-                        while let Some(element) = ancestor_vec.pop_front() {
-                            for child in &element.children {
-                                if !parent_map.contains_key(child) {
-                                    parent_map.insert(child, element);
-                                    ancestor_vec.push_back(child);
-                                }
-                                if child.tag == literal {
-                                    ancestor_vec.clear();//Comment this if you want all the parents instead of first
-                                    ret_vec.push(parent_map.get(child).unwrap());
-                                }
-                            }
-                        }
-                        ancestor_flag = false;
-                        if !aos_flag {
-                            is_valid_xpath = true;
+                        if !descendant_flag {
+                            record(&mut trace, token_steps, &mut traced_to, i + 1, ret_vec.len());
+                            break;
                         }
+                    } else {
+                        ret_vec.extend(StepMatches::descendants(node, literal.clone()));
                     }
-                    /*This is logic for literal after attribute token*/if attribute_flag {
-                        attribute_name = literal.clone();
-                    }
-                    /*This is logic for literal after equal token*/if equal_flag {
-                        /*let mut final_el : &Element = &Element::default();
-                        for element in &ret_vec {
-                            if element.attributes.get(&*attribute_name).unwrap().to_string() == literal {
-                                final_el = *element;
-                            }
-                        }
+
+                    dos_flag = false;
+                    is_valid_xpath = true;
+                }
+                /* This removes self for descendant*/ if descendant_flag {
+                    // ret_vec.pop();
+                    descendant_flag = true;
+                    is_valid_xpath = true;
+                }
+                /*This is logic for literal after self token*/if self_flag {
+                    if ret_vec.is_empty() {
+                        ret_vec.push(root);
+                    } else {
+                        let parent = ret_vec.pop().unwrap();
                         ret_vec.clear();
-                        ret_vec.push(final_el);
+                        ret_vec.extend(parent.find_child(|el| is_named(el, &literal)));
+                        self_flag = false;
                         is_valid_xpath = true;
-                        break;*/
-
-                        while let Some(element) = ret_vec.pop() {
-                            if literal == element.attributes.get(&*attribute_name).unwrap().to_string() {
-                                ret_vec.clear();
-                                ret_vec.push(element);
-                                is_valid_xpath = true;
-                                break;
-                            }
-                        }
                     }
-                    /*This is logic for literal after follow sibling token*/if follow_sibling_flag {
-                        let mut found: bool = false;
-                        let parent = ret_vec.pop().unwrap();
-                        ret_vec.clear();
-                        for sibling in &parent.children {
-                            if found {
-                                ret_vec.push(sibling);
+                }
+                /*This is logic for literal after ancestor-or-self token*/if aos_flag {
+                    let temp_vec = std::mem::take(&mut ret_vec);
+                    if let Some(first) = temp_vec.first() {
+                        ret_vec.extend(first.find_child(|el| is_named(el, &literal)));
+                    }
+                    ret_vec.extend(temp_vec);
+                    is_valid_xpath = true;
+                }
+                /*This is logic for literal after parent token*/if parent_flag {
+                    let mut parent_map = HashMap::new();
+                    let mut ancestor_vec = VecDeque::new();
+                    ret_vec.clear();
+                    ancestor_vec.push_back(root);
+
+                    while let Some(element) = ancestor_vec.pop_front() {
+                        for child in &element.children {
+                            if !parent_map.contains_key(child) {
+                                parent_map.insert(child, element);
+                                ancestor_vec.push_back(child);
                             }
-                            if sibling.tag == literal {
-                                found = true;
+                            if is_named(child, &literal) {
+                                ancestor_vec.clear();//Comment this if you want all the parents instead of first
+                                ret_vec.push(parent_map.get(child).unwrap());
                             }
                         }
+                    }
+                    ancestor_flag = false;
+                    if !aos_flag {
                         is_valid_xpath = true;
-                        break;
                     }
-                    /*This is logic for literal after preceding sibling token*/if preceding_sibling_flag {
-                        let mut found: bool = false;
-                        let parent = ret_vec.pop().unwrap();
-                        ret_vec.clear();
-                        for sibling in &parent.children {
-                            if sibling.tag == literal {
-                                found = true;
-                            }
-                            if !found {
-                                ret_vec.push(sibling);
-                            }
+                }
+                /*This is logic for literal after attribute token*/if attribute_flag {
+                    attribute_name = literal.clone();
+                }
+                /*This is logic for literal after equal token*/if equal_flag {
+                    /*let mut final_el : &Element = &Element::default();
+                    for element in &ret_vec {
+                        if element.attributes.get(&*attribute_name).unwrap().to_string() == literal {
+                            final_el = *element;
                         }
-                        is_valid_xpath = true;
-                        break;
                     }
+                    ret_vec.clear();
+                    ret_vec.push(final_el);
+                    is_valid_xpath = true;
+                    break;*/
+
+                    // matching nothing leaves nothing, which is not an error
+                    keep_attribute_equal(&mut ret_vec, &attribute_name, &literal);
+                    is_valid_xpath = true;
+                    // the predicate is applied once, not again to the steps after it
+                    equal_flag = false;
                 }
-                ValidToken::Number(num) => {
-                    if bracket_open {
-                        if ret_vec.is_empty() {
-                            return Err(Error::XPath);
+                /*This is logic for literal after a sibling token*/if follow_sibling_flag || preceding_sibling_flag {
+                    // the siblings after the first, or before the last, element of the step before
+                    let context: HashSet<*const Element> = ret_vec.drain(..).map(|el| el as *const Element).collect();
+                    let in_context = |el: &Element| context.contains(&(el as *const Element));
+                    for parent in root.iter() {
+                        let siblings = &parent.children[..];
+                        let selected = if follow_sibling_flag {
+                            siblings.iter().position(in_context).map_or(&[][..], |first| &siblings[first + 1..])
                         } else {
-                            if ret_vec.len() < num as usize {
-                                return Err(Error::XPath);
-                            }
-                            let ret_el = ret_vec[num as usize - 1];
-                            ret_vec.clear();
-                            ret_vec.push(ret_el);
-                        }
-                    } else {
-                        return Err(Error::XPath);
+                            siblings.iter().rposition(in_context).map_or(&[][..], |last| &siblings[..last])
+                        };
+                        ret_vec.extend(selected.iter().filter(|sibling| is_named(sibling, &literal)));
                     }
+                    is_valid_xpath = true;
+                    record(&mut trace, token_steps, &mut traced_to, i + 1, ret_vec.len());
+                    break;
                 }
-                ValidToken::Axes(axes_name) => {
-                    match axes_name {
-                        AxesName::Ancestor => {
-                            if !dos_flag {
-                                return Err(Error::XPath);
-                            }
-                            dos_flag = false;
-                            ancestor_flag = true;
-                            is_valid_xpath = false;
-                        }
-                        AxesName::AncestorOrSelf => {
-                            if !dos_flag {
-                                return Err(Error::XPath);
-                            }
-                            dos_flag = false;
-                            ancestor_flag = true;
-                            is_valid_xpath = false;
-                            aos_flag = true;
-                        }
-                        AxesName::Parent => {
-                            parent_flag = true;
-                        }
-                        AxesName::Attribute => {
-                            attribute_flag = true;
-                            is_valid_xpath = false;
+                if !naming_attribute {
+                    record(&mut trace, token_steps, &mut traced_to, i + 1, ret_vec.len());
+                }
+            }
+            ValidToken::Number(num) => {
+                if equal_flag {
+                    // compared as the text of the number, as by `StreamQuery`
+                    keep_attribute_equal(&mut ret_vec, &attribute_name, &num.to_string());
+                    is_valid_xpath = true;
+                    equal_flag = false;
+                    record(&mut trace, token_steps, &mut traced_to, i + 1, ret_vec.len());
+                } else if bracket_open && num >= 1.0 {
+                    // a position past the last element selects nothing
+                    let ret_el = ret_vec.get(num as usize - 1).copied();
+                    ret_vec.clear();
+                    ret_vec.extend(ret_el);
+                    record(&mut trace, token_steps, &mut traced_to, i + 1, ret_vec.len());
+                } else {
+                    return Err(QueryError::XPath.into());
+                }
+            }
+            ValidToken::Axes(axes_name) => {
+                match axes_name {
+                    AxesName::Ancestor => {
+                        if !dos_flag {
+                            return Err(QueryError::XPath.into());
                         }
-                        AxesName::Namespace => {
-                            if child_flag {
-                                child_flag = false;
-                            }
-                            namespace_flag = true;
-                            is_valid_xpath = true;
+                        dos_flag = false;
+                        ancestor_flag = true;
+                        is_valid_xpath = false;
+                    }
+                    AxesName::AncestorOrSelf => {
+                        if !dos_flag {
+                            return Err(QueryError::XPath.into());
                         }
-                        AxesName::Child => {
-                            child_flag = true;
-                            is_valid_xpath = false;
+                        dos_flag = false;
+                        ancestor_flag = true;
+                        is_valid_xpath = false;
+                        aos_flag = true;
+                    }
+                    AxesName::Parent => {
+                        parent_flag = true;
+                    }
+                    AxesName::Attribute => {
+                        // attributes are only tested in predicates; `select_all` reads their values
+                        if !bracket_open {
+                            return Err(QueryError::XPath.into());
                         }
-                        AxesName::Descendant => {
-                            descendant_flag = true;
-                            dos_flag = true;
+                        attribute_flag = true;
+                        is_valid_xpath = false;
+                    }
+                    AxesName::Namespace => {
+                        if child_flag {
+                            child_flag = false;
                         }
-                        AxesName::DescendantOrSelf => {
-                            dos_flag = true;
-                            is_valid_xpath = false;
+                        namespace_flag = true;
+                        is_valid_xpath = true;
+                    }
+                    AxesName::Child => {
+                        child_flag = true;
+                        is_valid_xpath = false;
+                    }
+                    AxesName::Descendant => {
+                        if child_flag {
+                            child_flag = false;
                         }
-                        AxesName::SelfAxis => {
-                            if child_flag {
-                                child_flag = false;
-                            }
-                            is_valid_xpath = true;
+                        descendant_flag = true;
+                        dos_flag = true;
+                    }
+                    AxesName::DescendantOrSelf => {
+                        dos_flag = true;
+                        is_valid_xpath = false;
+                    }
+                    AxesName::SelfAxis => {
+                        if child_flag {
+                            child_flag = false;
                         }
-                        AxesName::Following => {}//TBI
-                        AxesName::FollowingSibling => {
-                            parent_flag = true;
-                            follow_sibling_flag = true;
-                            is_valid_xpath = false;
+                        is_valid_xpath = true;
+                    }
+                    AxesName::Following => {}//TBI
+                    AxesName::FollowingSibling => {
+                        if child_flag {
+                            child_flag = false;
                         }
-                        AxesName::Preceding => {}//TBI
-                        AxesName::PrecedingSibling => {
-                            parent_flag = true;
-                            preceding_sibling_flag = true;
-                            is_valid_xpath = false;
+                        follow_sibling_flag = true;
+                        is_valid_xpath = false;
+                    }
+                    AxesName::Preceding => {}//TBI
+                    AxesName::PrecedingSibling => {
+                        if child_flag {
+                            child_flag = false;
                         }
+                        preceding_sibling_flag = true;
+                        is_valid_xpath = false;
                     }
                 }
-                _ => {}
             }
+            _ => {}
         }
+    }
+
 
+    if bracket_open {
+        is_valid_xpath = false;
+    }
 
-        if bracket_open {
-            is_valid_xpath = false;
+    if namespace_flag {
+        // no element left, such as after a predicate matching nothing, has no namespace to give
+        let ret_val = match ret_vec.pop() {
+            Some(ret_val) => ret_val,
+            None => return Ok(TreeMatches::found(fin_ret_vec)),
+        };
+        return match ret_val.namespace.as_ref() {
+            Some(t) => {
+                fin_ret_vec.push(ReturnEnum::ElementName(t.clone()));
+                Ok(TreeMatches::found(fin_ret_vec))
+            }
+            None => {
+                Err(QueryError::NoNamespace.into())
+            }
+        };
+    }
+    return if is_valid_xpath {
+        for i in ret_vec {
+            fin_ret_vec.push(ReturnEnum::ElementNode(i))
+        }
+        Ok(TreeMatches::found(fin_ret_vec))
+    } else {
+        Err(QueryError::XPath.into())
+    };
+}
+
+///Whether `element` is named `literal`, which only has to match the local name when it has no prefix
+///Keeps the elements whose attribute `name` is `value`
+fn keep_attribute_equal(elements: &mut Vec<&Element>, name: &str, value: &str) {
+    elements.retain(|element| element.attributes.get(name) == Some(value));
+}
+
+fn is_named(element: &Element, literal: &str) -> bool {
+    match literal.split_once(':') {
+        Some((prefix, local)) => element.namespace.as_deref() == Some(prefix) && element.tag == local,
+        None => element.tag == literal,
+    }
+}
+
+/// The matches of a query, those of its last step only looked for as they are asked for.
+///
+/// Comes from `Element::query_iter`, `ElementTree::query_iter` or `Bridge::produce_iter`, and
+/// yields the same nodes in the same order as `query` and `produce` return.
+pub struct TreeMatches<'a> {
+    found: std::vec::IntoIter<ReturnEnum<'a>>,
+    rest: StepMatches<'a>,
+}
+
+impl<'a> TreeMatches<'a> {
+    fn new(found: Vec<&'a Element>, rest: StepMatches<'a>) -> TreeMatches<'a> {
+        TreeMatches {
+            found: found.into_iter().map(ReturnEnum::ElementNode).collect::<Vec<_>>().into_iter(),
+            rest,
+        }
+    }
+
+    fn found(found: Vec<ReturnEnum<'a>>) -> TreeMatches<'a> {
+        TreeMatches {
+            found: found.into_iter(),
+            rest: StepMatches::Done,
+        }
+    }
+
+    ///The first match, without looking for the others
+    pub fn first(mut self) -> Option<ReturnEnum<'a>> {
+        self.next()
+    }
+
+    ///Whether anything matches, stopping at the first match
+    pub fn exists(mut self) -> bool {
+        self.next().is_some()
+    }
+}
+
+impl<'a> Iterator for TreeMatches<'a> {
+    type Item = ReturnEnum<'a>;
+
+    fn next(&mut self) -> Option<ReturnEnum<'a>> {
+        match self.found.next() {
+            Some(found) => Some(found),
+            None => self.rest.next().map(ReturnEnum::ElementNode),
         }
+    }
+}
+
+/// The elements a child or descendant step selects, found one at a time
+enum StepMatches<'a> {
+    Done,
+    /// Children named `literal` of each of `parents` in turn
+    Children {
+        literal: String,
+        parents: std::vec::IntoIter<&'a Element>,
+        children: std::slice::Iter<'a, Element>,
+    },
+    /// Elements named `literal` below an element, in document order
+    Descendants {
+        literal: String,
+        /// The children still to visit on each level of the walk
+        levels: Vec<std::slice::Iter<'a, Element>>,
+    },
+}
+
+impl<'a> StepMatches<'a> {
+    fn children(parents: Vec<&'a Element>, literal: String) -> StepMatches<'a> {
+        StepMatches::Children {
+            literal,
+            parents: parents.into_iter(),
+            children: [].iter(),
+        }
+    }
+
+    fn descendants(node: &'a Element, literal: String) -> StepMatches<'a> {
+        StepMatches::Descendants {
+            literal,
+            levels: vec![node.children.iter()],
+        }
+    }
+}
 
-        if namespace_flag {
-            let ret_val = ret_vec.pop().unwrap();
-            return match ret_val.namespace.as_ref() {
-                Some(t) => {
-                    fin_ret_vec.push(ReturnEnum::ElementName(t.clone()));
-                    Ok(fin_ret_vec)
+impl<'a> Iterator for StepMatches<'a> {
+    type Item = &'a Element;
+
+    fn next(&mut self) -> Option<&'a Element> {
+        match self {
+            StepMatches::Done => None,
+            StepMatches::Children { literal, parents, children } => loop {
+                match children.next() {
+                    Some(child) if is_named(child, literal) => return Some(child),
+                    Some(_) => {}
+                    None => *children = parents.next()?.children.iter(),
                 }
-                None => {
-                    Err(Error::NoNamespace)
+            },
+            StepMatches::Descendants { literal, levels } => loop {
+                match levels.last_mut()?.next() {
+                    Some(child) => {
+                        // matches may have matching elements below them too
+                        levels.push(child.children.iter());
+                        if is_named(child, literal) {
+                            return Some(child);
+                        }
+                    }
+                    None => {
+                        levels.pop();
+                    }
                 }
-            };
+            },
         }
-        return if is_valid_xpath {
-            for i in ret_vec {
-                fin_ret_vec.push(ReturnEnum::ElementNode(i))
-            }
-            Ok(fin_ret_vec)
-        } else {
-            Err(Error::XPath)
-        };
     }
 }
 
@@ -439,6 +633,16 @@ pub enum ReturnEnum<'a> {
     ElementName(String),
 }
 
+impl<'a> ReturnEnum<'a> {
+    ///Where the returned element was found in the source document
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ReturnEnum::ElementNode(element) => element.span,
+            ReturnEnum::ElementName(_) => None,
+        }
+    }
+}
+
 impl<'a> Display for ReturnEnum<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
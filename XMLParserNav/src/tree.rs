@@ -1,12 +1,26 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
 use std::fmt;
-use std::io::{Read, Write};
-use xml::common::XmlVersion;
-use crate::element::Element;
-use xml::reader::{EventReader, XmlEvent};
-use xml::writer::EmitterConfig;
-use xml::writer::XmlEvent as writer_XmlEvent;
-use crate::errors::XmlErrors;
+use std::io::{self, Cursor, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use xml::common::{Position, XmlVersion};
+use crate::attribute::Attributes;
+use crate::c14n::{self, Canonicalization};
+use crate::element::{Comment, Descendants, Element, Node, ProcessingInstruction};
+use xml::reader::{EventReader, ParserConfig, XmlEvent};
+use xml::writer::Error as EmitterError;
+use crate::encoding::{self, Transcoder};
+use crate::dtd::{self, Doctype};
+use crate::errors::{ParseError, ParseErrorKind, XmlErrors};
+use crate::input;
+use crate::lossless::Lossless;
+#[cfg(feature = "mmap")]
+use crate::input::MappedFile;
+use crate::options::{Budget, ParseOptions};
+use crate::recover::Recovery;
+use crate::serialize::{self, WriteOptions};
+use crate::position::{location_after, next_event, SourceReader, Span};
+use crate::translator::{evaluate, tokenize, ReturnEnum, TreeMatches};
 
 
 ///Tree of XML Nodes(Elements)
@@ -14,6 +28,14 @@ pub struct ElementTree {
     pub root: Option<Element>,
     pub version: XmlVersion,
     pub encoding: String,
+    /// The document type declaration, if the document has one
+    pub doctype: Option<Doctype>,
+    /// Comments and processing instructions before the root element
+    pub prolog: Vec<Node>,
+    /// Comments and processing instructions after the root element
+    pub epilog: Vec<Node>,
+    /// The source of a tree parsed with `parse_lossless`, so untouched parts are written as they were
+    pub lossless: Option<Lossless>,
 }
 
 impl Default for ElementTree {
@@ -22,89 +44,99 @@ impl Default for ElementTree {
             root: None,
             version: XmlVersion::Version10,
             encoding: "UTF-8".to_string(),
+            doctype: None,
+            prolog: Vec::new(),
+            epilog: Vec::new(),
+            lossless: None,
         }
     }
 }
 
 impl ElementTree {
     ///Method creates a new `ElementTree`
-    fn new() -> ElementTree {
+    pub fn new() -> ElementTree {
         ElementTree {
             ..ElementTree::default()
         }
     }
 
-
-
-    ///Abandoned effort
-/*
-    /*///Method creates a new `ElementTree` with root from ElementBuilder
-    fn new_with_root(root: ElementBuilder) -> ElementTree {
+    ///Method creates a new `ElementTree` with `root` (an `Element` or `ElementBuilder`)
+    pub fn new_with_root<E: Into<Element>>(root: E) -> ElementTree {
         ElementTree {
-            root: Some(root.element()),
-            ..Self::default()
-        }
-    }*/
-
- /*   ///Returns true if root is None and false if the ElementTree is not empty
-    fn check_blank_tree(&self)->bool{
-        return self.root.is_none()
-    }*/
-
-
-    /*///Returns reference to the root element
-    fn get_root(&self) -> Option<Element> {
-        if !self.check_blank_tree() {
-            return Some(self.root.as_ref().unwrap().clone());
+            root: Some(root.into()),
+            ..ElementTree::default()
         }
-        None
-    }*/
+    }
 
+    ///Returns reference to the root element
+    pub fn get_root(&self) -> Option<&Element> {
+        self.root.as_ref()
+    }
 
-    /*///Returns mutable reference to the root element
-    fn get_root_mut(&self) -> Option<&mut Element> {
-        if self.root.is_some() {
-            return Some(&mut self.root.as_ref().unwrap().clone());
-        }
-        None
+    ///Returns mutable reference to the root element
+    pub fn get_root_mut(&mut self) -> Option<&mut Element> {
+        self.root.as_mut()
     }
-*/
-    //Add set root here(if needed)
 
-    */
+    ///Load external XML document into element tree, within the default `ParseOptions`.
+    ///Documents nested deeper than 256 elements are rejected with `Limit::Depth`; use
+    ///`parse_with_options` with a larger `max_depth` to read them.
+    pub fn parse<T: Read>(read: T) -> Result<ElementTree, XmlErrors> {
+        ElementTree::parse_with_options(read, &ParseOptions::default())
+    }
 
-    ///Load external XML document into element tree
-    pub(crate) fn parse<T: Read>(read: T) -> Result<ElementTree, XmlErrors> {
-        let mut parser = EventReader::new(read);
+    ///Load external XML document into element tree, within the limits of `options`
+    pub fn parse_with_options<T: Read>(read: T, options: &ParseOptions) -> Result<ElementTree, XmlErrors> {
+        let mut read = Transcoder::new(read);
         let mut tree = ElementTree::new();
 
+        let mut config = ParserConfig::new().ignore_comments(false);
+        let (prolog, doctype) = dtd::read_doctype(&mut read, &mut config, options)?;
+        tree.doctype = doctype;
+        let source = SourceReader::new(Cursor::new(prolog).chain(read));
+        let mut parser = EventReader::new_with_config(source, config);
+
         loop {
-            let event = parser.next()?;
+            let event = next_event(&mut parser)?;
+            let start = parser.source().locate(parser.position());
+            parser.source_mut().discard_before(start);
             match event {
                   XmlEvent::StartDocument {
                       version, encoding, ..
                   } => {
                       tree.version = XmlVersion::from(version);
                       tree.encoding = encoding;
+                      // a byte order mark wins over a missing or contradicting declaration
+                      if let Some(detected) = parser.source().get_ref().get_ref().1.encoding() {
+                          if encoding::for_label(&tree.encoding) != Some(detected) {
+                              tree.encoding = detected.name().to_string();
+                          }
+                      }
                   },
-                XmlEvent::StartElement { name, attributes, .. } => {
-                    let mut attribute = HashMap::new();
-                    for att in attributes {
-                        let attr_name = match att.name.prefix {
-                            Some(p) => { format!("{}:{}", p, att.name.local_name) }
-                            None => { att.name.local_name }
-                        };
-                        attribute.insert(attr_name, att.value);
+                XmlEvent::StartElement { name, attributes, namespace: scope } => {
+                    if tree.root.is_some() {
+                        let msg = "Unexpected element after the root element".to_string();
+                        return Err(ParseError::new(ParseErrorKind::Syntax(msg), Some(start)).into());
+                    }
+                    let namespace = name.prefix.clone();
+                    let attributes = Attributes::from(attributes);
+                    let mut budget = Budget::new(options);
+                    let mut root = Element::parse_started(namespace, name, attributes, scope, start, &mut parser, &mut budget)
+                        .map_err(|err| dtd::unresolved_entity(err, tree.doctype.as_ref()))?;
+                    if let Some(ref doctype) = tree.doctype {
+                        doctype.add_default_attributes(&mut root, options)?;
                     }
-                    let mut root = Element {
-                        namespace: name.namespace,
-                        tag: name.local_name,
-                        attributes: attribute,
-                        ..Element::default()
-                    };
-                    root.parse(&mut parser)?;
                     tree.root = Some(root);
                 }
+                XmlEvent::Comment(value) => {
+                    let span = Some(Span { start, end: parser.source().current() });
+                    tree.misc_mut().push(Node::Comment(Comment { value, span }));
+                }
+                XmlEvent::ProcessingInstruction { name, data } => {
+                    let span = Some(Span { start, end: parser.source().current() });
+                    let pi = ProcessingInstruction { target: name, data, span };
+                    tree.misc_mut().push(Node::ProcessingInstruction(pi));
+                }
                 XmlEvent::EndDocument => break,
                 _ => {}
             }
@@ -112,41 +144,183 @@ impl ElementTree {
         Ok(tree)
     }
 
-    pub fn write<T: Write>(&self, mut t: &mut T) -> Result<(), XmlErrors> {
-        self.write_with(&mut t, true, " ", true)
+    ///Loads a document to be edited and written back, keeping its source so the parts that are
+    ///not edited are written byte for byte as they were, whitespace, quotes, entity references
+    ///and empty-element tags included. Whitespace between tags is kept as text.
+    pub fn parse_lossless<T: Read>(mut read: T) -> Result<ElementTree, XmlErrors> {
+        let mut bytes = Vec::new();
+        read.read_to_end(&mut bytes)?;
+        let options = ParseOptions { preserve_whitespace: true, ..ParseOptions::new() };
+        let mut tree = ElementTree::parse_with_options(&bytes[..], &options)?;
+        // the spans count bytes of the input as decoded for the parser
+        let mut source = String::new();
+        Transcoder::new(&bytes[..]).read_to_string(&mut source)?;
+        tree.lossless = Some(Lossless::new(source, &tree));
+        Ok(tree)
     }
 
-    ///Writes to `t`
-    pub(crate) fn write_with<T: Write>(&self, t: &mut T, doc_decl: bool, indnt_str: &'static str, indent: bool) //Removed 'static from indnt_str
-                            -> Result<(), XmlErrors> {
+    ///The prolog until the root element is parsed, the epilog after
+    fn misc_mut(&mut self) -> &mut Vec<Node> {
+        match self.root {
+            Some(_) => &mut self.epilog,
+            None => &mut self.prolog,
+        }
+    }
+
+    ///Parses a document held in a string, within the default `ParseOptions` like `parse`
+    pub fn parse_str(doc: &str) -> Result<ElementTree, XmlErrors> {
+        ElementTree::parse(doc.as_bytes())
+    }
+
+    ///Parses a document held in a byte slice, decompressing it if needed
+    pub fn parse_bytes(doc: &[u8]) -> Result<ElementTree, XmlErrors> {
+        ElementTree::parse(input::decompress(doc)?)
+    }
 
+    ///Parses the document stored in the file at `path`, decompressing it if needed
+    pub fn parse_path<P: AsRef<Path>>(path: P) -> Result<ElementTree, XmlErrors> {
+        ElementTree::parse(input::open(path)?)
+    }
 
-        let mut writer = EmitterConfig::new().perform_indent(indent)
-            .write_document_declaration(doc_decl)
-            .indent_string(indnt_str)
-            .create_writer(t);
+    ///Parses the file at `path` through a memory map, decompressing it if needed
+    #[cfg(feature = "mmap")]
+    pub fn parse_mmap<P: AsRef<Path>>(path: P) -> Result<ElementTree, XmlErrors> {
+        let map = MappedFile::open(path)?;
+        ElementTree::parse_bytes(&map)
+    }
 
-        if doc_decl {
-            writer.write(writer_XmlEvent::StartDocument {
-                version: self.version.into(),
-                encoding: Some(&self.encoding),
-                standalone: None,
-            })?;
+    ///Loads broken XML or HTML-like markup as well as it can, instead of stopping at the first error.
+    ///
+    ///Unclosed elements are closed, stray end tags dropped and bad markup kept as text;
+    ///the errors that were repaired are returned with the tree, whose root is `None` without elements.
+    pub fn parse_lenient<T: Read>(read: T) -> Result<(ElementTree, Vec<ParseError>), XmlErrors> {
+        ElementTree::parse_lenient_with_options(read, &ParseOptions::default())
+    }
+
+    ///Loads broken markup like `parse_lenient`, within the limits of `options`, which end the parse
+    ///when they are exceeded rather than being repaired
+    pub fn parse_lenient_with_options<T: Read>(read: T, options: &ParseOptions)
+                                               -> Result<(ElementTree, Vec<ParseError>), XmlErrors> {
+        let mut bytes = Vec::new();
+        let mut read = Transcoder::new(read);
+        read.read_to_end(&mut bytes).map_err(|err| match err.kind() {
+            io::ErrorKind::InvalidData => ParseError::new(ParseErrorKind::Encoding, None).into(),
+            _ => XmlErrors::from(err),
+        })?;
+        let input = String::from_utf8_lossy(&bytes);
+        let (document, mut diagnostics) = Recovery::parse(&input, options)?;
+        if let Cow::Owned(_) = input {
+            let location = location_after(&bytes[..std::str::from_utf8(&bytes).unwrap_err().valid_up_to()]);
+            diagnostics.insert(0, ParseError::new(ParseErrorKind::Encoding, Some(location)));
         }
-        if let Some(ref e) = self.root {
-            e.write(&mut writer)?;
+        let mut tree = match document {
+            Some(document) => document.to_tree(),
+            None => ElementTree::new(),
+        };
+        if let Some(detected) = read.encoding() {
+            if encoding::for_label(&tree.encoding) != Some(detected) {
+                tree.encoding = detected.name().to_string();
+            }
         }
+        Ok((tree, diagnostics))
+    }
+
+    ///Runs the XPath `xpath` against the tree.
+    ///
+    ///A position such as `//b[2]` picks from everything the step selected, not from the children
+    ///of each parent as `StreamQuery` does, and attributes can only be tested in predicates.
+    pub fn query(&self, xpath: &str) -> Result<Vec<ReturnEnum<'_>>, XmlErrors> {
+        let token_steps = tokenize(xpath)?;
+        match self.root {
+            Some(ref root) => evaluate(root, &token_steps),
+            None => Err(XmlErrors::ElementNotFound { t: "root".to_string() }),
+        }
+    }
+
+    ///Runs the XPath `xpath` lazily against the tree, stopping when no more matches are asked for
+    pub fn query_iter(&self, xpath: &str) -> Result<TreeMatches<'_>, XmlErrors> {
+        match self.root {
+            Some(ref root) => root.query_iter(xpath),
+            None => Err(XmlErrors::ElementNotFound { t: "root".to_string() }),
+        }
+    }
+
+    ///Iterates over every element of the tree in document order
+    pub fn iter(&self) -> Descendants<'_> {
+        match self.root {
+            Some(ref root) => root.iter(),
+            None => Descendants::empty(),
+        }
+    }
+
+    ///Writes the document, with declaration and indentation, to `t` in the tree's `encoding`
+    pub fn write<T: Write>(&self, t: &mut T) -> Result<(), XmlErrors> {
+        self.write_with_options(t, &WriteOptions::new())
+    }
+
+    ///Writes the document, with declaration and indentation, to `t` in `encoding`.
+    ///Characters of text and attribute values missing from `encoding` become character
+    ///references; anywhere else they are an error.
+    pub fn write_with_encoding<T: Write>(&self, t: &mut T, encoding: &str) -> Result<(), XmlErrors> {
+        self.write_encoded(t, encoding, &WriteOptions::new())
+    }
+
+    ///Writes to `t` in the tree's `encoding`, indenting with `indnt_str` if `indent` is set
+    pub fn write_with<T: Write>(&self, t: &mut T, doc_decl: bool, indnt_str: &str, indent: bool)
+                            -> Result<(), XmlErrors> {
+        let options = WriteOptions {
+            declaration: doc_decl,
+            indent: if indent { indnt_str.to_string() } else { String::new() },
+            ..WriteOptions::new()
+        };
+        self.write_with_options(t, &options)
+    }
+
+    ///Writes to `t` in the tree's `encoding`, laid out as `options` say
+    pub fn write_with_options<T: Write>(&self, t: &mut T, options: &WriteOptions) -> Result<(), XmlErrors> {
+        self.write_encoded(t, &self.encoding, options)
+    }
+
+    ///Writes the canonical form of the document to `t`, byte for byte stable for signing and hashing
+    pub fn canonicalize<T: Write>(&self, t: &mut T, options: &Canonicalization) -> Result<(), XmlErrors> {
+        t.write_all(c14n::canonicalize(self, options).as_bytes())?;
         Ok(())
     }
+
+    fn write_encoded<T: Write>(&self, t: &mut T, label: &str, options: &WriteOptions) -> Result<(), XmlErrors> {
+        let target = match encoding::for_label(label) {
+            Some(target) => target,
+            None => {
+                let msg = format!("unsupported encoding '{}'", label);
+                let err = io::Error::new(io::ErrorKind::InvalidInput, msg);
+                return Err(EmitterError::Io(err).into());
+            }
+        };
+        let utf8 = serialize::document(self, label, target, options)?;
+        encoding::write_encoded(t, &utf8, target)
+    }
 }
 
+impl FromStr for ElementTree {
+    type Err = XmlErrors;
+
+    fn from_str(doc: &str) -> Result<Self, Self::Err> {
+        ElementTree::parse_str(doc)
+    }
+}
+
+impl From<Element> for ElementTree {
+    fn from(root: Element) -> Self {
+        ElementTree::new_with_root(root)
+    }
+}
 
+/// Writes the tree as `write` would, failing with `fmt::Error` where `write` fails
 impl fmt::Display for ElementTree {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut vec = Vec::<u8>::new();
-        self.write(&mut vec)?;
-        let strng = String::from_utf8(vec).unwrap();
-        f.write_str(&strng[..])
+        // a string is always UTF-8, whatever the tree's encoding
+        let written = serialize::document(self, "UTF-8", encoding_rs::UTF_8, &WriteOptions::new()).map_err(|_| fmt::Error)?;
+        f.write_str(&written)
     }
 }
 